@@ -80,6 +80,27 @@ fn single_yield_bench(b: &mut Bencher) {
     g.raw_send(None);
 }
 
+#[bench]
+fn get_yield_bench(b: &mut Bencher) {
+    // echo the para through the type checked slots
+    let mut g = Gn::new(|| {
+        while let Some(v) = get_yield::<usize>() {
+            yield_with(v);
+        }
+        0usize
+    });
+
+    let mut i: usize = 0;
+    b.iter(|| {
+        let data: usize = g.send(i);
+        assert_eq!(data, i);
+        i += 1;
+    });
+
+    // quit g
+    g.raw_send(None);
+}
+
 #[bench]
 fn scoped_yield_bench(b: &mut Bencher) {
     let mut g = Gn::new_scoped(|mut s| {
@@ -114,6 +135,33 @@ fn scoped_yield_bench(b: &mut Bencher) {
     g.raw_send(None);
 }
 
+#[bench]
+fn scoped_local_yield_bench(b: &mut Bencher) {
+    let mut g = Gn::new_scoped_local(|mut s| {
+        let mut i = 0;
+        loop {
+            s.yield_with(i);
+            let Some(x) = s.get_yield() else { break };
+            i += 1;
+            assert_eq!(x, i);
+        }
+        20usize
+    });
+
+    // start g
+    g.raw_send(None);
+
+    let mut i: usize = 1;
+    b.iter(|| {
+        let data: usize = g.send(i);
+        assert_eq!(data, i);
+        i += 1;
+    });
+
+    // quit g
+    g.raw_send(None);
+}
+
 #[bench]
 fn create_gen(b: &mut Bencher) {
     b.iter(|| {
@@ -4,9 +4,70 @@ const NIGHTLY: bool = true;
 #[rustversion::not(nightly)]
 const NIGHTLY: bool = false;
 
+// naked functions are stable since 1.88
+#[rustversion::since(1.88)]
+const NAKED_ASM: bool = true;
+
+#[rustversion::before(1.88)]
+const NAKED_ASM: bool = false;
+
 fn main() {
     println!("cargo:rustc-check-cfg=cfg(nightly)");
     if NIGHTLY {
         println!("cargo:rustc-cfg=nightly");
     }
+
+    // set by `RUSTFLAGS="--cfg loom"` to run the loom tests
+    println!("cargo:rustc-check-cfg=cfg(loom)");
+
+    let env = |name| std::env::var(name).unwrap_or_default();
+
+    // the context switch is compiled by rustc instead of the external assembly,
+    // only ported to x86_64 unix for now, the other arches keep the `.S` files
+    println!("cargo:rustc-check-cfg=cfg(naked_asm)");
+    if NAKED_ASM
+        && env("CARGO_CFG_TARGET_ARCH") == "x86_64"
+        && env("CARGO_CFG_TARGET_FAMILY") == "unix"
+    {
+        println!("cargo:rustc-cfg=naked_asm");
+    }
+
+    // the floating point control words are switched on these arches
+    println!("cargo:rustc-check-cfg=cfg(fp_control)");
+    if std::env::var_os("CARGO_FEATURE_FP_CONTROL").is_some()
+        && matches!(env("CARGO_CFG_TARGET_ARCH").as_str(), "x86_64" | "aarch64")
+    {
+        println!("cargo:rustc-cfg=fp_control");
+    }
+
+    // the frame pointer chain is linked on x86_64 (unix and windows) and
+    // aarch64 unix, keep the `frame-chain` docs in sync
+    println!("cargo:rustc-check-cfg=cfg(frame_chain)");
+    if std::env::var_os("CARGO_FEATURE_FRAME_CHAIN").is_some()
+        && match env("CARGO_CFG_TARGET_ARCH").as_str() {
+            "x86_64" => true,
+            "aarch64" => env("CARGO_CFG_TARGET_FAMILY") == "unix",
+            _ => false,
+        }
+    {
+        println!("cargo:rustc-cfg=frame_chain");
+    }
+
+    // the runtime checks of the debug builds, kept in release by `paranoid`
+    println!("cargo:rustc-check-cfg=cfg(checks)");
+    if std::env::var_os("CARGO_CFG_DEBUG_ASSERTIONS").is_some()
+        || std::env::var_os("CARGO_FEATURE_PARANOID").is_some()
+    {
+        println!("cargo:rustc-cfg=checks");
+    }
+
+    // the unwind frames are only supported on x86_64 ELF targets for now
+    println!("cargo:rustc-check-cfg=cfg(unwind_frames)");
+    if std::env::var_os("CARGO_FEATURE_UNWIND").is_some()
+        && env("CARGO_CFG_TARGET_ARCH") == "x86_64"
+        && env("CARGO_CFG_TARGET_FAMILY") == "unix"
+        && env("CARGO_CFG_TARGET_VENDOR") != "apple"
+    {
+        println!("cargo:rustc-cfg=unwind_frames");
+    }
 }
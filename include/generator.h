@@ -0,0 +1,49 @@
+/*
+ * C API of the generator crate, enabled by the `ffi` feature
+ */
+#ifndef GENERATOR_H
+#define GENERATOR_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* opaque generator handle */
+typedef struct generator generator_t;
+/* opaque scope handle passed to the generator body */
+typedef struct generator_scope generator_scope_t;
+
+/* the generator body, the returned value is the last output */
+typedef void *(*generator_fn)(generator_scope_t *scope, void *arg);
+
+/* create a generator, stack_size is in words, 0 means the default */
+generator_t *generator_new(generator_fn f, void *arg, size_t stack_size);
+/* resume the generator, return NULL when done or panicked */
+void *generator_resume(generator_t *gen);
+/* send the para to the generator, return NULL when done or panicked */
+void *generator_send(generator_t *gen, void *para);
+bool generator_is_done(const generator_t *gen);
+/* cancel the generator, the body sees generator_is_cancelled() */
+void generator_cancel(generator_t *gen);
+/* free the generator, cancel it first if it's suspended */
+void generator_free(generator_t *gen);
+
+/* yield the value from the body, return the next sent para */
+void *generator_yield(generator_scope_t *scope, void *value);
+/* once cancelled, generator_yield returns NULL and the body should return */
+bool generator_is_cancelled(const generator_scope_t *scope);
+
+/* the id of the generator running on the current thread, 0 if none,
+ * it can be called from a signal handler */
+size_t generator_current_id(void);
+/* the name of the generator running on the current thread, NULL if none */
+const char *generator_current_name(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GENERATOR_H */
@@ -0,0 +1,59 @@
+"""gdb helpers for the generator crate
+
+    (gdb) source scripts/gdb/generator.py
+    (gdb) generator-bt <id>
+
+`id` is the value of `GeneratorObj::id`. The backtrace of the suspended
+generator is printed by loading its saved registers into the current thread
+and restoring them afterwards, so it needs a live process.
+"""
+
+import gdb
+
+# the offset of the saved registers in the generator, see `rt::Context`
+REGS_OFFSET = 64
+# the word slots of the saved registers, see `detail::*::Registers`
+X86_64_SLOTS = {"sp": 1, "fp": 2}
+AARCH64_SLOTS = {"fp": 10, "lr": 11, "sp": 12}
+
+
+def read_word(addr):
+    ptr = gdb.lookup_type("unsigned long").pointer()
+    return int(gdb.Value(addr).cast(ptr).dereference())
+
+
+def saved_registers(gen, arch):
+    """return the register names and the values to resume the generator"""
+    regs = gen + REGS_OFFSET
+    slot = lambda i: read_word(regs + 8 * i)
+    if "x86-64" in arch:
+        sp = slot(X86_64_SLOTS["sp"])
+        # the return address is pushed on the stack by the switch
+        return ("$rsp", "$rbp", "$pc"), (sp + 8, slot(X86_64_SLOTS["fp"]), read_word(sp))
+    if "aarch64" in arch:
+        return ("$sp", "$x29", "$pc"), tuple(slot(AARCH64_SLOTS[r]) for r in ("sp", "fp", "lr"))
+    raise gdb.GdbError("generator-bt: unsupported arch " + arch)
+
+
+class GeneratorBacktrace(gdb.Command):
+    """Print the backtrace of a suspended generator: generator-bt ID"""
+
+    def __init__(self):
+        super().__init__("generator-bt", gdb.COMMAND_STACK)
+
+    def invoke(self, arg, from_tty):
+        gen = int(gdb.parse_and_eval(arg))
+        gdb.execute("frame 0", to_string=True)
+        arch = gdb.selected_frame().architecture().name()
+        names, values = saved_registers(gen, arch)
+        saved = [int(gdb.parse_and_eval(name)) for name in names]
+        try:
+            for name, value in zip(names, values):
+                gdb.execute("set %s = %d" % (name, value))
+            gdb.execute("bt", from_tty)
+        finally:
+            for name, value in zip(names, saved):
+                gdb.execute("set %s = %d" % (name, value))
+
+
+GeneratorBacktrace()
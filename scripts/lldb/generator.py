@@ -0,0 +1,66 @@
+"""lldb helpers for the generator crate
+
+    (lldb) command script import scripts/lldb/generator.py
+    (lldb) generator-bt <id>
+
+`id` is the value of `GeneratorObj::id`. The backtrace of the suspended
+generator is printed by loading its saved registers into the current thread
+and restoring them afterwards, so it needs a live process.
+"""
+
+import lldb
+
+# the offset of the saved registers in the generator, see `rt::Context`
+REGS_OFFSET = 64
+# the word slots of the saved registers, see `detail::*::Registers`
+X86_64_SLOTS = {"sp": 1, "fp": 2}
+AARCH64_SLOTS = {"fp": 10, "lr": 11, "sp": 12}
+
+
+def saved_registers(process, gen, triple):
+    """return the register names and the values to resume the generator"""
+    error = lldb.SBError()
+    read_word = lambda addr: process.ReadPointerFromMemory(addr, error)
+    regs = gen + REGS_OFFSET
+    slot = lambda i: read_word(regs + 8 * i)
+    if triple.startswith("x86_64"):
+        sp = slot(X86_64_SLOTS["sp"])
+        # the return address is pushed on the stack by the switch
+        values = (sp + 8, slot(X86_64_SLOTS["fp"]), read_word(sp))
+        names = ("rsp", "rbp", "rip")
+    elif triple.startswith(("aarch64", "arm64")):
+        values = tuple(slot(AARCH64_SLOTS[r]) for r in ("sp", "fp", "lr"))
+        names = ("sp", "fp", "pc")
+    else:
+        raise RuntimeError("generator-bt: unsupported arch " + triple)
+    if error.Fail():
+        raise RuntimeError("generator-bt: " + error.GetCString())
+    return names, values
+
+
+def generator_bt(debugger, command, result, internal_dict):
+    target = debugger.GetSelectedTarget()
+    process = target.GetProcess()
+    frame = process.GetSelectedThread().GetFrameAtIndex(0)
+    gen = frame.EvaluateExpression(command).GetValueAsUnsigned()
+    try:
+        names, values = saved_registers(process, gen, target.GetTriple())
+    except RuntimeError as e:
+        result.SetError(str(e))
+        return
+    saved = [frame.FindRegister(name).GetValueAsUnsigned() for name in names]
+    interpreter = debugger.GetCommandInterpreter()
+
+    def write(regs):
+        for name, value in zip(names, regs):
+            interpreter.HandleCommand("register write %s %d" % (name, value), lldb.SBCommandReturnObject())
+
+    write(values)
+    try:
+        interpreter.HandleCommand("thread backtrace", result)
+    finally:
+        write(saved)
+
+
+def __lldb_init_module(debugger, internal_dict):
+    debugger.HandleCommand("command script add -f generator.generator_bt generator-bt")
@@ -0,0 +1,40 @@
+//! # batch generator
+//!
+//! yield many items per context switch
+//!
+
+use std::mem;
+
+use crate::gen_impl::GeneratorObj;
+use crate::scope::Scope;
+
+impl<A, U> Scope<'_, '_, A, Vec<U>> {
+    /// yield all the items pushed into `batch` in one suspension and get the
+    /// send para, `batch` is left empty with the same capacity
+    ///
+    /// nothing is yielded for an empty batch, the para is `None` then
+    pub fn yield_batch(&mut self, batch: &mut Vec<U>) -> Option<A> {
+        if batch.is_empty() {
+            return None;
+        }
+        let cap = batch.capacity();
+        self.yield_with(mem::replace(batch, Vec::with_capacity(cap)));
+        self.get_yield()
+    }
+}
+
+impl<A, U, const LOCAL: bool> GeneratorObj<'_, A, Vec<U>, LOCAL> {
+    /// resume the generator and append the next batch to `buf`
+    ///
+    /// return the number of the appended items, 0 means the generator is done
+    pub fn resume_batch(&mut self, buf: &mut Vec<U>) -> usize {
+        match self.resume() {
+            Some(mut batch) => {
+                let n = batch.len();
+                buf.append(&mut batch);
+                n
+            }
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! # block on futures
+//!
+//! await a future inside a generator by suspending it while pending
+//!
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::pin;
+use std::ptr;
+use std::task::{Context, Poll, Waker};
+
+use crate::gen_impl::GeneratorObj;
+use crate::genawaiter::noop_waker;
+use crate::scope::Scope;
+use crate::sync::thread_local;
+
+thread_local! {
+    // the waker of the task that resumes the running generators
+    static WAKER: Cell<*const Waker> = const { Cell::new(ptr::null()) };
+}
+
+// get the waker of the current task, a no-op waker if there is none
+pub(crate) fn current_waker() -> Waker {
+    let waker = WAKER.with(Cell::get);
+    if waker.is_null() {
+        noop_waker()
+    } else {
+        unsafe { (*waker).clone() }
+    }
+}
+
+// restore the waker of the outer task
+struct Restore(*const Waker);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        WAKER.with(|p| p.set(self.0));
+    }
+}
+
+// run `f` on behalf of the task of `waker`
+pub(crate) fn with_waker<R>(waker: &Waker, f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(WAKER.with(|p| p.replace(waker)));
+    f()
+}
+
+impl<A, T, const LOCAL: bool> GeneratorObj<'_, A, T, LOCAL> {
+    /// resume the generator on behalf of the task of `waker`
+    ///
+    /// `Scope::yield_pending` and `Scope::block_on` register this waker, so
+    /// the task only needs to resume the generator again once it's woken
+    pub fn resume_with_waker(&mut self, waker: &Waker) -> Option<T> {
+        with_waker(waker, || self.resume())
+    }
+}
+
+impl<A, U> Scope<'_, '_, A, Poll<U>> {
+    /// give the waker of the current task to `register` and yield
+    /// `Poll::Pending`, then get the send para
+    ///
+    /// the generator should be resumed after the waker is woken, it's a
+    /// no-op waker when the generator is not resumed by `resume_with_waker`
+    pub fn yield_pending(&mut self, register: impl FnOnce(Waker)) -> Option<A> {
+        register(current_waker());
+        self.yield_with(Poll::Pending);
+        self.get_yield()
+    }
+
+    /// poll the future until it's ready and return its output
+    ///
+    /// the generator yields `Poll::Pending` to its driver each time the
+    /// future is not ready, the values are yielded as `Poll::Ready`. The
+    /// future is polled with the waker of `resume_with_waker`, or a no-op
+    /// waker, then the driver should resume the generator later when the
+    /// awaited work may have progressed. The paras sent while pending are
+    /// dropped
+    pub fn block_on<F: Future>(&mut self, fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        loop {
+            // the generator may be resumed by another task
+            let waker = current_waker();
+            let mut cx = Context::from_waker(&waker);
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => {
+                    self.yield_with(Poll::Pending);
+                    self.get_yield();
+                }
+            }
+        }
+    }
+}
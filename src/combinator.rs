@@ -0,0 +1,458 @@
+//! # generator combinators
+//!
+//! generator native adaptors that keep the send channel
+//!
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::task::Poll;
+
+use crate::gen_impl::{Generator, GeneratorObj, Gn};
+use crate::scope::Scope;
+use crate::sync::{Arc, Mutex};
+use crate::yield_::done;
+
+// what to do with an output of the underlying generator
+enum Step<U> {
+    // pass the value to the driver
+    Yield(U),
+    // drop the value and resume the underlying generator again
+    Skip,
+    // stop the adaptor
+    Stop,
+}
+
+// the underlying generator is moved into the adaptor, which is only `Send`
+// when it's `'static`, the same as the underlying generator
+struct Inner<'a, A, T>(Generator<'a, A, T>);
+unsafe impl<A: Send, T: Send> Send for Inner<'_, A, T> {}
+
+// yield the value from the adaptor and get the next para, when the adaptor
+// is cancelled the underlying generators are cancelled by `cancel` first,
+// they can't be cancelled when dropped by the unwinding
+fn forward<A, T>(s: &mut Scope<A, T>, v: T, cancel: impl FnOnce()) -> Option<A> {
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        s.yield_with(v);
+        s.get_yield()
+    })) {
+        Ok(para) => para,
+        Err(e) => {
+            cancel();
+            panic::resume_unwind(e)
+        }
+    }
+}
+
+// drive the underlying generator on the adaptor stack, forward the para
+// from the driver and pass each output through `f`
+fn adapt<'a, A, T, U, F>(gen: Generator<'a, A, T>, mut f: F) -> Generator<'a, A, U>
+where
+    F: FnMut(T) -> Step<U> + Send + 'a,
+    A: Send + 'a,
+    T: Send + 'a,
+    U: Send + 'a,
+{
+    let gen = Inner(gen);
+    Gn::new_scoped(move |mut s| {
+        // move the whole wrapper instead of capturing the field
+        let Inner(mut gen) = { gen };
+        let mut para = s.get_yield();
+        loop {
+            let v = match gen.raw_send(para.take()) {
+                Some(v) => v,
+                None => return done(),
+            };
+            // the return value of the underlying generator
+            let last = gen.is_done();
+            match f(v) {
+                Step::Yield(u) if last => return u,
+                Step::Yield(u) => para = forward(&mut s, u, || gen.cancel()),
+                Step::Skip if last => return done(),
+                Step::Skip => {}
+                Step::Stop => {
+                    gen.cancel();
+                    return done();
+                }
+            }
+        }
+    })
+}
+
+impl<'a, A: Send + 'a, T: Send + 'a> Generator<'a, A, T> {
+    /// transform each output, the para is still sent to this generator
+    pub fn map_yield<U, F>(self, mut f: F) -> Generator<'a, A, U>
+    where
+        F: FnMut(T) -> U + Send + 'a,
+        U: Send + 'a,
+    {
+        adapt(self, move |v| Step::Yield(f(v)))
+    }
+
+    /// only pass the outputs that match the predicate
+    ///
+    /// this generator is resumed with no para after a skipped output
+    pub fn filter_yield<F>(self, mut f: F) -> Generator<'a, A, T>
+    where
+        F: FnMut(&T) -> bool + Send + 'a,
+    {
+        adapt(
+            self,
+            move |v| if f(&v) { Step::Yield(v) } else { Step::Skip },
+        )
+    }
+
+    /// pass the outputs until the predicate fails, then cancel this generator
+    pub fn take_while_yield<F>(self, mut f: F) -> Generator<'a, A, T>
+    where
+        F: FnMut(&T) -> bool + Send + 'a,
+    {
+        adapt(
+            self,
+            move |v| if f(&v) { Step::Yield(v) } else { Step::Stop },
+        )
+    }
+
+    /// transform the return value, the yielded values are passed as is
+    ///
+    /// `f` is not called if this generator finishes by `done!()`
+    pub fn map_return<F>(self, f: F) -> Generator<'a, A, T>
+    where
+        F: FnOnce(T) -> T + Send + 'a,
+    {
+        let gen = Inner(self);
+        Gn::new_scoped(move |mut s| {
+            let Inner(mut gen) = { gen };
+            let mut para = s.get_yield();
+            loop {
+                let v = match gen.raw_send(para.take()) {
+                    Some(v) => v,
+                    None => return done(),
+                };
+                if gen.is_done() {
+                    return f(v);
+                }
+                para = forward(&mut s, v, || gen.cancel());
+            }
+        })
+    }
+}
+
+impl<A: Send> Gn<A> {
+    /// create a generator that yields the pairs of outputs of both generators
+    ///
+    /// the para is sent to both generators, it stops when either of them is
+    /// done and the other one is cancelled
+    pub fn zip<'a, T, U>(a: Generator<'a, A, T>, b: Generator<'a, A, U>) -> Generator<'a, A, (T, U)>
+    where
+        A: Clone + 'a,
+        T: Send + 'a,
+        U: Send + 'a,
+    {
+        let (a, b) = (Inner(a), Inner(b));
+        Gn::new_scoped(move |mut s| {
+            let (Inner(mut a), Inner(mut b)) = { (a, b) };
+            let mut para = s.get_yield();
+            loop {
+                let pair = match a.raw_send(para.clone()) {
+                    Some(x) => b.raw_send(para.take()).map(|y| (x, y)),
+                    None => None,
+                };
+                let pair = match pair {
+                    Some(pair) if !a.is_done() && !b.is_done() => pair,
+                    pair => {
+                        a.cancel();
+                        b.cancel();
+                        return pair.unwrap_or_else(done);
+                    }
+                };
+                para = forward(&mut s, pair, || {
+                    a.cancel();
+                    b.cancel();
+                });
+            }
+        })
+    }
+
+    /// create a generator that yields `f(para)` for each sent para
+    ///
+    /// it finishes without a return value when resumed without para
+    pub fn repeat_with<'a, T, F>(mut f: F) -> Generator<'a, A, T>
+    where
+        F: FnMut(A) -> T + Send + 'a,
+        A: 'a,
+        T: Send + 'a,
+    {
+        Gn::new_scoped(move |mut s| {
+            let mut para = s.get_yield();
+            while let Some(a) = para {
+                s.yield_with(f(a));
+                para = s.get_yield();
+            }
+            done()
+        })
+    }
+
+    /// create a generator that yields all the outputs of `a` and then `b`
+    ///
+    /// the return value of `a` is yielded, `b` is started with no para
+    /// when `a` finishes without a return value
+    pub fn chain<'a, T>(a: Generator<'a, A, T>, b: Generator<'a, A, T>) -> Generator<'a, A, T>
+    where
+        A: 'a,
+        T: Send + 'a,
+    {
+        let (a, b) = (Inner(a), Inner(b));
+        Gn::new_scoped(move |mut s| {
+            let (Inner(mut a), Inner(mut b)) = { (a, b) };
+            let mut para = s.get_yield();
+            loop {
+                let v = if a.is_done() {
+                    match b.raw_send(para.take()) {
+                        Some(v) if !b.is_done() => v,
+                        v => return v.unwrap_or_else(done),
+                    }
+                } else {
+                    match a.raw_send(para.take()) {
+                        Some(v) => v,
+                        None => continue,
+                    }
+                };
+                para = forward(&mut s, v, || {
+                    a.cancel();
+                    b.cancel();
+                });
+            }
+        })
+    }
+
+    /// create a generator that interleaves the outputs of the generators,
+    /// resuming them in turn
+    ///
+    /// the para is sent to the generator resumed for the output, the return
+    /// values are yielded as outputs. A finished generator is left out of
+    /// the turns, it's done once all of them are done
+    pub fn merge<'a, T>(gens: Vec<Generator<'a, A, T>>) -> Generator<'a, A, T>
+    where
+        A: 'a,
+        T: Send + 'a,
+    {
+        let gens: Vec<_> = gens.into_iter().map(Inner).collect();
+        Gn::new_scoped(move |mut s| {
+            let mut gens: Vec<_> = { gens }.into_iter().map(|Inner(g)| g).collect();
+            let mut para = s.get_yield();
+            let mut i = 0;
+            while !gens.is_empty() {
+                i %= gens.len();
+                let v = gens[i].raw_send(para.take());
+                if gens[i].is_done() {
+                    gens.remove(i);
+                } else {
+                    i += 1;
+                }
+                let v = match v {
+                    Some(v) if gens.is_empty() => return v,
+                    Some(v) => v,
+                    None => continue,
+                };
+                para = forward(&mut s, v, || gens.iter_mut().for_each(|g| g.cancel()));
+            }
+            done()
+        })
+    }
+}
+
+// the source of `broadcast` and the outputs not taken by each consumer yet
+struct Hub<'a, T> {
+    // taken out by the consumer resuming it, so the lock is not held while
+    // the source runs and its panic doesn't poison the hub
+    source: Option<Inner<'a, (), T>>,
+    // `None` once the consumer is dropped
+    queues: Vec<Option<VecDeque<T>>>,
+    capacity: usize,
+    done: bool,
+}
+
+// leave the hub when the consumer is dropped, so it doesn't hold back the
+// others
+struct Member<'a, T> {
+    hub: Arc<Mutex<Hub<'a, T>>>,
+    id: usize,
+}
+
+impl<T> Drop for Member<'_, T> {
+    fn drop(&mut self) {
+        if let Ok(mut hub) = self.hub.lock() {
+            hub.queues[self.id] = None;
+        }
+    }
+}
+
+// put the source back into the hub, also when it panics, a panicked source
+// is done
+struct Resuming<'h, 'a, T> {
+    hub: &'h Mutex<Hub<'a, T>>,
+    source: Option<Inner<'a, (), T>>,
+}
+
+impl<T> Drop for Resuming<'_, '_, T> {
+    fn drop(&mut self) {
+        if let Ok(mut hub) = self.hub.lock() {
+            hub.source = self.source.take();
+        }
+    }
+}
+
+impl<T: Clone> Member<'_, T> {
+    // the next output for the consumer, `Poll::Pending` while a slower
+    // consumer has a full queue or another consumer is resuming the source
+    fn next(&self) -> Poll<Option<T>> {
+        let mut resuming = {
+            let mut hub = self.hub.lock().unwrap();
+            if let Some(v) = hub.queues[self.id].as_mut().and_then(VecDeque::pop_front) {
+                return Poll::Ready(Some(v));
+            }
+            if hub.done {
+                return Poll::Ready(None);
+            }
+            let capacity = hub.capacity;
+            if hub.queues.iter().flatten().any(|q| q.len() >= capacity) {
+                return Poll::Pending;
+            }
+            match hub.source.take() {
+                Some(source) => Resuming {
+                    hub: &self.hub,
+                    source: Some(source),
+                },
+                None => return Poll::Pending,
+            }
+        };
+        let source = &mut resuming.source.as_mut().unwrap().0;
+        let v = source.resume();
+        let done = source.is_done();
+        drop(resuming);
+
+        let mut hub = self.hub.lock().unwrap();
+        hub.done = done;
+        let v = match v {
+            Some(v) => v,
+            None => return Poll::Ready(None),
+        };
+        for (id, q) in hub.queues.iter_mut().enumerate() {
+            match q {
+                Some(q) if id != self.id => q.push_back(v.clone()),
+                _ => {}
+            }
+        }
+        Poll::Ready(Some(v))
+    }
+}
+
+impl Gn<()> {
+    /// create a generator that yields all the items of the iterator
+    // `Gn` is only a helper, it can't be a `FromIterator`
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_iter<'a, I>(iter: I) -> Generator<'a, (), I::Item>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'a,
+        I::Item: Send + 'a,
+    {
+        let iter = iter.into_iter();
+        Gn::new_scoped(move |mut s| {
+            for v in iter {
+                s.yield_with(v);
+            }
+            done()
+        })
+    }
+
+    /// create `n` consumers that all get the outputs of the generator
+    ///
+    /// the generator is resumed by the consumer that is the first to ask for
+    /// an output, which is queued for the others. A consumer yields
+    /// `Poll::Pending` instead when a slower one has `capacity` outputs
+    /// queued, the slower ones should be resumed first. A dropped consumer
+    /// no longer gets the outputs
+    ///
+    /// panics if `capacity` is 0
+    pub fn broadcast<'a, T>(
+        gen: Generator<'a, (), T>,
+        n: usize,
+        capacity: usize,
+    ) -> Vec<Generator<'a, (), Poll<T>>>
+    where
+        T: Clone + Send + 'a,
+    {
+        assert!(capacity > 0, "the broadcast capacity is 0");
+        let hub = Arc::new(Mutex::new(Hub {
+            source: Some(Inner(gen)),
+            queues: (0..n).map(|_| Some(VecDeque::new())).collect(),
+            capacity,
+            done: false,
+        }));
+        (0..n)
+            .map(|id| {
+                let member = Member {
+                    hub: hub.clone(),
+                    id,
+                };
+                Gn::new_scoped(move |mut s| {
+                    let member = member;
+                    loop {
+                        match member.next() {
+                            Poll::Ready(Some(v)) => s.yield_with(Poll::Ready(v)),
+                            Poll::Ready(None) => return done(),
+                            Poll::Pending => s.yield_with(Poll::Pending),
+                        }
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
+    /// send each input in turn and iterate the outputs, until the generator is done
+    pub fn send_iter<I: IntoIterator<Item = A>>(
+        &mut self,
+        inputs: I,
+    ) -> SendIter<'_, 'a, A, T, I::IntoIter, LOCAL> {
+        SendIter {
+            gen: self,
+            inputs: inputs.into_iter(),
+            prefetch: false,
+        }
+    }
+}
+
+/// Iterator of the outputs, created by `send_iter`
+pub struct SendIter<'g, 'a, A, T, I, const LOCAL: bool> {
+    gen: &'g mut GeneratorObj<'a, A, T, LOCAL>,
+    inputs: I,
+    prefetch: bool,
+}
+
+impl<A, T, I, const LOCAL: bool> SendIter<'_, '_, A, T, I, LOCAL> {
+    /// prefetch the generator after each output, so it's warm for the next
+    /// send while the caller is processing the output
+    pub fn auto_prefetch(mut self) -> Self {
+        self.prefetch = true;
+        self
+    }
+}
+
+impl<A, T, I: Iterator<Item = A>, const LOCAL: bool> Iterator for SendIter<'_, '_, A, T, I, LOCAL> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.gen.is_done() {
+            return None;
+        }
+        let para = self.inputs.next()?;
+        let ret = self.gen.raw_send(Some(para));
+        if self.prefetch {
+            self.gen.prefetch();
+        }
+        ret
+    }
+}
@@ -0,0 +1,73 @@
+//! # coroutine
+//!
+//! interop with the nightly `std::ops::Coroutine` trait
+//!
+
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+
+use crate::gen_impl::{Generator, GeneratorObj, Gn, LocalGenerator};
+use crate::scope::Scope;
+
+/// the generator is `Unpin`, each resume sends the arg as the para
+///
+/// a generator finished with `done!()` completes with `None`, the yields
+/// without a value are skipped like the iterator does
+impl<A, T, const LOCAL: bool> Coroutine<A> for GeneratorObj<'_, A, T, LOCAL> {
+    type Yield = T;
+    type Return = Option<T>;
+
+    fn resume(self: Pin<&mut Self>, arg: A) -> CoroutineState<T, Option<T>> {
+        let gen = self.get_mut();
+        assert!(!gen.is_done(), "generator resumed after completion");
+        let mut ret = gen.raw_send(Some(arg));
+        loop {
+            match ret {
+                _ if gen.is_done() => return CoroutineState::Complete(ret),
+                Some(v) => return CoroutineState::Yielded(v),
+                None => ret = gen.raw_send(None),
+            }
+        }
+    }
+}
+
+// drive the coroutine until it completes, it's pinned on the generator stack
+// that never moves, so static (self-referential) coroutines are supported
+fn drive<A: Default, T, C>(mut s: Scope<A, T>, c: C) -> T
+where
+    C: Coroutine<A, Yield = T, Return = T>,
+{
+    let mut c = std::pin::pin!(c);
+    loop {
+        let arg = s.get_yield().unwrap_or_default();
+        match c.as_mut().resume(arg) {
+            CoroutineState::Yielded(v) => s.yield_with(v),
+            CoroutineState::Complete(r) => return r,
+        }
+    }
+}
+
+impl<A: Default> Gn<A> {
+    /// create a generator that drives the compiler generated coroutine
+    ///
+    /// the send para is passed to the coroutine as the resume arg, a resume
+    /// without para (e.g. `next()`) passes `A::default()`
+    pub fn from_coroutine<'a, T, C>(c: C) -> Generator<'a, A, T>
+    where
+        C: Coroutine<A, Yield = T, Return = T> + Send + 'a,
+        T: Send + 'a,
+        A: Send + 'a,
+    {
+        Gn::new_scoped(move |s| drive(s, c))
+    }
+
+    /// create a local generator that drives the compiler generated coroutine
+    pub fn from_coroutine_local<'a, T, C>(c: C) -> LocalGenerator<'a, A, T>
+    where
+        C: Coroutine<A, Yield = T, Return = T> + 'a,
+        T: 'a,
+        A: 'a,
+    {
+        Gn::new_scoped_local(move |s| drive(s, c))
+    }
+}
@@ -25,6 +25,12 @@ extern "C" {
     pub fn swap_registers(out_regs: *mut Registers, in_regs: *const Registers);
 }
 
+// the indexes of the frame pointer (x29), the link register (x30) and the
+// stack pointer in the saved registers, which start at x19
+const FP: usize = 29 - 19;
+const LR: usize = 30 - 19;
+const SP: usize = 31 - 19;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct Registers {
@@ -48,6 +54,43 @@ impl Registers {
             prefetch(ptr.add(8)); // RSP + 8
         }
     }
+
+    /// the saved stack pointer
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.gpr[SP]
+    }
+
+    /// the saved instruction pointer to resume at, only valid when the
+    /// registers are saved by `swap_registers`
+    #[inline]
+    pub fn ip(&self) -> usize {
+        self.gpr[LR]
+    }
+}
+
+/// point the frame pointer of the first generator frame to the frame record
+#[cfg(frame_chain)]
+pub fn link_frame(regs: &mut Registers, record: *const usize) {
+    regs.gpr[FP] = record as usize;
+}
+
+/// the frame pointer and an instruction address of the current function,
+/// a frame record that links to the current function
+#[cfg(frame_chain)]
+#[inline(always)]
+pub fn current_frame() -> [usize; 2] {
+    let (fp, ip): (usize, usize);
+    unsafe {
+        std::arch::asm!(
+            "mov {0}, x29",
+            "adr {1}, .",
+            out(reg) fp,
+            out(reg) ip,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    [fp, ip]
 }
 
 pub fn initialize_call_frame(
@@ -62,10 +105,6 @@ pub fn initialize_call_frame(
     const X20: usize = 20 - 19;
     const X21: usize = 21 - 19;
 
-    const FP: usize = 29 - 19;
-    const LR: usize = 30 - 19;
-    const SP: usize = 31 - 19;
-
     let sp = align_down(stack.end());
 
     // These registers are frobbed by bootstrap_green_task into the right
@@ -77,7 +116,7 @@ pub fn initialize_call_frame(
     // Aarch64 current stack frame pointer
     regs.gpr[FP] = sp as usize;
 
-    regs.gpr[LR] = bootstrap_green_task as usize;
+    regs.gpr[LR] = bootstrap_green_task as *const () as usize;
 
     // setup the init stack
     // this is prepared for the swap context
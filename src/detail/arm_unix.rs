@@ -39,6 +39,20 @@ impl Registers {
             prefetch(ptr.add(1)); // SP + 4
         }
     }
+
+    /// the saved stack pointer
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.gpr[8]
+    }
+
+    /// the saved instruction pointer to resume at, only valid when the
+    /// registers are saved by `swap_registers`
+    #[inline]
+    pub fn ip(&self) -> usize {
+        // the link register
+        self.gpr[9]
+    }
 }
 
 pub fn initialize_call_frame(
@@ -68,7 +82,7 @@ pub fn initialize_call_frame(
     // arm current stack frame pointer
     regs.gpr[FP] = sp as usize;
 
-    regs.gpr[LR] = bootstrap_green_task as usize;
+    regs.gpr[LR] = bootstrap_green_task as *const () as usize;
 
     // setup the init stack
     // this is prepared for the swap context
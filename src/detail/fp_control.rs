@@ -0,0 +1,63 @@
+// the floating point control words, they are not part of the callee saved
+// registers, so the rounding mode and the exception masks set by one side
+// of a switch would leak into the other side
+
+use std::arch::asm;
+
+/// the saved floating point control words
+#[derive(Clone, Copy)]
+pub struct FpControl {
+    #[cfg(target_arch = "x86_64")]
+    mxcsr: u32,
+    #[cfg(target_arch = "x86_64")]
+    x87: u16,
+    #[cfg(target_arch = "aarch64")]
+    fpcr: u64,
+}
+
+impl FpControl {
+    /// save the current control words
+    #[inline]
+    pub fn save() -> FpControl {
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            let mut mxcsr = 0u32;
+            let mut x87 = 0u16;
+            asm!(
+                "stmxcsr [{0}]",
+                "fnstcw [{1}]",
+                in(reg) &mut mxcsr,
+                in(reg) &mut x87,
+                options(nostack, preserves_flags),
+            );
+            FpControl { mxcsr, x87 }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            let fpcr: u64;
+            asm!("mrs {0}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags));
+            FpControl { fpcr }
+        }
+    }
+
+    /// load the saved control words
+    #[inline]
+    pub fn load(self) {
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            asm!(
+                "ldmxcsr [{0}]",
+                "fldcw [{1}]",
+                in(reg) &self.mxcsr,
+                in(reg) &self.x87,
+                options(nostack, preserves_flags, readonly),
+            );
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            asm!("msr fpcr, {0}", in(reg) self.fpcr, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
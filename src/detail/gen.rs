@@ -1,11 +1,12 @@
-use crate::rt::ContextStack;
 use crate::stack::{overflow, Func};
 use crate::yield_::yield_now;
-use crate::Error;
-use std::any::Any;
-use std::panic;
+#[cfg(not(panic = "abort"))]
+use crate::{rt::ContextStack, Error};
+#[cfg(not(panic = "abort"))]
+use std::{any::Any, panic};
 
 /// don't print panic info for Done/Cancel
+#[cfg(not(panic = "abort"))]
 fn catch_unwind_filter<F: FnOnce() -> R + panic::UnwindSafe, R>(f: F) -> std::thread::Result<R> {
     use std::sync::Once;
     static INIT: Once = Once::new();
@@ -35,6 +36,11 @@ pub fn gen_init_impl(_: usize, f: *mut usize) -> ! {
         func.call_once();
     };
 
+    // a panic can't be caught, run the closure without the unwind machinery
+    #[cfg(panic = "abort")]
+    clo();
+
+    #[cfg(not(panic = "abort"))]
     fn check_err(cause: Box<dyn Any + Send + 'static>) {
         // this is not an error at all, ignore it
         if let Some(Error::Cancel | Error::Done) = cause.downcast_ref::<Error>() {
@@ -47,6 +53,7 @@ pub fn gen_init_impl(_: usize, f: *mut usize) -> ! {
 
     // we can't panic inside the generator context
     // need to propagate the panic to the main thread
+    #[cfg(not(panic = "abort"))]
     if let Err(cause) = catch_unwind_filter(clo) {
         check_err(cause);
     }
@@ -58,6 +58,20 @@ impl Registers {
             prefetch(ptr.add(8)); // SP + 8
         }
     }
+
+    /// the saved stack pointer
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.gpr[1]
+    }
+
+    /// the saved instruction pointer to resume at, only valid when the
+    /// registers are saved by `swap_registers`
+    #[inline]
+    pub fn ip(&self) -> usize {
+        // the return address register
+        self.gpr[0]
+    }
 }
 
 pub fn initialize_call_frame(
@@ -85,7 +99,7 @@ pub fn initialize_call_frame(
     // LoongArch64 current stack frame pointer
     regs.gpr[FP] = sp as usize;
 
-    regs.gpr[RA] = bootstrap_green_task as usize;
+    regs.gpr[RA] = bootstrap_green_task as *const () as usize;
 
     // setup the init stack
     // this is prepared for the swap context
@@ -30,13 +30,35 @@
 #[cfg_attr(all(unix, target_arch = "riscv64"), path = "riscv64_unix.rs")]
 pub mod asm;
 
+#[cfg(fp_control)]
+mod fp_control;
 mod gen;
+#[cfg(target_arch = "x86_64")]
+mod xstate;
 
+#[cfg(unwind_frames)]
+pub use self::asm::unwind_slot;
+#[cfg(frame_chain)]
+pub use self::asm::{current_frame, link_frame};
 pub use self::asm::{gen_init, initialize_call_frame, swap_registers, InitFn, Registers};
+#[cfg(fp_control)]
+pub use self::fp_control::FpControl;
+#[cfg(target_arch = "x86_64")]
+pub use self::xstate::XState;
+
+/// prefetch the data into cache
+#[inline]
+pub fn prefetch<T>(data: *const T) {
+    // it's a safe wrapper on some platforms
+    #[allow(unused_unsafe)]
+    unsafe {
+        self::asm::prefetch(data as *const usize)
+    }
+}
 
 #[inline]
 fn align_down(sp: *mut usize) -> *mut usize {
-    let sp = (sp as usize) & !(16 - 1);
+    let sp = (sp as usize) & !(crate::stack::Stack::alignment() - 1);
     sp as *mut usize
 }
 
@@ -39,6 +39,20 @@ impl Registers {
             prefetch(ptr.add(1)); // SP + 8
         }
     }
+
+    /// the saved stack pointer
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.gpr[12]
+    }
+
+    /// the saved instruction pointer to resume at, only valid when the
+    /// registers are saved by `swap_registers`
+    #[inline]
+    pub fn ip(&self) -> usize {
+        // the return address register
+        self.gpr[13]
+    }
 }
 
 pub fn initialize_call_frame(
@@ -68,5 +82,5 @@ pub fn initialize_call_frame(
     regs.gpr[FP] = sp as usize;
     regs.gpr[S1] = 0;
     regs.gpr[SP] = sp as usize;
-    regs.gpr[RA] = bootstrap_green_task as usize;
+    regs.gpr[RA] = bootstrap_green_task as *const () as usize;
 }
@@ -9,16 +9,38 @@ pub extern "sysv64" fn gen_init(a1: usize, a2: *mut usize) -> ! {
 }
 
 cfg_if::cfg_if! {
-    if #[cfg(target_os = "macos")] {
+    if #[cfg(naked_asm)] {
+        #[path = "x86_64_unix_naked.rs"]
+        mod naked;
+        #[cfg(not(unwind_frames))]
+        use self::naked::bootstrap_green_task;
+        pub use self::naked::{prefetch, swap_registers};
+    } else if #[cfg(target_os = "macos")] {
         std::arch::global_asm!(include_str!("asm/asm_x86_64_sysv_macho.S"));
     } else {
         std::arch::global_asm!(include_str!("asm/asm_x86_64_sysv_elf.S"));
     }
 }
 
+#[cfg(unwind_frames)]
+std::arch::global_asm!(include_str!("asm/asm_x86_64_sysv_elf_unwind.S"));
+
+#[cfg(unwind_frames)]
+extern "sysv64" {
+    fn bootstrap_green_task_unwind();
+}
+
+/// the slot that points to the registers saved by the resumer
+#[cfg(unwind_frames)]
+pub fn unwind_slot(stack: &Stack) -> *mut usize {
+    mut_offset(align_down(stack.end()), -1)
+}
+
 // #[cfg(not(nightly))]
 //#[link(name = "asm", kind = "static")]
+#[cfg(not(naked_asm))]
 extern "sysv64" {
+    #[cfg(not(unwind_frames))]
     pub fn bootstrap_green_task();
     pub fn prefetch(data: *const usize);
     pub fn swap_registers(out_regs: *mut Registers, in_regs: *const Registers);
@@ -124,6 +146,44 @@ impl Registers {
             prefetch(ptr.add(8)); // RSP + 8
         }
     }
+
+    /// the saved stack pointer
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.gpr[1]
+    }
+
+    /// the saved instruction pointer to resume at, only valid when the
+    /// registers are saved by `swap_registers`
+    #[inline]
+    pub fn ip(&self) -> usize {
+        // the return address is pushed on the stack by the call
+        unsafe { *(self.gpr[1] as *const usize) }
+    }
+}
+
+/// point the frame pointer of the first generator frame to the frame record
+#[cfg(frame_chain)]
+pub fn link_frame(regs: &mut Registers, record: *const usize) {
+    regs.gpr[2] = record as usize; // RBP
+}
+
+/// the frame pointer and an instruction address of the current function,
+/// a frame record that links to the current function
+#[cfg(frame_chain)]
+#[inline(always)]
+pub fn current_frame() -> [usize; 2] {
+    let (fp, ip): (usize, usize);
+    unsafe {
+        std::arch::asm!(
+            "mov {0}, rbp",
+            "lea {1}, [rip]",
+            out(reg) fp,
+            out(reg) ip,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    [fp, ip]
 }
 
 pub fn initialize_call_frame(
@@ -155,9 +215,14 @@ pub fn initialize_call_frame(
     // this is prepared for the swap context
     regs.gpr[RUSTRT_RSP] = mut_offset(sp, -2) as usize;
 
+    #[cfg(not(unwind_frames))]
+    let bootstrap = bootstrap_green_task as *const ();
+    #[cfg(unwind_frames)]
+    let bootstrap = bootstrap_green_task_unwind as *const ();
+
     unsafe {
         // leave enough space for RET
-        *mut_offset(sp, -2) = bootstrap_green_task as usize;
+        *mut_offset(sp, -2) = bootstrap as usize;
         *mut_offset(sp, -1) = 0;
     }
 }
@@ -0,0 +1,46 @@
+// the context switch functions compiled by rustc, they mirror the
+// `asm_x86_64_sysv_*.S` files used by the older compilers
+
+use std::arch::naked_asm;
+
+use super::Registers;
+
+#[unsafe(naked)]
+pub unsafe extern "sysv64" fn prefetch(data: *const usize) {
+    naked_asm!("prefetcht2 [rdi]", "ret")
+}
+
+#[cfg(not(unwind_frames))]
+#[unsafe(naked)]
+pub unsafe extern "sysv64" fn bootstrap_green_task() {
+    naked_asm!(
+        "mov rdi, r12",   // setup the function arg
+        "mov rsi, r13",   // setup the function arg
+        "and rsp, -16",   // align the stack pointer
+        "mov [rsp], r14", // this is the new return adrress
+        "ret",
+    )
+}
+
+#[unsafe(naked)]
+pub unsafe extern "sysv64" fn swap_registers(out_regs: *mut Registers, in_regs: *const Registers) {
+    // The first argument is in rdi, and the second one is in rsi
+    naked_asm!(
+        "mov [rdi + 0*8], rbx",
+        "mov [rdi + 1*8], rsp",
+        "mov [rdi + 2*8], rbp",
+        "mov [rdi + 4*8], r12",
+        "mov [rdi + 5*8], r13",
+        "mov [rdi + 6*8], r14",
+        "mov [rdi + 7*8], r15",
+        "mov rbx, [rsi + 0*8]",
+        "mov rsp, [rsi + 1*8]",
+        "mov rbp, [rsi + 2*8]",
+        "mov r12, [rsi + 4*8]",
+        "mov r13, [rsi + 5*8]",
+        "mov r14, [rsi + 6*8]",
+        "mov r15, [rsi + 7*8]",
+        "pop rax",
+        "jmp rax",
+    )
+}
@@ -193,6 +193,44 @@ impl Registers {
             prefetch(ptr.add(8)); // RSP + 8
         }
     }
+
+    /// the saved stack pointer
+    #[inline]
+    pub fn sp(&self) -> usize {
+        self.gpr[1]
+    }
+
+    /// the saved instruction pointer to resume at, only valid when the
+    /// registers are saved by `swap_registers`
+    #[inline]
+    pub fn ip(&self) -> usize {
+        // the return address is pushed on the stack by the call
+        unsafe { *(self.gpr[1] as *const usize) }
+    }
+}
+
+/// point the frame pointer of the first generator frame to the frame record
+#[cfg(frame_chain)]
+pub fn link_frame(regs: &mut Registers, record: *const usize) {
+    regs.gpr[2] = record as usize; // RBP
+}
+
+/// the frame pointer and an instruction address of the current function,
+/// a frame record that links to the current function
+#[cfg(frame_chain)]
+#[inline(always)]
+pub fn current_frame() -> [usize; 2] {
+    let (fp, ip): (usize, usize);
+    unsafe {
+        std::arch::asm!(
+            "mov {0}, rbp",
+            "lea {1}, [rip]",
+            out(reg) fp,
+            out(reg) ip,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    [fp, ip]
 }
 
 pub fn initialize_call_frame(
@@ -211,6 +249,7 @@ pub fn initialize_call_frame(
     const RUSTRT_STACK_BASE: usize = 11;
     const RUSTRT_STACK_LIMIT: usize = 12;
     const RUSTRT_STACK_DEALLOC: usize = 13;
+    const RUSTRT_STACK_GUARANTEE: usize = 15;
 
     let sp = align_down(stack.end());
 
@@ -224,8 +263,11 @@ pub fn initialize_call_frame(
     regs.gpr[RUSTRT_RBP] = 0;
 
     regs.gpr[RUSTRT_STACK_BASE] = stack.end() as usize;
-    regs.gpr[RUSTRT_STACK_LIMIT] = stack.begin() as usize;
-    regs.gpr[RUSTRT_STACK_DEALLOC] = 0; //mut_offset(sp, -8192) as usize;
+    // the OS grows a lazily committed stack down from the limit
+    regs.gpr[RUSTRT_STACK_LIMIT] = stack.committed_begin() as usize;
+    regs.gpr[RUSTRT_STACK_DEALLOC] = stack.alloc_base(); //mut_offset(sp, -8192) as usize;
+                                                         // the stack guarantee of the generator, not the one of the thread
+    regs.gpr[RUSTRT_STACK_GUARANTEE] = stack.guarantee();
 
     // setup the init stack
     // this is prepared for the swap context
@@ -233,7 +275,7 @@ pub fn initialize_call_frame(
 
     unsafe {
         // leave enough space for RET
-        *mut_offset(sp, -2) = bootstrap_green_task as usize;
+        *mut_offset(sp, -2) = bootstrap_green_task as *const () as usize;
         *mut_offset(sp, -1) = 0;
     }
 }
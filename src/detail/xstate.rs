@@ -0,0 +1,78 @@
+// the extended vector state saved by xsave
+//
+// the switch only keeps the registers the ABI requires (xmm6-15 on windows),
+// this optionally keeps the upper ymm/zmm halves and the avx-512 registers
+// for the code that keeps vector data across the yields
+
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::arch::x86_64::{__cpuid_count, _xgetbv, _xrstor64, _xsave64};
+
+// the upper halves of ymm0-15, the opmask, the upper halves of zmm0-15 and
+// zmm16-31, the xmm registers are left to the switch
+const VECTOR_MASK: u64 = 0b1110_0100;
+
+/// the saved extended vector state of a generator and its resumer
+pub struct XState {
+    mask: u64,
+    layout: Layout,
+    resumer: *mut u8,
+    gen: *mut u8,
+}
+
+impl XState {
+    /// return `None` if xsave or the vector extensions are not enabled
+    pub fn new() -> Option<XState> {
+        if !is_x86_feature_detected!("xsave") {
+            return None;
+        }
+        unsafe {
+            let mask = _xgetbv(0) & VECTOR_MASK;
+            if mask == 0 {
+                return None;
+            }
+            // the area size for all the features enabled in xcr0
+            let size = __cpuid_count(0xd, 0).ebx as usize;
+            let layout = Layout::from_size_align(size, 64).ok()?;
+            // a zeroed header loads the init state
+            let resumer = alloc_zeroed(layout);
+            let gen = alloc_zeroed(layout);
+            assert!(
+                !resumer.is_null() && !gen.is_null(),
+                "failed to alloc xsave area"
+            );
+            Some(XState {
+                mask,
+                layout,
+                resumer,
+                gen,
+            })
+        }
+    }
+
+    /// save the resumer state and load the generator state
+    #[inline]
+    pub fn switch_in(&mut self) {
+        unsafe {
+            _xsave64(self.resumer, self.mask);
+            _xrstor64(self.gen, self.mask);
+        }
+    }
+
+    /// save the generator state and load the resumer state
+    #[inline]
+    pub fn switch_out(&mut self) {
+        unsafe {
+            _xsave64(self.gen, self.mask);
+            _xrstor64(self.resumer, self.mask);
+        }
+    }
+}
+
+impl Drop for XState {
+    fn drop(&mut self) {
+        unsafe {
+            dealloc(self.resumer, self.layout);
+            dealloc(self.gen, self.layout);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! # deterministic test mode
+//!
+//! make the generator stacks of the current thread reproducible across runs,
+//! so snapshot tests of the stack usage and the failure injection don't flake
+//!
+//! when enabled on a thread
+//!
+//! * the stacks are allocated one after the other from an arena at an
+//!   address derived from the seed, the same allocations get the same
+//!   addresses in every run
+//! * a reused stack, e.g. by `reset_with`, is zero filled so it looks the
+//!   same as a new one
+//! * the default stack size is `DEFAULT_STACK_SIZE`, the env var and
+//!   `set_default_stack_size` are ignored
+//!
+//! the mode is per thread so the tests running in parallel don't affect each
+//! other, the threads running at the same time should use different seeds
+//!
+
+use std::cell::Cell;
+
+use crate::sync::thread_local;
+
+// each seed gets its own region of the address space
+#[cfg(target_pointer_width = "64")]
+const ARENA_BASE: usize = 0x2000_0000_0000;
+#[cfg(target_pointer_width = "64")]
+const ARENA_SIZE: usize = 0x1_0000_0000;
+#[cfg(target_pointer_width = "64")]
+const ARENA_COUNT: u64 = 0x1000;
+
+#[cfg(not(target_pointer_width = "64"))]
+const ARENA_BASE: usize = 0x5000_0000;
+#[cfg(not(target_pointer_width = "64"))]
+const ARENA_SIZE: usize = 0x0100_0000;
+#[cfg(not(target_pointer_width = "64"))]
+const ARENA_COUNT: u64 = 8;
+
+// the allocation granularity of windows, also fine for the page sizes
+const SLOT_ALIGN: usize = 0x10000;
+
+thread_local! {
+    // the address of the next stack, `None` if the mode is off
+    static NEXT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// enable the deterministic mode on the current thread
+///
+/// the arena starts over at the beginning of the seed's region, the stacks
+/// allocated since the last `enable` should be dropped by then, otherwise
+/// a new stack may find its address taken and fail to allocate
+pub fn enable(seed: u64) {
+    let base = ARENA_BASE + (seed % ARENA_COUNT) as usize * ARENA_SIZE;
+    NEXT.with(|p| p.set(Some(base)));
+}
+
+/// disable the deterministic mode on the current thread
+pub fn disable() {
+    NEXT.with(|p| p.set(None));
+}
+
+/// check if the deterministic mode is enabled on the current thread
+#[inline]
+pub fn is_enabled() -> bool {
+    NEXT.with(Cell::get).is_some()
+}
+
+// take the address for a stack of `size` bytes from the arena
+pub(crate) fn next_stack_addr(size: usize) -> Option<usize> {
+    let addr = NEXT.with(Cell::get)?;
+    let slot = size.checked_add(SLOT_ALIGN - 1)? & !(SLOT_ALIGN - 1);
+    NEXT.with(|p| p.set(Some(addr + slot)));
+    Some(addr)
+}
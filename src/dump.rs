@@ -0,0 +1,100 @@
+//! # generator dump
+//!
+//! write the live generators and their suspended stacks for postmortem
+//! tooling, e.g. from a panic hook or a crash handler thread
+//!
+//! the dump is little endian, it starts with the header
+//!
+//! | field   | size |                                  |
+//! |---------|------|----------------------------------|
+//! | magic   | 8    | `b"GENDUMP\0"`                   |
+//! | version | 4    | `1`                              |
+//! | count   | 4    | the number of generator records  |
+//!
+//! followed by `count` generator records
+//!
+//! | field  | size  |                                             |
+//! |--------|-------|---------------------------------------------|
+//! | id     | 8     | see `GeneratorObj::id`                      |
+//! | state  | 1     | fresh 0, suspended 1, running 2, done 3     |
+//! | ip     | 8     | the resume address, 0 if not suspended      |
+//! | sp     | 8     | the saved stack pointer, 0 if not suspended |
+//! | len    | 8     | the length of the frames                    |
+//! | frames | `len` | the stack bytes from `sp`                   |
+//!
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Mutex, PoisonError};
+
+use crate::gen_impl::State;
+
+/// the magic number at the beginning of a dump
+pub const DUMP_MAGIC: [u8; 8] = *b"GENDUMP\0";
+
+/// the dump format version
+pub const DUMP_VERSION: u32 = 1;
+
+// the state of a generator read by the dump
+pub(crate) struct GenInfo {
+    pub(crate) state: State,
+    pub(crate) ip: usize,
+    pub(crate) sp: usize,
+    // the generator is alive while the registry is locked
+    pub(crate) frames: *const [u8],
+}
+
+// read the info of the type erased generator
+pub(crate) type InfoFn = unsafe fn(*const ()) -> GenInfo;
+
+// the live generators, keyed by the id
+static REGISTRY: Mutex<BTreeMap<usize, InfoFn>> = Mutex::new(BTreeMap::new());
+
+// the dump is best effort, a panic while holding the lock doesn't matter
+fn registry() -> std::sync::MutexGuard<'static, BTreeMap<usize, InfoFn>> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn register(id: usize, info: InfoFn) {
+    registry().insert(id, info);
+}
+
+pub(crate) fn unregister(id: usize) {
+    registry().remove(&id);
+}
+
+/// write all the live generators and the frames of the suspended ones into
+/// `w`, returns the number of generators written
+///
+/// it's best effort, the generators are read without synchronization, so it
+/// should be called when the other threads are stopped. The format is
+/// described in the module docs
+///
+/// it's not async-signal-safe, it takes a lock and the writer may allocate,
+/// so it must not be called from a signal handler, e.g. the one of a
+/// `SIGSEGV`. A crash handler can wake a thread that writes the dump
+/// instead
+pub fn write_dump<W: Write>(mut w: W) -> io::Result<usize> {
+    let registry = registry();
+    w.write_all(&DUMP_MAGIC)?;
+    w.write_all(&DUMP_VERSION.to_le_bytes())?;
+    w.write_all(&(registry.len() as u32).to_le_bytes())?;
+    for (&id, info) in registry.iter() {
+        let info = unsafe { info(id as *const ()) };
+        let frames = unsafe { &*info.frames };
+        let state: u8 = match info.state {
+            State::Fresh => 0,
+            State::Suspended => 1,
+            State::Running => 2,
+            State::Done => 3,
+        };
+        w.write_all(&(id as u64).to_le_bytes())?;
+        w.write_all(&[state])?;
+        w.write_all(&(info.ip as u64).to_le_bytes())?;
+        w.write_all(&(info.sp as u64).to_le_bytes())?;
+        w.write_all(&(frames.len() as u64).to_le_bytes())?;
+        w.write_all(frames)?;
+    }
+    w.flush()?;
+    Ok(registry.len())
+}
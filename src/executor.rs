@@ -0,0 +1,242 @@
+//! # executor
+//!
+//! run generators that suspend on wakers
+//!
+
+use std::collections::VecDeque;
+#[cfg(all(feature = "reactor", unix))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use crate::gen_impl::Generator;
+#[cfg(all(feature = "reactor", unix))]
+use crate::reactor::Driver;
+use crate::sync::{Arc, Condvar, Mutex};
+use crate::timer::{with_timers, TimerWheel};
+
+// the ids of the tasks to resume
+#[derive(Default)]
+struct Queue {
+    ready: Mutex<VecDeque<usize>>,
+    cond: Condvar,
+    // wakes the driver the executor parks in, see `Executor::with_driver`
+    #[cfg(all(feature = "reactor", unix))]
+    unpark: Option<Waker>,
+    // set under the lock while the queue is found empty
+    #[cfg(all(feature = "reactor", unix))]
+    parked: AtomicBool,
+}
+
+impl Queue {
+    fn push(&self, id: usize) {
+        let mut ready = self.ready.lock().unwrap();
+        ready.push_back(id);
+        #[cfg(all(feature = "reactor", unix))]
+        if let Some(unpark) = &self.unpark {
+            if self.parked.load(Ordering::Relaxed) {
+                unpark.wake_by_ref();
+            }
+        }
+        drop(ready);
+        self.cond.notify_one();
+    }
+
+    // pop an id without blocking, the driver is unparked by the next push
+    // once it's `None`
+    #[cfg(all(feature = "reactor", unix))]
+    fn try_pop(&self) -> Option<usize> {
+        let mut ready = self.ready.lock().unwrap();
+        let id = ready.pop_front();
+        self.parked.store(id.is_none(), Ordering::Relaxed);
+        id
+    }
+
+    // pop an id, wait up to the timeout while there is none
+    fn pop(&self, timeout: Option<Duration>) -> Option<usize> {
+        let mut ready = self.ready.lock().unwrap();
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if let Some(id) = ready.pop_front() {
+                return Some(id);
+            }
+            ready = match deadline {
+                None => self.cond.wait(ready).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.cond.wait_timeout(ready, deadline - now).unwrap().0
+                }
+            };
+        }
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    queue: Arc<Queue>,
+}
+
+// `Wake` takes the std `Arc`
+impl Wake for TaskWaker {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.queue.push(self.id);
+    }
+}
+
+struct Task<'a> {
+    gen: Generator<'a, (), Poll<()>>,
+    waker: Waker,
+}
+
+/// A minimal executor for the generators that suspend by
+/// `Scope::yield_pending` or `Scope::block_on`
+///
+/// a task yielding `Poll::Pending` is only resumed after its waker is woken,
+/// `Poll::Ready(())` yields to the other tasks. A task may be resumed
+/// spuriously, so it should check the awaited condition again
+///
+/// ```
+/// use generator::{Executor, Gn};
+/// use std::sync::{Arc, Mutex};
+/// use std::task::{Poll, Waker};
+///
+/// let slot = Arc::new(Mutex::new(None::<Waker>));
+/// let mut exec = Executor::new();
+/// let waiter = slot.clone();
+/// exec.spawn(Gn::new_scoped(move |mut s| {
+///     s.yield_pending(|w| *waiter.lock().unwrap() = Some(w));
+///     Poll::Ready(())
+/// }));
+/// exec.spawn(Gn::new_scoped(move |mut s| {
+///     while slot.lock().unwrap().is_none() {
+///         s.yield_(Poll::Ready(()));
+///     }
+///     slot.lock().unwrap().take().unwrap().wake();
+///     Poll::Ready(())
+/// }));
+/// exec.run();
+/// ```
+pub struct Executor<'a> {
+    tasks: Vec<Option<Task<'a>>>,
+    queue: Arc<Queue>,
+    live: usize,
+    timers: TimerWheel,
+    #[cfg(all(feature = "reactor", unix))]
+    driver: Option<Box<dyn Driver>>,
+}
+
+impl Default for Executor<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Executor<'a> {
+    /// create an executor without tasks
+    pub fn new() -> Self {
+        Executor {
+            tasks: Vec::new(),
+            queue: Arc::default(),
+            live: 0,
+            timers: TimerWheel::default(),
+            #[cfg(all(feature = "reactor", unix))]
+            driver: None,
+        }
+    }
+
+    /// create an executor that parks in `driver` while all its tasks are
+    /// pending, the tasks can wait on the fds by `Scope::wait_readable` and
+    /// `Scope::wait_writable`
+    #[cfg(all(feature = "reactor", unix))]
+    pub fn with_driver(driver: impl Driver + 'static) -> Self {
+        let queue = Queue {
+            unpark: Some(driver.unparker()),
+            ..Queue::default()
+        };
+        Executor {
+            tasks: Vec::new(),
+            queue: Arc::new(queue),
+            live: 0,
+            timers: TimerWheel::default(),
+            driver: Some(Box::new(driver)),
+        }
+    }
+
+    /// add a task, it's started by `run`
+    pub fn spawn(&mut self, gen: Generator<'a, (), Poll<()>>) {
+        let id = self.tasks.len();
+        let waker = Waker::from(std::sync::Arc::new(TaskWaker {
+            id,
+            queue: self.queue.clone(),
+        }));
+        self.tasks.push(Some(Task { gen, waker }));
+        self.live += 1;
+        self.queue.push(id);
+    }
+
+    /// run the tasks until all of them are done
+    ///
+    /// it blocks the thread while all the tasks are pending, the sleeping
+    /// tasks are resumed by the timers of the executor
+    pub fn run(&mut self) {
+        let Executor {
+            tasks,
+            queue,
+            live,
+            timers,
+            #[cfg(all(feature = "reactor", unix))]
+            driver,
+        } = self;
+        let timers = timers as *mut TimerWheel;
+        // the tasks only touch the timers while they run
+        let expire = || unsafe { (*timers).expire(Instant::now()) };
+        with_timers(timers, || {
+            #[cfg(all(feature = "reactor", unix))]
+            if let Some(driver) = driver.as_deref_mut() {
+                let driver = driver as *mut dyn Driver;
+                let next = |queue: &Queue| loop {
+                    let timeout = expire();
+                    match queue.try_pop() {
+                        Some(id) => return id,
+                        // the tasks don't touch the driver while it's parked
+                        None => unsafe { (*driver).park(timeout) }
+                            .unwrap_or_else(|e| panic!("failed to park in the driver: {e}")),
+                    }
+                };
+                return crate::reactor::with_driver(driver, || run_tasks(tasks, live, queue, next));
+            }
+            run_tasks(tasks, live, queue, |queue| loop {
+                if let Some(id) = queue.pop(expire()) {
+                    return id;
+                }
+            })
+        })
+    }
+}
+
+// resume the tasks in the order given by `next` until all are done
+fn run_tasks(
+    tasks: &mut [Option<Task<'_>>],
+    live: &mut usize,
+    queue: &Queue,
+    next: impl Fn(&Queue) -> usize,
+) {
+    while *live > 0 {
+        let id = next(queue);
+        // the task may be done before it's woken
+        let task = match &mut tasks[id] {
+            Some(task) => task,
+            None => continue,
+        };
+        let ret = task.gen.resume_with_waker(&task.waker);
+        if task.gen.is_done() {
+            tasks[id] = None;
+            *live -= 1;
+        } else if ret == Some(Poll::Ready(())) {
+            queue.push(id);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! # C FFI
+//!
+//! `extern "C"` API to drive generators over type erased payloads,
+//! see `include/generator.h` for the C declarations
+//!
+
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::gen_impl::{default_stack_words, Generator, Gn};
+use crate::rt::Error;
+use crate::scope::Scope;
+
+// the type erased payload
+struct Payload(*mut c_void);
+
+// it's the C side responsibility to make the payload sendable
+unsafe impl Send for Payload {}
+
+/// the generator body written in C, receives the scope and the user arg
+pub type GeneratorFn = unsafe extern "C" fn(*mut FfiScope<'static>, *mut c_void) -> *mut c_void;
+
+/// the opaque generator handle
+pub struct FfiGenerator {
+    gen: Generator<'static, Payload, Payload>,
+}
+
+/// the opaque scope handle passed to the generator body
+pub struct FfiScope<'s> {
+    scope: Scope<'s, 'static, Payload, Payload>,
+    cancelled: bool,
+}
+
+// run the generator, a panic can't unwind into the C caller
+fn catch<R>(f: impl FnOnce() -> R) -> Option<R> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
+
+/// create a generator that runs `f(scope, arg)` on its own stack
+///
+/// a `stack_size` of 0 means the default stack size (in words)
+#[no_mangle]
+pub extern "C" fn generator_new(
+    f: GeneratorFn,
+    arg: *mut c_void,
+    stack_size: usize,
+) -> *mut FfiGenerator {
+    let size = if stack_size == 0 {
+        default_stack_words()
+    } else {
+        stack_size
+    };
+    let arg = Payload(arg);
+    let gen = Gn::new_scoped_opt(size, move |scope| {
+        let arg = arg;
+        let mut scope = FfiScope {
+            scope,
+            cancelled: false,
+        };
+        // the scope lives on the generator stack until the body returns
+        let scope = (&mut scope as *mut FfiScope<'_>).cast::<FfiScope<'static>>();
+        Payload(unsafe { f(scope, arg.0) })
+    });
+    Box::into_raw(Box::new(FfiGenerator { gen }))
+}
+
+/// resume the generator, return the yielded or returned value
+///
+/// return null if the generator is done or panicked
+///
+/// # Safety
+///
+/// `gen` must be a valid handle returned by `generator_new`
+#[no_mangle]
+pub unsafe extern "C" fn generator_resume(gen: *mut FfiGenerator) -> *mut c_void {
+    let gen = &mut (*gen).gen;
+    catch(|| gen.resume())
+        .flatten()
+        .map_or(ptr::null_mut(), |p| p.0)
+}
+
+/// send the para to the generator, return the yielded or returned value
+///
+/// return null if the generator is done or panicked
+///
+/// # Safety
+///
+/// `gen` must be a valid handle returned by `generator_new`
+#[no_mangle]
+pub unsafe extern "C" fn generator_send(gen: *mut FfiGenerator, para: *mut c_void) -> *mut c_void {
+    let gen = &mut (*gen).gen;
+    catch(|| gen.raw_send(Some(Payload(para))))
+        .flatten()
+        .map_or(ptr::null_mut(), |p| p.0)
+}
+
+/// check if the generator is done
+///
+/// # Safety
+///
+/// `gen` must be a valid handle returned by `generator_new`
+#[no_mangle]
+pub unsafe extern "C" fn generator_is_done(gen: *const FfiGenerator) -> bool {
+    (*gen).gen.is_done()
+}
+
+/// cancel the generator, the suspended `generator_yield` returns null and
+/// `generator_is_cancelled` returns true, the body should return promptly
+///
+/// # Safety
+///
+/// `gen` must be a valid handle returned by `generator_new`
+#[no_mangle]
+pub unsafe extern "C" fn generator_cancel(gen: *mut FfiGenerator) {
+    let gen = &mut (*gen).gen;
+    let _ = catch(|| gen.cancel());
+}
+
+/// free the generator, a suspended generator is cancelled first
+///
+/// # Safety
+///
+/// `gen` must be a valid handle returned by `generator_new` and must not
+/// be used after this call
+#[no_mangle]
+pub unsafe extern "C" fn generator_free(gen: *mut FfiGenerator) {
+    let gen = Box::from_raw(gen);
+    let _ = catch(move || drop(gen));
+}
+
+/// yield the value from the generator body, return the next sent para
+///
+/// return null without suspending once the generator is cancelled
+///
+/// # Safety
+///
+/// `scope` must be the scope passed to the running generator body
+#[no_mangle]
+pub unsafe extern "C" fn generator_yield(
+    scope: *mut FfiScope<'_>,
+    value: *mut c_void,
+) -> *mut c_void {
+    let scope = &mut *scope;
+    if scope.cancelled {
+        return ptr::null_mut();
+    }
+
+    // the cancel panic must not unwind through the C frames
+    let s = &mut scope.scope;
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        s.yield_with(Payload(value));
+        s.get_yield()
+    })) {
+        Ok(para) => para.map_or(ptr::null_mut(), |p| p.0),
+        Err(e) => match e.downcast_ref::<Error>() {
+            Some(Error::Cancel) => {
+                scope.cancelled = true;
+                ptr::null_mut()
+            }
+            _ => std::process::abort(),
+        },
+    }
+}
+
+/// check if the generator is cancelled
+///
+/// # Safety
+///
+/// `scope` must be the scope passed to the running generator body
+#[no_mangle]
+pub unsafe extern "C" fn generator_is_cancelled(scope: *const FfiScope<'_>) -> bool {
+    (*scope).cancelled
+}
+
+/// get the id of the generator running on the current thread, 0 if none
+///
+/// it's cheap and can be called from a signal handler, e.g. by a sampling
+/// profiler to attribute the samples to the generators
+#[no_mangle]
+pub extern "C" fn generator_current_id() -> usize {
+    crate::rt::current_id().unwrap_or(0)
+}
+
+/// get the name of the generator running on the current thread, null if
+/// there is none or it's not named
+#[no_mangle]
+pub extern "C" fn generator_current_name() -> *const c_char {
+    crate::rt::current_name().map_or(ptr::null(), |name| name.as_ptr())
+}
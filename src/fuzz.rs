@@ -0,0 +1,178 @@
+//! # fuzzing support
+//!
+//! interpret a byte string as a sequence of operations on a few generators
+//! and check each result against a model of the generator state machine,
+//! see the cargo-fuzz targets in `fuzz/`
+//!
+//! each operation is an opcode byte followed by its argument bytes, the
+//! missing bytes at the end of the input are read as zero. The state and the
+//! stack usage of the touched generator are checked after every operation
+//!
+//! | opcode | operation                                       |
+//! |--------|-------------------------------------------------|
+//! | 0      | create a generator in a slot, replacing the old |
+//! | 1      | send a para                                     |
+//! | 2      | resume without para                             |
+//! | 3      | cancel                                          |
+//! | 4      | drop                                            |
+//! | 5      | re-init by `reset_with`                         |
+//! | 6      | nothing, only the check after every operation   |
+//!
+
+use crate::gen_impl::{Generator, Gn, ResetError, State};
+use crate::scope::Scope;
+
+// the number of generators alive at the same time
+const SLOTS: usize = 4;
+
+// the stack sizes in words, the odd one turns on the full usage tracking,
+// the smallest one still holds the deepest `burn` in debug builds
+const STACK_SIZES: [usize; 4] = [0x800, 0x801, 0x1000, 0x4000];
+
+// the bytes read from the input
+struct Input<'d>(std::slice::Iter<'d, u8>);
+
+impl Input<'_> {
+    fn byte(&mut self) -> u8 {
+        self.0.next().copied().unwrap_or(0)
+    }
+}
+
+// use some stack before yielding
+#[inline(never)]
+fn burn(depth: u8) -> u64 {
+    let buf = std::hint::black_box([depth; 0x200]);
+    match depth {
+        0 => u64::from(buf[0]),
+        _ => burn(depth - 1) + u64::from(buf[0x1ff]),
+    }
+}
+
+// the generator under test, it yields the sum of the paras `yields` times
+// and returns the sum
+fn body(yields: u8, depth: u8) -> impl FnOnce(Scope<'_, 'static, u64, u64>) -> u64 + Send {
+    move |mut s| {
+        let mut acc = s.get_yield().unwrap_or(0);
+        for _ in 0..yields {
+            std::hint::black_box(burn(depth));
+            acc = acc.wrapping_add(s.yield_(acc).unwrap_or(0));
+        }
+        acc
+    }
+}
+
+// the expected behavior of a generator created by `body`
+struct Model {
+    state: State,
+    yields: u8,
+    // the yields to go after the current one
+    left: u8,
+    acc: u64,
+}
+
+impl Model {
+    fn new(yields: u8) -> Self {
+        Model {
+            state: State::Fresh,
+            yields,
+            left: 0,
+            acc: 0,
+        }
+    }
+
+    fn send(&mut self, para: Option<u64>) -> Option<u64> {
+        let para = para.unwrap_or(0);
+        match self.state {
+            State::Fresh => {
+                self.acc = para;
+                self.left = self.yields;
+            }
+            State::Suspended => self.acc = self.acc.wrapping_add(para),
+            State::Done | State::Running => return None,
+        }
+        if self.left == 0 {
+            self.state = State::Done;
+        } else {
+            self.left -= 1;
+            self.state = State::Suspended;
+        }
+        Some(self.acc)
+    }
+
+    fn cancel(&mut self) {
+        self.state = State::Done;
+    }
+}
+
+struct Slot {
+    gen: Generator<'static, u64, u64>,
+    model: Model,
+}
+
+impl Slot {
+    fn check(&self) {
+        assert_eq!(self.gen.state(), self.model.state);
+        assert_eq!(self.gen.is_done(), self.model.state == State::Done);
+        let (total, used) = self.gen.stack_usage();
+        assert!(used <= total, "stack usage {used} exceeds {total}");
+    }
+}
+
+/// run the operations encoded in `data` and panic on the first result that
+/// differs from the model
+///
+/// ```
+/// // create a generator yielding twice, send 1, resume and cancel it
+/// generator::fuzz::run(&[0, 0, 2, 2, 0, 1, 0, 1, 2, 0, 3, 0]);
+/// ```
+pub fn run(data: &[u8]) {
+    let mut input = Input(data.iter());
+    let mut slots: [Option<Slot>; SLOTS] = Default::default();
+
+    while input.0.len() > 0 {
+        let op = input.byte() % 7;
+        let slot = &mut slots[usize::from(input.byte()) % SLOTS];
+        match op {
+            0 => {
+                let size = STACK_SIZES[usize::from(input.byte()) % STACK_SIZES.len()];
+                let (yields, depth) = (input.byte() % 8, input.byte() % 4);
+                *slot = Some(Slot {
+                    gen: Gn::<u64>::new_scoped_opt(size, body(yields, depth)),
+                    model: Model::new(yields),
+                });
+            }
+            1 | 2 => {
+                let para = (op == 1).then(|| u64::from(input.byte()));
+                if let Some(s) = slot {
+                    assert_eq!(s.gen.raw_send(para), s.model.send(para));
+                }
+            }
+            3 => {
+                if let Some(s) = slot {
+                    s.gen.cancel();
+                    s.model.cancel();
+                }
+            }
+            4 => *slot = None,
+            5 => {
+                let (yields, depth) = (input.byte() % 8, input.byte() % 4);
+                if let Some(s) = slot {
+                    match s.gen.reset_with(body(yields, depth)) {
+                        Ok(()) => {
+                            assert_ne!(s.model.state, State::Suspended);
+                            s.model = Model::new(yields);
+                        }
+                        Err(e) => {
+                            assert_eq!(e, ResetError::Suspended);
+                            assert_eq!(s.model.state, State::Suspended);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(s) = slot {
+            s.check();
+        }
+    }
+}
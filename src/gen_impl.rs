@@ -4,21 +4,91 @@
 //!
 
 use crate::detail::gen_init;
+use crate::group::GeneratorGroup;
+use crate::hook::{global_hook, runtime_hooks, switch_extras, Switch};
 use crate::reg_context::RegContext;
-use crate::rt::{Context, ContextStack, Error};
+use crate::rt::{
+    max_nesting_depth, overflow_message, Context, ContextStack, Error, ForeignCancel, GuardFault,
+};
 use crate::scope::Scope;
-use crate::stack::{Func, Stack, StackBox};
-
-use std::any::Any;
+#[cfg(feature = "debug-checks")]
+use crate::stack::SCRATCH_NAME;
+use crate::stack::{Entry, Func, Stack, StackBox, StackConfig, StackError};
+use crate::sync::Arc;
+
+use std::any::{Any, TypeId};
+use std::convert::Infallible;
+use std::ffi::CStr;
 use std::fmt;
+use std::iter::FusedIterator;
 use std::marker::PhantomData;
+use std::mem::MaybeUninit;
 use std::panic;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 
-/// The default stack size for generators, in bytes.
+/// The default stack size for generators, in words.
 // windows has a minimal size as 0x4a8!!!!
 pub const DEFAULT_STACK_SIZE: usize = 0x1000;
 
+// the process wide default stack size in words, 0 means not initialized
+static DEFAULT_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+// the env var to override the default stack size in bytes
+const STACK_SIZE_ENV: &str = "GENERATOR_DEFAULT_STACK_SIZE";
+
+// round the bytes up to an even number of words, an odd size would turn on
+// the full stack usage tracking
+fn stack_words(bytes: usize) -> usize {
+    let words = bytes.div_ceil(std::mem::size_of::<usize>());
+    ((words + 1) & !1).max(2)
+}
+
+/// set the default stack size in bytes for the constructors without a size
+///
+/// it takes precedence over the `GENERATOR_DEFAULT_STACK_SIZE` env var,
+/// the size is rounded up to an even number of words
+pub fn set_default_stack_size(bytes: usize) {
+    DEFAULT_SIZE.store(stack_words(bytes), Ordering::Relaxed);
+}
+
+/// get the default stack size in bytes
+pub fn default_stack_size() -> usize {
+    default_stack_words() * std::mem::size_of::<usize>()
+}
+
+#[inline]
+pub(crate) fn default_stack_words() -> usize {
+    #[cfg(feature = "deterministic")]
+    if crate::deterministic::is_enabled() {
+        return DEFAULT_STACK_SIZE;
+    }
+    match DEFAULT_SIZE.load(Ordering::Relaxed) {
+        0 => init_default_stack_words(),
+        words => words,
+    }
+}
+
+// read the env var once, `DEFAULT_STACK_SIZE` if not set or invalid
+#[cold]
+fn init_default_stack_words() -> usize {
+    let words = match std::env::var(STACK_SIZE_ENV) {
+        Ok(v) => match v.trim().parse::<usize>() {
+            Ok(bytes) => stack_words(bytes),
+            Err(_) => {
+                warn!("invalid {STACK_SIZE_ENV}: {v:?}");
+                DEFAULT_STACK_SIZE
+            }
+        },
+        Err(_) => DEFAULT_STACK_SIZE,
+    };
+    // a size set at the same time wins
+    match DEFAULT_SIZE.compare_exchange(0, words, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => words,
+        Err(set) => set,
+    }
+}
+
 #[inline]
 #[cold]
 fn cold() {}
@@ -37,11 +107,153 @@ pub(crate) fn unlikely(b: bool) -> bool {
     b
 }
 
+// the output left in the `ret` slot between resumes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Buffered {
+    Empty,
+    // returned by the next resume
+    Peeked,
+    // borrowed by resume_ref, dropped by the next resume
+    Lent,
+}
+
 /// the generator obj type, the functor passed to it must be Send
+///
+/// # Address stability
+///
+/// The generator state (`GeneratorImpl`) is allocated at the top of the
+/// generator's own stack and never moves. The `GeneratorObj` is only a
+/// handle that points to it, so all the self-referential pointers
+/// (`context.parent`, the para/ret slots captured by the `Scope`) stay
+/// valid when the handle is moved between resumes. That's why the type
+/// is `Unpin`, and a `Pin<&mut Generator>` can be resumed directly.
+///
+/// The stack descriptor is stored inline in the `GeneratorImpl`, so the
+/// handle is a single non-null pointer and `resume`/`is_done` only follow
+/// one indirection. The state can't be stored inline in the handle, since
+/// the generator side updates it through its own pointer while the handle
+/// is free to move.
+///
+/// # Unwind safety
+///
+/// The generator is not `UnwindSafe`, the type of its closure is erased so
+/// the closure's own unwind safety can't be told, a `catch_unwind` around
+/// it needs an `AssertUnwindSafe`. A panic can only leave these states
+/// behind:
+///
+/// * a panic inside the generator is propagated out of the `resume` and
+///   finishes the generator, the closure and all it captured are dropped by
+///   the unwinding, the generator is `Done` and `resume` returns `None`
+/// * a panic in a switch hook or a stack watch callback is raised out of
+///   the `resume` before or after the switch, the generator is left in the
+///   state it has at that point
+/// * a panic outside of the generator leaves it where it's suspended, the
+///   closure only runs again from its yield by the next `resume`
+///
+/// The closure is not required to be unwind safe, like the one passed to
+/// `thread::spawn`, the state it shares with the outside, e.g. through a
+/// `&RefCell`, is its own business.
 pub struct GeneratorObj<'a, A, T, const LOCAL: bool> {
     gen: StackBox<GeneratorImpl<'a, A, T>>,
 }
 
+// moving the handle never moves the `GeneratorImpl`, see above
+impl<A, T, const LOCAL: bool> Unpin for GeneratorObj<'_, A, T, LOCAL> {}
+
+/// the lifecycle stage of a generator, see `GeneratorObj::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// not resumed yet
+    Fresh,
+    /// suspended by a yield, waiting for the next resume
+    Suspended,
+    /// running, it can only be seen from the generator itself, e.g. through
+    /// a raw pointer
+    Running,
+    /// finished, cancelled, or panicked
+    Done,
+}
+
+/// the saved registers and the live frames of a suspended generator, see
+/// `GeneratorObj::suspended_stack`
+#[derive(Debug, Clone, Copy)]
+pub struct SuspendedStack<'g> {
+    ip: usize,
+    sp: usize,
+    frames: &'g [u8],
+}
+
+impl<'g> SuspendedStack<'g> {
+    /// the instruction pointer the generator resumes at
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// the saved stack pointer of the generator
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    /// the used stack bytes from the stack pointer to the first frame, the
+    /// first byte is at the address `sp()`
+    pub fn frames(&self) -> &'g [u8] {
+        self.frames
+    }
+}
+
+/// the result of a step of a stackless generator, see `Gn::new_stackless`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume<T> {
+    /// yield the value, the step is called again by the next resume
+    Yield(T),
+    /// finish the generator with the return value
+    Return(T),
+    /// finish the generator without a return value, like `done!()`
+    Done,
+}
+
+/// the error of `GeneratorObj::try_resume`, the generator is cancelled
+/// instead of resumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ResumeError {
+    /// the group of the generator is expired, see `join_group`
+    DeadlineExceeded,
+    /// the generator it's attached to by `attach` is cancelled or dropped
+    /// before it's done
+    CreatorCancelled,
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ResumeError::DeadlineExceeded => "the deadline of the generator group is exceeded",
+            ResumeError::CreatorCancelled => "the generator it's attached to is cancelled",
+        })
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+/// the error of `reset_with`, the new closure is dropped and the generator
+/// is left as it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ResetError {
+    /// the generator is suspended, it's neither done nor fresh
+    Suspended,
+}
+
+impl fmt::Display for ResetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ResetError::Suspended => "the generator is suspended",
+        })
+    }
+}
+
+impl std::error::Error for ResetError {}
+
 /// the generator type, the functor passed to it must be Send
 pub type Generator<'a, A, T> = GeneratorObj<'a, A, T, false>;
 
@@ -50,22 +262,75 @@ unsafe impl<A: Send, T: Send> Send for Generator<'static, A, T> {}
 
 impl<'a, A, T> Generator<'a, A, T> {
     /// init a heap based generator with scoped closure
+    ///
+    /// a done generator can be re-armed with a new closure, the stack is
+    /// reused without reallocation and its usage tracking is reset
     pub fn scoped_init<F>(&mut self, f: F)
     where
         for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
         T: Send + 'a,
         A: Send + 'a,
     {
-        self.gen.scoped_init(f);
+        self.gen.template = None;
+        self.gen.init_scoped(f);
+    }
+
+    /// replace the closure of a done or not started generator and run it
+    /// again, the stack is reused
+    ///
+    /// returns `ResetError::Suspended` and drops `f` if the generator is
+    /// suspended, `cancel` it first to reset it anyway
+    pub fn reset_with<F>(&mut self, f: F) -> Result<(), ResetError>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
+        T: Send + 'a,
+        A: Send + 'a,
+    {
+        self.gen.check_reset()?;
+        self.gen.template = None;
+        self.gen.init_scoped(f);
+        Ok(())
     }
 
     /// init a heap based generator
-    // it's can be used to re-init a 'done' generator before it's get dropped
+    ///
+    /// it can be used to re-init a 'done' generator, see `scoped_init`
     pub fn init_code<F: FnOnce() -> T + Send + 'a>(&mut self, f: F)
     where
         T: Send + 'a,
     {
-        self.gen.init_code(f);
+        self.gen.template = None;
+        self.gen.init_plain(f);
+    }
+
+    /// take the closure back out of a generator that's never resumed, the
+    /// stack goes back to the pool
+    ///
+    /// a plain closure can be run inline, a scoped one can re-arm another
+    /// generator by `scoped_init`. returns the generator back if it's
+    /// started
+    pub fn into_inner(mut self) -> Result<Inner<'a, A, T>, Self> {
+        match self.gen.take_inner() {
+            Some(f) => Ok(f),
+            None => Err(self),
+        }
+    }
+}
+
+/// the closure taken back out of a generator by `Generator::into_inner`
+pub enum Inner<'a, A, T> {
+    /// the closure of `Gn::new_opt` and `init_code`, it can be run inline
+    Plain(Box<dyn FnOnce() -> T + Send + 'a>),
+    /// the scoped closure, it needs a generator to run
+    Scoped(Box<ScopedFn<'a, A, T>>),
+}
+
+impl<A, T> fmt::Debug for Inner<'_, A, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inner::Plain(_) => f.write_str("Plain(..)"),
+            Inner::Scoped(_) => f.write_str("Scoped(..)"),
+        }
     }
 }
 
@@ -74,19 +339,60 @@ pub type LocalGenerator<'a, A, T> = GeneratorObj<'a, A, T, true>;
 
 impl<'a, A, T> LocalGenerator<'a, A, T> {
     /// init a heap based generator with scoped closure
+    ///
+    /// a done generator can be re-armed with a new closure, the stack is
+    /// reused without reallocation and its usage tracking is reset
     pub fn scoped_init<F>(&mut self, f: F)
     where
         for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + 'a,
         T: 'a,
         A: 'a,
     {
+        self.gen.template = None;
+        self.gen.scoped_init(f);
+    }
+
+    /// replace the closure of a done or not started generator and run it
+    /// again, see `Generator::reset_with`
+    pub fn reset_with<F>(&mut self, f: F) -> Result<(), ResetError>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + 'a,
+        T: 'a,
+        A: 'a,
+    {
+        self.gen.check_reset()?;
+        self.gen.template = None;
         self.gen.scoped_init(f);
+        Ok(())
     }
 }
 
+// the raw pointer magic, mixed with the type hash
+const RAW_MAGIC: usize = 0x5241_5747;
+
 impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
+    // identify the generator type for the raw pointer APIs
+    //
+    // `TypeId` needs `A: 'static` and `T: 'static`, but a scoped generator
+    // may yield or take borrowed data and still go through `into_raw`, so the
+    // bounds would break those callers. the type names are the same for the
+    // same type, a name shared by two distinct types only makes the check
+    // miss the mismatch, it never rejects a right pointer
+    fn raw_tag() -> usize {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        std::any::type_name::<A>().hash(&mut hasher);
+        std::any::type_name::<T>().hash(&mut hasher);
+        LOCAL.hash(&mut hasher);
+        (hasher.finish() as usize ^ RAW_MAGIC) | 1
+    }
+
     /// Constructs a Generator from a raw pointer.
     ///
+    /// In debug builds, the pointer is checked against the tag written by
+    /// `into_raw`, so a type mismatch or reclaiming the same pointer twice
+    /// panics immediately.
+    ///
     /// # Safety
     ///
     /// This function is unsafe because improper use may lead to
@@ -94,6 +400,22 @@ impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
     /// function is called twice on the same raw pointer.
     #[inline]
     pub unsafe fn from_raw(raw: *mut usize) -> Self {
+        // the context is always at the start of the generator
+        let context = &mut *(raw as *mut Context);
+        let tag = std::mem::replace(&mut context.raw_tag, 0);
+        if cfg!(checks) && tag != Self::raw_tag() {
+            if tag == 0 {
+                panic!(
+                    "Generator::from_raw: the pointer is not from `into_raw` or already reclaimed"
+                );
+            }
+            panic!(
+                "Generator::from_raw: type mismatch, expected Generator<{}, Output={}, Local={}>",
+                std::any::type_name::<A>(),
+                std::any::type_name::<T>(),
+                LOCAL
+            );
+        }
         GeneratorObj {
             gen: StackBox::from_raw(raw as *mut GeneratorImpl<'a, A, T>),
         }
@@ -101,13 +423,17 @@ impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
 
     /// Consumes the `Generator`, returning a wrapped raw pointer.
     #[inline]
-    pub fn into_raw(self) -> *mut usize {
+    pub fn into_raw(mut self) -> *mut usize {
+        self.gen.context.raw_tag = Self::raw_tag();
         let ret = self.gen.as_ptr() as *mut usize;
         std::mem::forget(self);
         ret
     }
 
     /// prefetch the generator into cache
+    ///
+    /// this touches the saved registers, the top of the generator stack and
+    /// the exchange slots, call it a while before the next resume
     #[inline]
     pub fn prefetch(&self) {
         self.gen.prefetch();
@@ -144,19 +470,91 @@ impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
         self.gen.resume()
     }
 
+    /// resume the generator like `resume`, but tell a yield without a value
+    /// from the end
+    ///
+    /// return `None` when the generator is done, `Some(None)` when it's
+    /// suspended by [`Scope::co_yield`]
+    #[inline]
+    pub fn resume_step(&mut self) -> Option<Option<T>> {
+        match self.gen.resume() {
+            None if self.gen.is_done() => None,
+            r => Some(r),
+        }
+    }
+
     /// `raw_send`
     #[inline]
     pub fn raw_send(&mut self, para: Option<A>) -> Option<T> {
         self.gen.raw_send(para)
     }
 
+    /// resume the generator once and buffer the output without consuming it
+    ///
+    /// the next `resume`/`send` returns the buffered value without resuming
+    /// the generator, the para of that `send` is saved for the following
+    /// resume, just like `set_para`
+    #[inline]
+    pub fn peek(&mut self) -> Option<&T> {
+        self.gen.peek()
+    }
+
+    /// resume the generator and borrow the output in place
+    ///
+    /// the output is kept in the generator until the next resume, so a large
+    /// value can be inspected or modified without moving it out
+    #[inline]
+    pub fn resume_ref(&mut self) -> Option<&mut T> {
+        self.gen.resume_ref()
+    }
+
+    /// resume the generator and write the output into the caller provided slot
+    ///
+    /// a scoped generator yields directly into `out` without the hop through
+    /// the generator owned storage, return true if `out` is initialized
+    #[inline]
+    pub fn resume_into(&mut self, out: &mut MaybeUninit<T>) -> bool {
+        self.gen.resume_into(out)
+    }
+
+    /// preserve the extended vector state across the switches
+    ///
+    /// the switch only keeps the vector registers the ABI requires, e.g.
+    /// xmm6-15 on windows, this also keeps the upper ymm/zmm halves and the
+    /// avx-512 registers for the generators that yield in the middle of a
+    /// vector kernel. Return false if xsave is not available
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_preserve_vector_state(&mut self, on: bool) -> bool {
+        self.gen.xstate = if on {
+            crate::detail::XState::new()
+        } else {
+            None
+        };
+        self.gen.update_extras();
+        self.gen.xstate.is_some() == on
+    }
+
     /// send interface
     pub fn send(&mut self, para: A) -> T {
         self.gen.send(para)
     }
 
+    /// resume the generator with the payload raised at the pending yield
+    ///
+    /// the yield panics with the payload, it's propagated out of `throw` if
+    /// the generator doesn't catch it, or the generator handles it as the
+    /// `Err` of [`Scope::yield_catch`] and keeps running. A not started or a
+    /// stackless generator is cancelled and the payload is raised here, a
+    /// done one ignores it, and a peeked output is dropped
+    pub fn throw<E: Any + Send>(&mut self, e: E) -> Option<T> {
+        self.gen.throw(Box::new(e))
+    }
+
     /// cancel the generator
     /// this will trigger a Cancel panic to unwind the stack and finish the generator
+    ///
+    /// with `panic = "abort"` the stack can't unwind, the suspended frames
+    /// are abandoned without running their destructors
     pub fn cancel(&mut self) {
         self.gen.cancel()
     }
@@ -167,19 +565,270 @@ impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
         self.gen.is_done()
     }
 
+    /// check if the generator is resumed since it's created or re-initialized
+    ///
+    /// a not started generator, `State::Fresh`, is torn down by just dropping
+    /// its closure, while a suspended one is resumed to unwind its frames
+    /// when it's cancelled or dropped
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.gen.is_started()
+    }
+
+    /// bind the generator to the current thread, resuming it on another
+    /// thread panics in debug builds
+    ///
+    /// a local generator is bound to the thread creating it
+    pub fn bind_thread(&mut self) {
+        #[cfg(checks)]
+        {
+            self.gen.owner = Some(thread::current().id());
+        }
+    }
+
+    /// allow the generator to be resumed on any thread, it undoes the
+    /// binding of `bind_thread` and the one of a local generator
+    ///
+    /// a local generator looks up the thread context on each resume then, it's
+    /// still up to the caller to not use its `!Send` data across the threads
+    pub fn allow_any_thread(&mut self) {
+        self.gen.root = std::ptr::null_mut();
+        #[cfg(checks)]
+        {
+            self.gen.owner = None;
+        }
+    }
+
+    /// get how many times the generator is resumed, including the resume to
+    /// cancel it
+    ///
+    /// the counters are reset when the generator is re-initialized
+    #[inline]
+    pub fn resume_count(&self) -> usize {
+        self.gen.resumes
+    }
+
+    /// get how many values the generator yielded, the return value is not
+    /// counted
+    #[inline]
+    pub fn yield_count(&self) -> usize {
+        self.gen.yields
+    }
+
+    /// get the lifecycle stage of the generator
+    #[inline]
+    pub fn state(&self) -> State {
+        self.gen.state()
+    }
+
     /// get stack total size and used size in word
+    ///
+    /// the guard region below the stack is not included, see
+    /// `Builder::guard_size`
     pub fn stack_usage(&self) -> (usize, usize) {
         self.gen.stack_usage()
     }
+
+    /// get the bytes of the stack that are resident in memory, the stack is
+    /// reserved at once but only the touched pages are backed by memory
+    ///
+    /// unlike the peak usage of `stack_usage`, the pages stay resident after
+    /// the frames are popped, this is what the stack costs the process
+    #[cfg(feature = "stack-rss")]
+    pub fn stack_resident_bytes(&self) -> std::io::Result<usize> {
+        self.gen.stack.resident_bytes()
+    }
+
+    /// inspect the saved registers and the live frames of a suspended
+    /// generator, returns `None` if the generator is not suspended or is
+    /// stackless
+    ///
+    /// it's for debuggers and crash handlers to dump what a stuck generator
+    /// was doing
+    pub fn suspended_stack(&self) -> Option<SuspendedStack<'_>> {
+        self.gen.suspended_stack()
+    }
+
+    /// the id of the generator that is passed to the switch hooks and
+    /// returned by `current_id` while it's running
+    ///
+    /// it's unique among the live generators, the id of a dropped generator
+    /// may be reused
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.gen.as_ptr() as usize
+    }
+
+    /// set the name reported by `current_name` while the generator is running
+    ///
+    /// the stack mapping is labeled with the name too where the OS supports
+    /// it, e.g. `[anon:generator:<name>]` in `/proc/<pid>/smaps` on linux
+    pub fn set_name(&mut self, name: &'static CStr) {
+        self.gen.context.name = Some(name);
+        self.gen.stack.set_name(name);
+    }
+
+    /// get the name set by `set_name`
+    #[inline]
+    pub fn name(&self) -> Option<&'static CStr> {
+        self.gen.context.name
+    }
+
+    /// stop the generator from being cancelled with the generator that
+    /// created it, e.g. for a generator made and handed out by a factory
+    /// generator that outlives it
+    ///
+    /// a generator created inside a running one is attached to it, and
+    /// cancelled once it's cancelled or dropped before it's done, in the
+    /// order of
+    /// - the ones still held by its frames, dropped by the unwinding from
+    ///   the newest frame, before the frames holding them finish
+    /// - the ones escaped from its frames, at their next resume instead of
+    ///   running on, `try_resume` tells it by `ResumeError::CreatorCancelled`
+    ///
+    /// the cancelled ones cancel their own attached generators the same way
+    pub fn detach(&mut self) {
+        self.gen.parent_cancel = None;
+        self.gen.update_extras();
+    }
+
+    /// attach the generator to the running generator that calls this
+    /// instead of the one that created it, see `detach`. It's a no-op
+    /// outside a generator
+    pub fn attach(&mut self) {
+        let current = ContextStack::current().top();
+        if current.is_generator() {
+            let token = current.cancel_token.get_or_insert_with(Default::default);
+            self.gen.parent_cancel = Some(token.clone());
+            self.gen.update_extras();
+        }
+    }
+
+    /// add the generator to the group, it's cancelled at its next resume
+    /// once the group expires, see `try_resume`
+    ///
+    /// the generators created inside it join the group too
+    pub fn join_group(&mut self, group: &GeneratorGroup) {
+        self.gen.context.group = Some(group.clone());
+        self.gen.update_extras();
+    }
+
+    /// resume the generator like `resume`, or cancel it and tell why if
+    /// its group is expired or the generator it's attached to is cancelled
+    pub fn try_resume(&mut self) -> Result<Option<T>, ResumeError> {
+        let err = if self.gen.group_expired() {
+            ResumeError::DeadlineExceeded
+        } else if self.gen.creator_cancelled() {
+            ResumeError::CreatorCancelled
+        } else {
+            return Ok(self.gen.resume());
+        };
+        self.gen.cancel();
+        Err(err)
+    }
+
+    /// set what a cancel does while the generator is suspended inside
+    /// `foreign_call`, it's `ForeignCancel::Abort` by default
+    pub fn set_foreign_cancel(&mut self, policy: ForeignCancel) {
+        self.gen.foreign_cancel = policy;
+    }
+
+    /// set the hook called with the generator id on each switch of this
+    /// generator, after the process wide one set by `set_switch_hook`
+    ///
+    /// the hook runs on the resumer side of the switch and is kept when the
+    /// generator is re-initialized
+    pub fn set_switch_hook<F>(&mut self, f: F)
+    where
+        F: FnMut(usize, Switch) + Send + 'a,
+    {
+        self.gen.switch_hook = Some(Box::new(f));
+        self.gen.update_extras();
+    }
+
+    /// call `f` with the total and the used stack size in words once the
+    /// stack usage reaches `percent` of the total
+    ///
+    /// the usage is checked each time the generator yields or returns, by
+    /// scanning the foot print filled into the free stack here, so it must
+    /// be set before the generator starts. The watch is removed after `f` is
+    /// called or when the generator is re-initialized
+    pub fn watch_stack<F>(&mut self, percent: u8, f: F)
+    where
+        F: FnOnce(usize, usize) + Send + 'a,
+    {
+        self.gen.watch_stack(percent, f);
+    }
+
+    /// run the remembered closure again from scratch on the same stack
+    ///
+    /// a suspended generator is cancelled first. returns `false` if the
+    /// generator is not restartable, or its closure is replaced by
+    /// `scoped_init` or `reset_with`
+    pub fn restart(&mut self) -> bool {
+        self.gen.restart()
+    }
+
+    /// clone a restartable generator that is not started yet
+    ///
+    /// the clone gets a fresh stack of the same size and a copy of the
+    /// closure, returns `None` if the generator is started or not restartable
+    pub fn try_clone(&self) -> Option<Self> {
+        let gen = self.gen.try_clone()?;
+        Some(GeneratorObj { gen })
+    }
+
+    /// set the number of remaining items reported by `Iterator::size_hint`
+    ///
+    /// the bounds include the return value and are decreased for each item
+    #[inline]
+    pub fn set_size_hint(&mut self, lower: usize, upper: Option<usize>) {
+        self.gen.size_hint = (lower, upper);
+    }
 }
 
+impl<T, const LOCAL: bool> GeneratorObj<'_, (), T, LOCAL> {
+    // the yields without a value are skipped, `None` only ends a done
+    // generator so the iterator is fused
+    #[cold]
+    fn skip_empty_yields(&mut self) -> Option<T> {
+        while !self.is_done() {
+            if let Some(v) = self.resume() {
+                return Some(v);
+            }
+        }
+        None
+    }
+}
+
+/// iterate the generator that don't need para
+///
+/// `&mut Generator` is also an iterator, so `for x in &mut gen { ... }`
+/// can be used to keep the generator for later use, e.g. check `is_done`
 impl<T, const LOCAL: bool> Iterator for GeneratorObj<'_, (), T, LOCAL> {
     type Item = T;
     fn next(&mut self) -> Option<T> {
-        self.resume()
+        let ret = match self.resume() {
+            Some(v) => v,
+            None => self.skip_empty_yields()?,
+        };
+        let (lower, upper) = &mut self.gen.size_hint;
+        *lower = lower.saturating_sub(1);
+        *upper = upper.map(|n| n.saturating_sub(1));
+        Some(ret)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.is_done() {
+            return (0, Some(0));
+        }
+        self.gen.size_hint
     }
 }
 
+// resume always returns None after the generator is done
+impl<T, const LOCAL: bool> FusedIterator for GeneratorObj<'_, (), T, LOCAL> {}
+
 impl<A, T, const LOCAL: bool> fmt::Debug for GeneratorObj<'_, A, T, LOCAL> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -205,7 +854,22 @@ impl<A> Gn<A> {
         T: Send + 'a,
         A: Send + 'a,
     {
-        Self::new_scoped_opt(DEFAULT_STACK_SIZE, f)
+        Self::new_scoped_opt(default_stack_words(), f)
+    }
+
+    /// create a scoped generator with default stack size for a closure that
+    /// never returns, e.g. `|mut s| loop { s.yield_(v); }`
+    ///
+    /// it never finishes unless cancelled, the size hint is unbounded
+    pub fn new_scoped_infinite<'a, T, F>(f: F) -> Generator<'a, A, T>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> Infallible + Send + 'a,
+        T: Send + 'a,
+        A: Send + 'a,
+    {
+        let mut gen = Self::new_scoped(move |s| match f(s) {});
+        gen.set_size_hint(usize::MAX, None);
+        gen
     }
 
     /// create a scoped local generator with default stack size
@@ -215,31 +879,204 @@ impl<A> Gn<A> {
         T: 'a,
         A: 'a,
     {
-        Self::new_scoped_opt_local(DEFAULT_STACK_SIZE, f)
+        Self::new_scoped_opt_local(default_stack_words(), f)
     }
 
     /// create a scoped generator with specified stack size
+    ///
+    /// panics if the stack can't be allocated, see `try_new_scoped_opt`
     pub fn new_scoped_opt<'a, T, F>(size: usize, f: F) -> Generator<'a, A, T>
     where
         for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
         T: Send + 'a,
         A: Send + 'a,
     {
-        let mut gen = GeneratorImpl::<A, T>::new(Stack::new(size));
-        gen.scoped_init(f);
-        Generator { gen }
+        Self::try_new_scoped_opt(size, f).unwrap_or_else(|e| stack_error(e))
     }
 
     /// create a scoped local generator with specified stack size
+    ///
+    /// panics if the stack can't be allocated, see `try_new_scoped_opt_local`
     pub fn new_scoped_opt_local<'a, T, F>(size: usize, f: F) -> LocalGenerator<'a, A, T>
     where
         F: FnOnce(Scope<A, T>) -> T + 'a,
         T: 'a,
         A: 'a,
     {
-        let mut gen = GeneratorImpl::<A, T>::new(Stack::new(size));
+        Self::try_new_scoped_opt_local(size, f).unwrap_or_else(|e| stack_error(e))
+    }
+
+    /// create a scoped generator with specified stack size
+    ///
+    /// the size is rounded up to the page size, an error is returned if the
+    /// stack can't be allocated or is too small to hold the generator
+    pub fn try_new_scoped_opt<'a, T, F>(
+        size: usize,
+        f: F,
+    ) -> Result<Generator<'a, A, T>, StackError>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
+        T: Send + 'a,
+        A: Send + 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(GeneratorImpl::<A, T>::alloc_stack::<F>(size)?);
+        gen.init_scoped(f);
+        Ok(Generator { gen })
+    }
+
+    /// create a scoped local generator with specified stack size
+    ///
+    /// the size is rounded up to the page size, an error is returned if the
+    /// stack can't be allocated or is too small to hold the generator
+    pub fn try_new_scoped_opt_local<'a, T, F>(
+        size: usize,
+        f: F,
+    ) -> Result<LocalGenerator<'a, A, T>, StackError>
+    where
+        F: FnOnce(Scope<A, T>) -> T + 'a,
+        T: 'a,
+        A: 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(GeneratorImpl::<A, T>::alloc_stack::<F>(size)?);
+        gen.bind_local();
         gen.scoped_init(f);
-        LocalGenerator { gen }
+        Ok(LocalGenerator { gen })
+    }
+}
+
+impl<A> Gn<A> {
+    /// create a builder for a generator with more stack options
+    pub fn builder() -> Builder<A> {
+        Builder::new()
+    }
+}
+
+/// Generator builder with the stack options
+///
+/// ```
+/// let mut g = generator::Gn::<()>::builder()
+///     .stack_size(0x4000)
+///     .prefault(true)
+///     .scoped(|mut s| {
+///         s.yield_(1);
+///         2
+///     })
+///     .unwrap();
+/// assert_eq!(g.resume(), Some(1));
+/// assert_eq!(g.resume(), Some(2));
+/// ```
+pub struct Builder<A = ()> {
+    size: usize,
+    config: StackConfig,
+    phantom: PhantomData<A>,
+}
+
+impl<A> Default for Builder<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> Builder<A> {
+    /// create a builder with the default stack size
+    pub fn new() -> Self {
+        Builder {
+            size: default_stack_words(),
+            config: StackConfig::default(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// set the stack size in words
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// lock the stack in memory with `mlock`, so it's never paged out
+    ///
+    /// the locked memory is limited by `RLIMIT_MEMLOCK` for an unprivileged
+    /// process, going over it fails with `StackError::IoError` of
+    /// `io::ErrorKind::OutOfMemory`
+    pub fn mlock(mut self, mlock: bool) -> Self {
+        self.config.mlock = mlock;
+        self
+    }
+
+    /// set the size in bytes of the guard region below the stack
+    ///
+    /// it's rounded up to the page size and is at least one page, a larger
+    /// guard catches the overflows by functions with multi-page frames. The
+    /// default is `default_guard_size`
+    pub fn guard_size(mut self, bytes: usize) -> Self {
+        self.config.guard = bytes.max(1);
+        self
+    }
+
+    /// commit the stack on demand like a thread stack on windows
+    ///
+    /// only the top of the stack is committed at first, a `PAGE_GUARD` page
+    /// below it grows the commit as the stack is used. The other OSes commit
+    /// the stack pages on the first touch anyway. It's ignored with `mlock`
+    /// or `prefault`, the usage of `stack_usage` is in pages with it
+    pub fn lazy_commit(mut self, lazy: bool) -> Self {
+        self.config.lazy = lazy;
+        self
+    }
+
+    /// keep `bytes` of the stack for the stack overflow handlers on windows,
+    /// like `SetThreadStackGuarantee` does for a thread
+    ///
+    /// the OS raises the stack overflow exception with the guaranteed bytes
+    /// left, the exception is passed to the handlers of the program instead
+    /// of failing the generator. It's rounded up to the page size and turns
+    /// on `lazy_commit`, the stack must be large enough to hold it. It's
+    /// ignored on the other OSes, except for the size check
+    pub fn stack_guarantee(mut self, bytes: usize) -> Self {
+        self.config.guarantee = bytes;
+        self
+    }
+
+    /// reserve `bytes` of the stack for the values of `Scope::alloc`
+    ///
+    /// the space is taken from the stack besides the frames, and it's reused
+    /// when the generator is re-initialized
+    pub fn scratch(mut self, bytes: usize) -> Self {
+        self.config.scratch = bytes;
+        self
+    }
+
+    /// fault in all the pages of the stack when it's allocated
+    pub fn prefault(mut self, prefault: bool) -> Self {
+        self.config.prefault = prefault;
+        self
+    }
+
+    /// create a scoped generator with the options
+    pub fn scoped<'a, T, F>(self, f: F) -> Result<Generator<'a, A, T>, StackError>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
+        T: Send + 'a,
+        A: Send + 'a,
+    {
+        let stack = GeneratorImpl::<A, T>::alloc_stack_with::<F>(self.size, self.config)?;
+        let mut gen = GeneratorImpl::<A, T>::new(stack);
+        gen.init_scoped(f);
+        Ok(Generator { gen })
+    }
+
+    /// create a scoped local generator with the options
+    pub fn scoped_local<'a, T, F>(self, f: F) -> Result<LocalGenerator<'a, A, T>, StackError>
+    where
+        F: FnOnce(Scope<A, T>) -> T + 'a,
+        T: 'a,
+        A: 'a,
+    {
+        let stack = GeneratorImpl::<A, T>::alloc_stack_with::<F>(self.size, self.config)?;
+        let mut gen = GeneratorImpl::<A, T>::new(stack);
+        gen.bind_local();
+        gen.scoped_init(f);
+        Ok(LocalGenerator { gen })
     }
 }
 
@@ -251,7 +1088,7 @@ impl<A: Any> Gn<A> {
     where
         F: FnOnce() -> T + Send + 'a,
     {
-        Self::new_opt(DEFAULT_STACK_SIZE, f)
+        Self::new_opt(default_stack_words(), f)
     }
 
     /// create a new generator with specified stack size
@@ -260,44 +1097,257 @@ impl<A: Any> Gn<A> {
     where
         F: FnOnce() -> T + Send + 'a,
     {
-        let mut gen = GeneratorImpl::<A, T>::new(Stack::new(size));
+        let stack =
+            GeneratorImpl::<A, T>::alloc_stack::<F>(size).unwrap_or_else(|e| stack_error(e));
+        let mut gen = GeneratorImpl::<A, T>::new(stack);
         gen.init_context();
-        gen.init_code(f);
+        gen.init_plain(f);
+        Generator { gen }
+    }
+}
+
+impl<A: Send> Gn<A> {
+    /// create a scoped generator with default stack size that remembers
+    /// its closure, so it can be run again by `restart`
+    pub fn new_restartable<'a, T, F>(f: F) -> Generator<'a, A, T>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Clone + Send + 'a,
+        T: Send + 'a,
+        A: 'a,
+    {
+        Self::new_restartable_opt(default_stack_words(), f)
+    }
+
+    /// create a scoped generator with specified stack size that remembers
+    /// its closure, so it can be run again by `restart`
+    pub fn new_restartable_opt<'a, T, F>(size: usize, f: F) -> Generator<'a, A, T>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Clone + Send + 'a,
+        T: Send + 'a,
+        A: 'a,
+    {
+        let mut gen = Self::new_scoped_opt(size, f.clone());
+        gen.gen.template = Some(Box::new(f));
+        gen
+    }
+}
+
+// the closure remembered by a restartable generator
+trait Template<'a, A, T> {
+    // init the generator with a copy of the closure
+    fn init(&self, gen: &mut GeneratorImpl<'a, A, T>);
+    // remember a copy of the closure for a cloned generator
+    fn clone_box(&self) -> Box<dyn Template<'a, A, T> + 'a>;
+}
+
+impl<'a, A, T, F> Template<'a, A, T> for F
+where
+    for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Clone + 'a,
+    A: 'a,
+    T: 'a,
+{
+    fn init(&self, gen: &mut GeneratorImpl<'a, A, T>) {
+        gen.scoped_init(self.clone());
+    }
+
+    fn clone_box(&self) -> Box<dyn Template<'a, A, T> + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+impl<A> Gn<A> {
+    /// create a stackless generator driven by the state machine `f`
+    ///
+    /// each resume calls `f` with the send para on the resumer stack, there
+    /// is no context switch and the generator is placed on the heap without
+    /// a system stack, so it's much cheaper for a simple generator that
+    /// doesn't need to suspend in the middle of a call. There is no
+    /// generator context inside `f`, it can't use the scope or the yield
+    /// functions, and it can't be re-initialized with a closure
+    pub fn new_stackless<'a, T, F>(f: F) -> Generator<'a, A, T>
+    where
+        F: FnMut(Option<A>) -> Resume<T> + Send + 'a,
+        T: Send + 'a,
+        A: Send + 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(GeneratorImpl::<A, T>::heap_stack());
+        gen.stackless_init(f);
         Generator { gen }
     }
+
+    /// create a stackless local generator driven by the state machine `f`
+    pub fn new_stackless_local<'a, T, F>(f: F) -> LocalGenerator<'a, A, T>
+    where
+        F: FnMut(Option<A>) -> Resume<T> + 'a,
+        T: 'a,
+        A: 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(GeneratorImpl::<A, T>::heap_stack());
+        gen.stackless_init(f);
+        LocalGenerator { gen }
+    }
+}
+
+#[cold]
+fn stack_error(e: StackError) -> ! {
+    panic!("failed to create the generator: {e}")
+}
+
+// the callback registered by `watch_stack`
+struct StackWatch<'a> {
+    // the number of used words that fires the callback
+    limit: usize,
+    f: Box<dyn FnOnce(usize, usize) + Send + 'a>,
+}
+
+// the state machine of a stackless generator
+type StepFn<'a, A, T> = dyn FnMut(Option<A>) -> Resume<T> + 'a;
+
+/// the scoped closure of a `Send` generator
+pub type ScopedFn<'a, A, T> = dyn for<'scope> FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a;
+
+// move the closure out of the functor on the stack
+type TakeFn<'a, A, T> = fn(Func) -> Inner<'a, A, T>;
+
+// the closure on the stack with the return slot
+struct GenEntry<F, T> {
+    f: F,
+    ret: *mut Option<T>,
+}
+
+impl<F: FnOnce() -> T, T> Entry for GenEntry<F, T> {
+    fn run(self) {
+        let r = (self.f)();
+        unsafe { *self.ret = Some(r) };
+    }
+}
+
+// the scoped closure on the stack, kept apart from its scope so that it can
+// be taken back
+struct ScopedEntry<'a, F, A, T> {
+    f: F,
+    scope: Scope<'a, 'a, A, T>,
+    ret: *mut Option<T>,
+}
+
+impl<'a, F, A, T> Entry for ScopedEntry<'a, F, A, T>
+where
+    for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T,
+{
+    fn run(self) {
+        let r = (self.f)(self.scope);
+        unsafe { *self.ret = Some(r) };
+    }
 }
 
 /// `GeneratorImpl`
+// the context goes first, then the fields every resume touches, the cold
+// ones are at the end
 #[repr(C)]
 struct GeneratorImpl<'a, A, T> {
     // run time context
-    context: Context,
-    // stack
-    stack: Stack,
+    context: Context,
     // save the input
     para: Option<A>,
     // save the output
     ret: Option<T>,
+    // the thread root context cached by a local generator, null if Send
+    root: *mut Context,
+    // any optional switch feature is on for the generator, the stackless
+    // state machine, the stack watch, the switch hook or the vector state,
+    // see `update_extras`
+    extras: bool,
+    // it's attached to a generator or in a group, a resume checks if it's
+    // cancelled by them
+    linked: bool,
+    // the number of resumes and yields of the current run
+    resumes: usize,
+    yields: usize,
+    // the state machine of a stackless generator, it's called on the
+    // resumer stack instead of switching to the generator stack
+    stackless: Option<Box<StepFn<'a, A, T>>>,
+    // the stack usage callback
+    stack_watch: Option<StackWatch<'a>>,
+    // the hook called on each switch
+    switch_hook: Option<Box<dyn FnMut(usize, Switch) + Send + 'a>>,
+    // the thread allowed to resume the generator, any thread if none
+    #[cfg(checks)]
+    owner: Option<thread::ThreadId>,
+    // the preserved extended vector state
+    #[cfg(target_arch = "x86_64")]
+    xstate: Option<crate::detail::XState>,
+    // the fp control words of the generator, inherited on the first resume
+    #[cfg(fp_control)]
+    fp_control: Option<crate::detail::FpControl>,
+    // point to the resumer registers for unwinding
+    #[cfg(unwind_frames)]
+    unwind_slot: *mut usize,
+    // the frame record of the resumer that the first generator frame links to
+    #[cfg(frame_chain)]
+    frame_record: [usize; 2],
+    // the output left in `ret` by peek or resume_ref
+    buffered: Buffered,
+    // the caller provided output slot of `resume_into`, null if not provided
+    out: *mut T,
+    // stack
+    stack: Stack,
     // boxed functor
     f: Option<Func>,
+    // take the closure out of `f`, only set for a not scoped closure
+    inner: Option<TakeFn<'a, A, T>>,
+    // the free and the high end of the scratch space for `Scope::alloc`
+    scratch: (usize, usize),
+    // the closure remembered by a restartable generator
+    template: Option<Box<dyn Template<'a, A, T> + 'a>>,
+    // the remaining items hint for the iterator
+    size_hint: (usize, Option<usize>),
+    // the cancel token of the generator it's attached to
+    parent_cancel: Option<Arc<AtomicBool>>,
+    // the cancel policy for the frames of `foreign_call`
+    foreign_cancel: ForeignCancel,
+    // the frames are abandoned by `ForeignCancel::Leak`, the stack is kept
+    leaked: bool,
     // phantom lifetime
     phantom: PhantomData<&'a T>,
 }
 
 impl<A: Any, T: Any> GeneratorImpl<'_, A, T> {
-    /// create a new generator with default stack size
+    /// point the context to the typed para and ret slots for the free
+    /// yield functions, the types are only checked on access
     fn init_context(&mut self) {
-        unsafe {
-            std::ptr::write(
-                self.context.para.as_mut_ptr(),
-                &mut self.para as &mut dyn Any,
-            );
-            std::ptr::write(self.context.ret.as_mut_ptr(), &mut self.ret as &mut dyn Any);
-        }
+        self.context.para = &mut self.para as *mut Option<A> as *mut ();
+        self.context.ret = &mut self.ret as *mut Option<T> as *mut ();
+        self.context.slot_types = Some([TypeId::of::<A>(), TypeId::of::<T>()]);
     }
 }
 
 impl<'a, A, T> GeneratorImpl<'a, A, T> {
+    // allocate a stack of `size` words that can hold the generator and the closure `F`
+    fn alloc_stack<F>(size: usize) -> Result<Stack, StackError> {
+        Self::alloc_stack_with::<F>(size, StackConfig::default())
+    }
+
+    // allocate a stack like `alloc_stack` with the options
+    fn alloc_stack_with<F>(size: usize, config: StackConfig) -> Result<Stack, StackError> {
+        use std::mem::{align_of, size_of};
+        // the closure is wrapped with the return slot and the scope
+        let reserve = size_of::<Self>()
+            + align_of::<Self>()
+            + size_of::<F>()
+            + align_of::<F>()
+            + 4 * size_of::<usize>();
+        Stack::try_new(size, reserve, config)
+    }
+
+    // allocate a heap buffer that only holds the generator, there is no
+    // system stack for a stackless generator
+    fn heap_stack() -> Stack {
+        use std::mem::{align_of, size_of};
+        // the generator and the entry marking it as not started
+        let reserve = size_of::<Self>() + align_of::<Self>() + 4 * size_of::<usize>();
+        Stack::new_heap(reserve, align_of::<Self>())
+    }
+
     /// create a new generator with specified stack size
     fn new(mut stack: Stack) -> StackBox<Self> {
         // the stack box would finally dealloc the stack!
@@ -308,17 +1358,74 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
                 stack,
                 ret: None,
                 f: None,
+                inner: None,
+                scratch: (0, 0),
+                template: None,
+                stackless: None,
+                stack_watch: None,
+                switch_hook: None,
+                size_hint: (0, None),
+                extras: false,
+                linked: false,
+                resumes: 0,
+                yields: 0,
+                buffered: Buffered::Empty,
+                out: std::ptr::null_mut(),
+                root: std::ptr::null_mut(),
+                parent_cancel: None,
+                foreign_cancel: ForeignCancel::Abort,
+                leaked: false,
+                #[cfg(checks)]
+                owner: None,
+                #[cfg(target_arch = "x86_64")]
+                xstate: None,
+                #[cfg(fp_control)]
+                fp_control: None,
+                #[cfg(unwind_frames)]
+                unwind_slot: std::ptr::null_mut(),
+                #[cfg(frame_chain)]
+                frame_record: [0; 2],
                 context: Context::new(),
                 phantom: PhantomData,
             });
-            stack_box.assume_init()
+            let mut stack_box = stack_box.assume_init();
+            stack_box.inherit();
+            if let Some(hooks) = runtime_hooks() {
+                let id = stack_box.as_ptr() as usize;
+                hooks.on_stack_acquire(id, stack_box.stack.size());
+                hooks.on_spawn(id);
+            }
+            #[cfg(feature = "dump")]
+            crate::dump::register(stack_box.as_ptr() as usize, Self::dump_info);
+            stack_box
+        }
+    }
+
+    // read the generator for the dump, it's registered until dropped
+    #[cfg(feature = "dump")]
+    unsafe fn dump_info(gen: *const ()) -> crate::dump::GenInfo {
+        let gen = &*(gen as *const Self);
+        let (ip, sp, frames) = match gen.suspended_stack() {
+            Some(view) => (view.ip(), view.sp(), view.frames() as *const [u8]),
+            None => (0, 0, &[][..] as *const [u8]),
+        };
+        crate::dump::GenInfo {
+            state: gen.state(),
+            ip,
+            sp,
+            frames,
         }
     }
 
     /// prefetch the generator into cache
     #[inline]
     pub fn prefetch(&self) {
+        // the saved registers and the top of the generator stack
         self.context.regs.prefetch();
+        // the hot context fields and the exchange slots
+        crate::detail::prefetch(&self.context);
+        crate::detail::prefetch(&self.para);
+        crate::detail::prefetch(&self.ret);
     }
 
     /// init a heap based generator with scoped closure
@@ -328,9 +1435,66 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
         T: 'a,
         A: 'a,
     {
-        use std::mem::transmute;
-        let scope: Scope<A, T> = unsafe { transmute(Scope::new(&mut self.para, &mut self.ret)) };
-        self.init_code(move || f(scope));
+        // the scope is only moved into the closure, see `Scope::new`
+        let scope = unsafe {
+            Scope::new(
+                &mut self.para,
+                &mut self.ret,
+                &mut self.out,
+                &mut self.scratch,
+            )
+        };
+        let ret = &mut self.ret as *mut _;
+        self.init_entry(ScopedEntry { f, scope, ret });
+    }
+
+    /// init a heap based generator whose scoped closure can be taken back by
+    /// `into_inner` before it's started
+    fn init_scoped<F>(&mut self, f: F)
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
+        T: 'a,
+        A: 'a,
+    {
+        self.scoped_init(f);
+        self.inner = Some(Self::take_scoped::<F>);
+    }
+
+    // move the closure of `init_scoped` out of the entry
+    fn take_scoped<F>(func: Func) -> Inner<'a, A, T>
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'a, A, T>) -> T + Send + 'a,
+    {
+        // the entry is created by `scoped_init` with the same closure type
+        let entry = unsafe { func.take::<ScopedEntry<F, A, T>>() };
+        Inner::Scoped(Box::new(entry.f))
+    }
+
+    /// init a heap based generator whose closure can be taken back by
+    /// `into_inner` before it's started
+    fn init_plain<F: FnOnce() -> T + Send + 'a>(&mut self, f: F)
+    where
+        T: 'a,
+    {
+        self.init_code(f);
+        self.inner = Some(Self::take_plain::<F>);
+    }
+
+    // move the closure of `init_plain` out of the entry
+    fn take_plain<F: FnOnce() -> T + Send + 'a>(func: Func) -> Inner<'a, A, T> {
+        // the entry is created by `init_code` with the same closure type
+        let entry = unsafe { func.take::<GenEntry<F, T>>() };
+        Inner::Plain(Box::new(entry.f))
+    }
+
+    // take the closure of a not started generator
+    fn take_inner(&mut self) -> Option<Inner<'a, A, T>> {
+        let take = self.inner?;
+        let func = self.f.take()?;
+        self.inner = None;
+        // nothing would run on the stack, mark it as done
+        self.context._ref = 1;
+        Some(take(func))
     }
 
     /// init a heap based generator
@@ -339,6 +1503,12 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
     where
         T: 'a,
     {
+        let ret = &mut self.ret as *mut _;
+        self.init_entry(GenEntry { f, ret });
+    }
+
+    // put the entry on the stack and make it ready to start
+    fn init_entry<E: Entry + 'a>(&mut self, entry: E) {
         // make sure the last one is finished
         if self.f.is_none() && self.context._ref == 0 {
             self.cancel();
@@ -346,36 +1516,383 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
             let _ = self.f.take();
         }
 
+        assert!(
+            !self.leaked,
+            "the stack of the generator is leaked by a cancel in `foreign_call`"
+        );
+        assert!(
+            self.stack.config().heap_align == 0,
+            "a stackless generator has no stack to run the closure"
+        );
+        // reuse the stack, the frames and the closure of the last run are gone
+        unsafe { self.stack.release_after(self as *const Self) };
+        // a normal finish leaves nothing of the optional features behind
+        if unlikely(self.extras || self.run_state_left()) {
+            self.reset_run_state();
+        }
+
         // init ctx parent to itself, this would be the new top
         self.context.parent = &mut self.context;
 
         // init the ref to 0 means that it's ready to start
         self.context._ref = 0;
-        let ret = &mut self.ret as *mut _;
+        self.size_hint = (0, None);
+        self.resumes = 0;
+        self.yields = 0;
+        self.buffered = Buffered::Empty;
+        self.out = std::ptr::null_mut();
+        #[cfg(fp_control)]
+        {
+            self.fp_control = None;
+        }
+        self.ret = None;
+        self.scratch = self.stack.alloc_scratch();
         // alloc the function on stack
-        let func = StackBox::new_fn_once(&mut self.stack, move || {
-            let r = f();
-            unsafe { *ret = Some(r) };
-        });
+        let func = StackBox::new_entry(&mut self.stack, entry);
 
         self.f = Some(func);
+        self.inner = None;
 
         let guard = (self.stack.begin() as usize, self.stack.end() as usize);
         self.context.stack_guard = guard;
+        self.context.guard_size = self.stack.guard_size();
         self.context.regs.init_with(
             gen_init,
             0,
             &mut self.f as *mut _ as *mut usize,
             &self.stack,
         );
+        #[cfg(unwind_frames)]
+        {
+            self.unwind_slot = crate::detail::unwind_slot(&self.stack);
+        }
+        #[cfg(frame_chain)]
+        crate::detail::link_frame(&mut self.context.regs.regs, self.frame_record.as_ptr());
+        self.check_invariants("init");
+    }
+
+    // the state of the last run that `init_code` has to clear
+    #[inline]
+    fn run_state_left(&self) -> bool {
+        let cx = &self.context;
+        cx.err.is_some()
+            || cx.thrown.is_some()
+            || cx.foreign != 0
+            || !cx.locals.is_empty()
+            || cx.cancel_token.is_some()
+    }
+
+    #[cold]
+    fn reset_run_state(&mut self) {
+        self.context.err = None;
+        self.context.thrown = None;
+        self.context.foreign = 0;
+        self.context.locals.clear();
+        self.context.cancel_token = None;
+        self.stackless = None;
+        self.stack_watch = None;
+        self.update_extras();
+    }
+
+    /// init a stackless generator, its heap buffer only holds the generator
+    fn stackless_init<F>(&mut self, f: F)
+    where
+        F: FnMut(Option<A>) -> Resume<T> + 'a,
+        T: 'a,
+    {
+        // the closure is never called, it marks the generator as not started
+        let ret = &mut self.ret as *mut _;
+        let f_mark = || -> T { unreachable!("stackless generator entered its stack") };
+        self.f = Some(StackBox::new_entry(
+            &mut self.stack,
+            GenEntry { f: f_mark, ret },
+        ));
+        self.stackless = Some(Box::new(f));
+        self.extras = true;
+
+        self.context.parent = &mut self.context;
+        self.context._ref = 0;
+        self.context.stack_guard = (self.stack.begin() as usize, self.stack.end() as usize);
+        self.check_invariants("init");
+    }
+
+    // run one step of the stackless generator in place of a context switch
+    fn resume_stackless(&mut self) {
+        // consume the not started mark
+        self.f = None;
+        self.resumes += 1;
+        self.call_switch_hooks(Switch::Resume);
+        // cancelled, no frames to unwind, just drop the state machine
+        if self.context._ref != 1 {
+            self.stackless = None;
+            self.call_switch_hooks(Switch::Done);
+            return;
+        }
+        let step = self
+            .stackless
+            .as_mut()
+            .expect("stackless generator is done");
+        // a panic finishes the generator, the `_ref` is left as 1
+        match step(self.para.take()) {
+            Resume::Yield(v) => {
+                self.ret = Some(v);
+                self.context._ref -= 1;
+                self.yields += 1;
+            }
+            Resume::Return(v) => {
+                self.ret = Some(v);
+                self.stackless = None;
+            }
+            Resume::Done => self.stackless = None,
+        }
+        let switch = if self.context._ref == 0 {
+            Switch::Yield
+        } else {
+            Switch::Done
+        };
+        self.check_invariants(if switch == Switch::Yield {
+            "yield"
+        } else {
+            "done"
+        });
+        self.call_switch_hooks(switch);
+    }
+
+    // validate the internal state around a switch and panic with what's
+    // broken, `at` names the check point
+    #[cfg(feature = "debug-checks")]
+    #[cold]
+    #[inline(never)]
+    fn check_invariants(&self, at: &str) {
+        let id = self as *const Self as usize;
+        let fail = |msg: String| -> ! { panic!("generator {id:#x}: {at}: {msg}") };
+        let (begin, end) = (self.stack.begin() as usize, self.stack.end() as usize);
+
+        // the closure below the generator is consumed after the start
+        let guard = self.context.stack_guard;
+        if guard.0 != begin || guard.1 > end {
+            fail(format!(
+                "stack guard {guard:x?} is out of the stack {begin:#x}..{end:#x}"
+            ));
+        }
+        // only a running generator is linked into the context stack
+        if !std::ptr::eq(self.context.parent, &self.context) && self.context.local_data.is_null() {
+            if at == "resume" {
+                fail("resumed while it's running, a re-entrant resume".into());
+            }
+            fail("the context is still linked into the context stack".into());
+        }
+        // the low end of the stack is only reached by an overflow, the one
+        // caught in the guard region is reported by the resumer
+        let fault = matches!(&self.context.err, Some(e) if e.is::<GuardFault>());
+        if !fault && !self.stack.canary_intact() {
+            fail(format!("the stack canary at {begin:#x} is overwritten"));
+        }
+
+        let r = self.context._ref;
+        match at {
+            "resume" => {
+                // 1 to run, 2 to cancel
+                if r != 1 && r != 2 {
+                    fail(format!("`_ref` is {r} before the switch, expected 1 or 2"));
+                }
+                if r == 1 && self.ret.is_some() && self.buffered == Buffered::Empty {
+                    fail("the output of the last resume is not taken".into());
+                }
+            }
+            _ => {
+                // 0 yielded, 1 returned or panicked, 2 cancelled
+                if r > 2 {
+                    fail(format!("`_ref` is {r}, expected 0, 1 or 2"));
+                }
+                if r == 0 && self.stackless.is_none() && !self.is_started() && at != "init" {
+                    fail("suspended without consuming the closure".into());
+                }
+            }
+        }
+
+        if at == "yield" && self.stackless.is_none() {
+            let sp = self.context.regs.regs.sp();
+            if sp < begin || sp > guard.1 {
+                fail(format!(
+                    "the saved sp {sp:#x} is out of the stack guard {guard:x?}"
+                ));
+            }
+        }
+    }
+
+    #[cfg(not(feature = "debug-checks"))]
+    #[inline(always)]
+    fn check_invariants(&self, _at: &str) {}
+
+    // panic with the stack boxes that are left besides the generator and
+    // its not started closure, they are leaked by a missed drop path, it's
+    // only logged while the thread is already panicking
+    #[cfg(feature = "debug-checks")]
+    #[cold]
+    #[inline(never)]
+    fn check_leaks(&self) {
+        let id = self as *const Self as usize;
+        let func = self.f.as_ref().map_or(0, Func::addr);
+        let leaked: Vec<_> = self
+            .stack
+            .live_boxes()
+            .into_iter()
+            .filter(|b| b.addr != id && b.addr != func && b.type_name != SCRATCH_NAME)
+            .map(|b| b.to_string())
+            .collect();
+        if leaked.is_empty() {
+            return;
+        }
+        let msg = format!(
+            "generator {id:#x}: leaked {} stack allocation(s): {}",
+            leaked.len(),
+            leaked.join(", ")
+        );
+        if thread::panicking() {
+            error!("{msg}");
+        } else {
+            panic!("{msg}");
+        }
+    }
+
+    #[cfg(not(feature = "debug-checks"))]
+    #[inline(always)]
+    fn check_leaks(&self) {}
+
+    // call the process wide, the runtime and the generator hooks if any
+    #[inline]
+    fn call_switch_hooks(&mut self, switch: Switch) {
+        let global = global_hook();
+        let runtime = runtime_hooks();
+        if unlikely(global.is_some() || runtime.is_some() || self.switch_hook.is_some()) {
+            let id = self as *mut Self as usize;
+            if let Some(f) = global {
+                f(id, switch);
+            }
+            match (runtime, switch) {
+                (Some(hooks), Switch::Yield) => hooks.on_yield(id),
+                (Some(hooks), Switch::Done) => hooks.on_done(id),
+                _ => {}
+            }
+            if let Some(f) = self.switch_hook.as_mut() {
+                f(id, switch);
+            }
+        }
+    }
+
+    // a local generator never leaves the creating thread
+    fn bind_local(&mut self) {
+        self.root = ContextStack::current().root;
+        #[cfg(checks)]
+        {
+            self.owner = Some(thread::current().id());
+        }
+    }
+
+    // panic if the generator is resumed on a thread it's not bound to
+    #[cfg(checks)]
+    #[inline]
+    fn check_thread(&self) {
+        #[cold]
+        fn fail(id: usize, owner: thread::ThreadId) -> ! {
+            let cur = thread::current();
+            panic!(
+                "generator {id:#x} is bound to thread {owner:?} but resumed on thread '{}' \
+                 {:?}, see `allow_any_thread`",
+                cur.name().unwrap_or("<unnamed>"),
+                cur.id()
+            );
+        }
+        if let Some(owner) = self.owner {
+            if owner != thread::current().id() {
+                fail(self as *const Self as usize, owner);
+            }
+        }
     }
 
     /// resume the generator
     #[inline]
     fn resume_gen(&mut self) {
-        let env = ContextStack::current();
+        #[cfg(checks)]
+        self.check_thread();
+        self.check_invariants("resume");
+        // all the optional features are behind one test
+        if unlikely(cfg!(fp_control) || self.extras || switch_extras()) {
+            return self.resume_gen_extras();
+        }
+        let env = self.env();
+        self.swap_in(&env);
+        self.propagate_err();
+    }
+
+    // resume the generator with the optional features of the switch, the
+    // stackless step, the nesting limit, the hooks, the vector and fp state
+    // and the stack watch
+    #[cold]
+    #[inline(never)]
+    fn resume_gen_extras(&mut self) {
+        if self.stackless.is_some() {
+            return self.resume_stackless();
+        }
+        let env = self.env();
+        // the cancel goes one level deeper anyway to unwind the frames
+        let max = max_nesting_depth();
+        if unlikely(max != 0 && env.top().depth >= max && self.context._ref != 2) {
+            self.nesting_panic(max);
+        }
+        self.call_switch_hooks(Switch::Resume);
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(xstate) = self.xstate.as_mut() {
+            xstate.switch_in();
+        }
+
+        #[cfg(fp_control)]
+        let fp_control = crate::detail::FpControl::save();
+        #[cfg(fp_control)]
+        if let Some(c) = self.fp_control {
+            c.load();
+        }
+
+        let switch = self.swap_in(&env);
+
+        #[cfg(fp_control)]
+        {
+            self.fp_control = Some(crate::detail::FpControl::save());
+            fp_control.load();
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(xstate) = self.xstate.as_mut() {
+            xstate.switch_out();
+        }
+
+        self.call_switch_hooks(switch);
+        if self.stack_watch.is_some() {
+            self.check_stack_watch();
+        }
+        self.propagate_err();
+    }
+
+    // the context stack of the resumer
+    #[inline]
+    fn env(&self) -> ContextStack {
+        // skip the thread local lookup for a local generator
+        if self.root.is_null() {
+            ContextStack::current()
+        } else {
+            ContextStack { root: self.root }
+        }
+    }
+
+    // switch to the generator until it yields or returns
+    #[inline]
+    fn swap_in(&mut self, env: &ContextStack) -> Switch {
         // get the current regs
         let cur = &mut env.top().regs;
+        #[cfg(feature = "debug-checks")]
+        let resumer = env.top() as *const Context;
 
         // switch to new context, always use the top context's reg
         // for normal generator self.context.parent == self.context
@@ -386,22 +1903,102 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
         // save current generator context on stack
         env.push_context(&mut self.context);
 
+        // the generator frames unwind into where the current regs are saved
+        #[cfg(unwind_frames)]
+        unsafe {
+            *self.unwind_slot = cur as *mut RegContext as usize;
+        }
+        // and the frame pointer chain walks into the current function
+        #[cfg(frame_chain)]
+        {
+            self.frame_record = crate::detail::current_frame();
+        }
+
         // swap to the generator
+        self.resumes += 1;
         RegContext::swap(cur, &top.regs);
+        // suspended by a yield
+        let switch = if self.context._ref == 0 {
+            self.yields += 1;
+            Switch::Yield
+        } else {
+            Switch::Done
+        };
+
+        #[cfg(feature = "debug-checks")]
+        if !std::ptr::eq(env.top(), resumer) {
+            let id = self as *const Self as usize;
+            panic!("generator {id:#x}: {switch:?}: the context stack is not back to the resumer");
+        }
+        self.check_invariants(if switch == Switch::Yield {
+            "yield"
+        } else {
+            "done"
+        });
+        // the frames abandoned by a stack overflow can't drop anything
+        if switch == Switch::Done && self.context.err.is_none() {
+            self.check_leaks();
+        }
+        switch
+    }
 
-        // comes back, check the panic status
+    // comes back, check the panic status
+    #[inline]
+    fn propagate_err(&mut self) {
         // this would propagate the panic until root context
         // if it's a coroutine just stop propagate
-        if !self.context.local_data.is_null() {
-            return;
+        #[cfg(not(panic = "abort"))]
+        {
+            if !self.context.local_data.is_null() {
+                return;
+            }
+
+            if let Some(err) = self.context.err.take() {
+                if err.is::<GuardFault>() {
+                    self.overflow_panic();
+                }
+                // pass the error to the parent until root
+                panic::resume_unwind(err);
+            }
         }
 
-        if let Some(err) = self.context.err.take() {
-            // pass the error to the parent until root
-            panic::resume_unwind(err);
+        // report the overflow before the abort
+        #[cfg(panic = "abort")]
+        if self
+            .context
+            .err
+            .as_ref()
+            .is_some_and(|e| e.is::<GuardFault>())
+        {
+            self.overflow_panic();
         }
     }
 
+    // raise the fault caught in the guard region as a panic with the
+    // overflow message, like the one of a thread
+    #[cold]
+    fn overflow_panic(&self) -> ! {
+        let size = self.stack.size() * std::mem::size_of::<usize>();
+        panic!("{}", overflow_message(self.context.name, size));
+    }
+
+    // refuse the resume over the max nesting depth, the generator is left
+    // as it is
+    #[cold]
+    #[inline(never)]
+    fn nesting_panic(&mut self, max: usize) -> ! {
+        self.context._ref -= 1;
+        let id = self as *const Self as usize;
+        let name = match self.context.name {
+            Some(name) => format!("'{}'", name.to_string_lossy()),
+            None => format!("{id:#x}"),
+        };
+        panic!(
+            "resuming generator {name} would nest {} generators, over the max nesting depth {max}",
+            max + 1
+        )
+    }
+
     #[inline]
     fn is_started(&self) -> bool {
         // when the f is consumed we think it's running
@@ -436,7 +2033,13 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
     /// you should call `set_para` before this method
     #[inline]
     fn resume(&mut self) -> Option<T> {
-        if unlikely(self.is_done()) {
+        if unlikely(self.buffered != Buffered::Empty) {
+            if let Some(ret) = self.take_buffered() {
+                return ret;
+            }
+        }
+
+        if unlikely(self.is_done() || self.cancel_pending()) {
             return None;
         }
 
@@ -451,7 +2054,14 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
     /// `raw_send`
     #[inline]
     fn raw_send(&mut self, para: Option<A>) -> Option<T> {
-        if unlikely(self.is_done()) {
+        if unlikely(self.buffered != Buffered::Empty) {
+            if let Some(ret) = self.take_buffered() {
+                self.para = para;
+                return ret;
+            }
+        }
+
+        if unlikely(self.is_done() || self.cancel_pending()) {
             return None;
         }
 
@@ -467,23 +2077,209 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
         self.ret.take()
     }
 
+    /// resume the generator and leave the output in `ret`
+    fn resume_ref(&mut self) -> Option<&mut T> {
+        match self.buffered {
+            Buffered::Peeked => {
+                self.buffered = Buffered::Lent;
+                return self.ret.as_mut();
+            }
+            Buffered::Lent => self.ret = None,
+            Buffered::Empty => {}
+        }
+
+        if unlikely(self.is_done() || self.cancel_pending()) {
+            self.buffered = Buffered::Empty;
+            return None;
+        }
+
+        self.context._ref += 1;
+        self.resume_gen();
+        self.buffered = Buffered::Lent;
+        self.ret.as_mut()
+    }
+
+    // take the output buffered by peek, or drop the one left by resume_ref
+    #[cold]
+    fn take_buffered(&mut self) -> Option<Option<T>> {
+        match std::mem::replace(&mut self.buffered, Buffered::Empty) {
+            Buffered::Peeked => Some(self.ret.take()),
+            _ => {
+                self.ret = None;
+                None
+            }
+        }
+    }
+
+    /// resume the generator and let the scope yield into `out`
+    fn resume_into(&mut self, out: &mut MaybeUninit<T>) -> bool {
+        if unlikely(self.buffered != Buffered::Empty || self.is_done() || self.cancel_pending()) {
+            return match self.resume() {
+                Some(v) => {
+                    out.write(v);
+                    true
+                }
+                None => false,
+            };
+        }
+
+        self.out = out.as_mut_ptr();
+        self.context._ref += 1;
+        self.resume_gen();
+        // the scope clears the slot pointer after writing it
+        let written = self.out.is_null();
+        self.out = std::ptr::null_mut();
+        if written {
+            return true;
+        }
+        // the returned value or the value yielded by the `Any` API
+        match self.ret.take() {
+            Some(v) => {
+                out.write(v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// resume the generator and keep the output in `ret`
+    fn peek(&mut self) -> Option<&T> {
+        if self.buffered != Buffered::Peeked {
+            if self.buffered == Buffered::Lent {
+                self.buffered = Buffered::Empty;
+                self.ret = None;
+            }
+            if self.is_done() || self.cancel_pending() {
+                return None;
+            }
+            self.context._ref += 1;
+            self.resume_gen();
+            self.buffered = Buffered::Peeked;
+        }
+        self.ret.as_ref()
+    }
+
     /// send interface
     fn send(&mut self, para: A) -> T {
         let ret = self.raw_send(Some(para));
         ret.expect("send got None return")
     }
 
+    // attach to the running generator if any and join its group
+    fn inherit(&mut self) {
+        let parent = ContextStack::current().top();
+        if parent.is_generator() {
+            let token = parent.cancel_token.get_or_insert_with(Default::default);
+            self.parent_cancel = Some(token.clone());
+            self.context.group = parent.group.clone();
+            self.update_extras();
+        }
+    }
+
+    // cancel the generator instead of resuming it once the generator it's
+    // attached to is cancelled or its group is expired, see `attach` and
+    // `join_group`
+    #[inline]
+    fn cancel_pending(&mut self) -> bool {
+        if unlikely(self.linked && (self.creator_cancelled() || self.group_expired())) {
+            self.cancel();
+            return true;
+        }
+        false
+    }
+
+    #[inline]
+    fn creator_cancelled(&self) -> bool {
+        match &self.parent_cancel {
+            Some(token) => token.load(Ordering::Acquire),
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn group_expired(&self) -> bool {
+        match &self.context.group {
+            Some(group) => group.is_expired(),
+            None => false,
+        }
+    }
+
     /// cancel the generator without any check
     #[inline]
     fn raw_cancel(&mut self) {
+        // the generators attached to it are cancelled at their next resume
+        if let Some(token) = &self.context.cancel_token {
+            token.store(true, Ordering::Release);
+        }
         // tell the func to panic
         // so that we can stop the inner func
         self.context._ref = 2;
-        // save the old panic hook, we don't want to print anything for the Cancel
-        let old = panic::take_hook();
-        panic::set_hook(Box::new(|_| {}));
+        // without unwinding, the suspended frames are just abandoned
+        // and their destructors never run
+        #[cfg(not(panic = "abort"))]
+        {
+            if self.context.foreign != 0 && !self.cancel_foreign() {
+                return;
+            }
+            // the hook is already quiet in the unwinding of an outer cancel
+            if thread::panicking() {
+                return self.resume_gen();
+            }
+            // save the old panic hook, we don't want to print anything for the Cancel
+            let old = panic::take_hook();
+            panic::set_hook(Box::new(|_| {}));
+            self.resume_gen();
+            panic::set_hook(old);
+        }
+    }
+
+    // apply the policy to the cancel of a generator suspended in the foreign
+    // frames, returns true if it's resumed to unwind anyway
+    #[cfg(not(panic = "abort"))]
+    #[cold]
+    fn cancel_foreign(&mut self) -> bool {
+        match self.foreign_cancel {
+            ForeignCancel::Abort => {
+                let id = self as *const Self as usize;
+                eprintln!(
+                    "generator {id:#x} is cancelled inside `foreign_call`, \
+                     its foreign frames can't be unwound, aborting"
+                );
+                std::process::abort();
+            }
+            ForeignCancel::Leak => {
+                self.leaked = true;
+                // the generator is the first box of the stack
+                unsafe { StackBox::leak_stack(self as *mut Self) };
+                false
+            }
+            ForeignCancel::Defer => true,
+        }
+    }
+
+    /// resume the generator with the payload raised at the pending yield
+    fn throw(&mut self, e: Box<dyn Any + Send>) -> Option<T> {
+        if unlikely(self.buffered != Buffered::Empty) {
+            self.buffered = Buffered::Empty;
+            self.ret = None;
+        }
+
+        if self.is_done() {
+            return None;
+        }
+
+        // there is no yield to raise it
+        if !self.is_started() || self.stackless.is_some() {
+            self.cancel();
+            panic::resume_unwind(e);
+        }
+
+        self.context.thrown = Some(e);
+        self.context._ref += 1;
         self.resume_gen();
-        panic::set_hook(old);
+        // not taken by a yield, e.g. the generator is done
+        self.context.thrown = None;
+        self.ret.take()
     }
 
     /// cancel the generator
@@ -502,6 +2298,62 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
         }
     }
 
+    // re-init with the remembered closure
+    fn restart(&mut self) -> bool {
+        let template = match self.template.take() {
+            Some(template) => template,
+            None => return false,
+        };
+        template.init(self);
+        self.template = Some(template);
+        true
+    }
+
+    // create a not started generator with the same closure on a new stack
+    fn try_clone(&self) -> Option<StackBox<Self>> {
+        if self.is_started() {
+            return None;
+        }
+        let template = self.template.as_ref()?;
+        let stack = Stack::try_new(self.stack.request_size(), 0, self.stack.config())
+            .unwrap_or_else(|e| stack_error(e));
+        let mut gen = Self::new(stack);
+        gen.root = self.root;
+        #[cfg(checks)]
+        {
+            gen.owner = self.owner;
+        }
+        gen.size_hint = self.size_hint;
+        #[cfg(target_arch = "x86_64")]
+        if self.xstate.is_some() {
+            gen.xstate = crate::detail::XState::new();
+        }
+        template.init(&mut gen);
+        gen.template = Some(template.clone_box());
+        Some(gen)
+    }
+
+    // a suspended generator can't be reset without cancelling it
+    fn check_reset(&self) -> Result<(), ResetError> {
+        if self.is_started() && !self.is_done() {
+            return Err(ResetError::Suspended);
+        }
+        Ok(())
+    }
+
+    // a running generator is linked in the thread context list
+    fn state(&self) -> State {
+        if !self.is_started() {
+            State::Fresh
+        } else if self.context.is_generator() {
+            State::Running
+        } else if self.is_done() {
+            State::Done
+        } else {
+            State::Suspended
+        }
+    }
+
     /// is finished
     #[inline]
     fn is_done(&self) -> bool {
@@ -512,12 +2364,103 @@ impl<'a, A, T> GeneratorImpl<'a, A, T> {
     fn stack_usage(&self) -> (usize, usize) {
         (self.stack.size(), self.stack.get_used_size())
     }
+
+    fn suspended_stack(&self) -> Option<SuspendedStack<'_>> {
+        if self.state() != State::Suspended || self.stackless.is_some() {
+            return None;
+        }
+        let regs = &self.context.regs.regs;
+        let sp = regs.sp();
+        let end = self.stack.end() as usize;
+        debug_assert!(self.stack.begin() as usize <= sp && sp <= end);
+        // the frames are not touched while the generator is suspended
+        let frames = unsafe { std::slice::from_raw_parts(sp as *const u8, end - sp) };
+        Some(SuspendedStack {
+            ip: regs.ip(),
+            sp,
+            frames,
+        })
+    }
+
+    fn watch_stack<F>(&mut self, percent: u8, f: F)
+    where
+        F: FnOnce(usize, usize) + Send + 'a,
+    {
+        assert!(
+            !self.is_started(),
+            "the stack can only be watched before the generator starts"
+        );
+        assert!(percent <= 100, "invalid stack usage percent {percent}");
+        self.stack.fill_footprint();
+        let limit = self.stack.size() * usize::from(percent) / 100;
+        self.stack_watch = Some(StackWatch {
+            limit,
+            f: Box::new(f),
+        });
+        self.update_extras();
+    }
+
+    // the features that need the slow path of the resume
+    fn update_extras(&mut self) {
+        let mut extras =
+            self.stackless.is_some() || self.stack_watch.is_some() || self.switch_hook.is_some();
+        #[cfg(target_arch = "x86_64")]
+        {
+            extras |= self.xstate.is_some();
+        }
+        self.extras = extras;
+        self.linked = self.parent_cancel.is_some() || self.context.group.is_some();
+    }
+
+    // fire the stack watch if the usage reaches the limit
+    #[cold]
+    fn check_stack_watch(&mut self) {
+        let (total, used) = self.stack_usage();
+        match self.stack_watch.take() {
+            Some(watch) if used >= watch.limit => (watch.f)(total, used),
+            watch => self.stack_watch = watch,
+        }
+    }
+}
+
+// the running generator is unwound by its cancel
+fn cancel_unwinding() -> bool {
+    let current = ContextStack::current().top();
+    current.is_generator() && current.is_cancelled()
+}
+
+// calls `on_stack_release` when dropped, unless the stack is leaked
+struct StackRelease {
+    hooks: &'static dyn crate::hook::RuntimeHooks,
+    gen: *const (),
+    size: usize,
+    leaked: *const bool,
+}
+
+impl Drop for StackRelease {
+    fn drop(&mut self) {
+        // the flag lives in the generator which is still in place
+        if !unsafe { *self.leaked } {
+            self.hooks.on_stack_release(self.gen as usize, self.size);
+        }
+    }
 }
 
 impl<A, T> Drop for GeneratorImpl<'_, A, T> {
     fn drop(&mut self) {
-        // when the thread is already panic, do nothing
-        if thread::panicking() {
+        #[cfg(feature = "dump")]
+        crate::dump::unregister(self as *mut Self as usize);
+        // tell the release on any way out, the stack is freed right after
+        let _release = runtime_hooks().map(|hooks| StackRelease {
+            hooks,
+            gen: self as *const Self as *const (),
+            size: self.stack.size(),
+            leaked: &self.leaked,
+        });
+
+        // when the thread is already panic, do nothing, but the cancel of
+        // the running generator still cancels the ones in its frames
+        if thread::panicking() && !cancel_unwinding() {
             return;
         }
 
@@ -539,8 +2482,42 @@ impl<A, T> Drop for GeneratorImpl<'_, A, T> {
             // next time will just use
             // set_stack_size::<F>(used_stack);
         } else {
-            error!("stack overflow detected!");
+            error!(
+                "stack overflow detected! {used_stack} of {total_stack} words are used, \
+                 the guard is {} bytes",
+                self.stack.guard_size()
+            );
             panic::panic_any(Error::StackErr);
         }
+        // the abandoned frames still own the closure
+        if !self.leaked {
+            self.check_leaks();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    #[cfg(feature = "debug-checks")]
+    fn test_leak_report() {
+        use super::Gn;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let mut g = Gn::<()>::new_scoped(|mut s| {
+            s.yield_(1);
+            2
+        });
+        assert_eq!(g.by_ref().sum::<i32>(), 3);
+        // nothing else is left on the stack of a done generator
+        let mut b = unsafe { g.gen.stack.alloc_uninit_box::<[u32; 4]>().assume_init() };
+        unsafe { b.init([7; 4]) };
+        std::mem::forget(b);
+        let e = catch_unwind(AssertUnwindSafe(|| drop(g))).unwrap_err();
+        let msg = e.downcast_ref::<String>().unwrap();
+        assert!(
+            msg.contains("leaked 1 stack allocation(s): `[u32; 4]`"),
+            "{msg}"
+        );
     }
 }
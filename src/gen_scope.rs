@@ -0,0 +1,122 @@
+//! # generator scope
+//!
+//! create generators that can borrow from the enclosing frame
+//!
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+use crate::gen_impl::{Generator, Gn, LocalGenerator};
+use crate::scope::Scope;
+
+// type erased drop function of an owned generator
+type DropFn = unsafe fn(*mut ());
+
+// type erased owned generators, dropped in the reverse order of creation
+#[derive(Default)]
+struct Gens(Vec<(*mut (), DropFn)>);
+
+unsafe fn drop_box<G>(gen: *mut ()) {
+    drop(Box::from_raw(gen as *mut G));
+}
+
+impl Drop for Gens {
+    fn drop(&mut self) {
+        for (gen, drop) in self.0.drain(..).rev() {
+            unsafe { drop(gen) };
+        }
+    }
+}
+
+/// A scope to create generators that borrow non-`'static` data.
+///
+/// The scope owns all the generators created by it and only hands out
+/// `&mut` references, so they can't escape or be leaked. When the scope
+/// ends, all the generators that are not done yet are cancelled, before any
+/// of the borrowed data goes out of scope.
+pub struct GenScope<'scope, 'env: 'scope> {
+    gens: RefCell<Gens>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope> GenScope<'scope, '_> {
+    // each call hands out a distinct box, so the `&mut` never aliases
+    #[allow(clippy::mut_from_ref)]
+    fn own<G: 'scope>(&'scope self, gen: G) -> &'scope mut G {
+        let gen = Box::into_raw(Box::new(gen));
+        self.gens
+            .borrow_mut()
+            .0
+            .push((gen as *mut (), drop_box::<G>));
+        // the box is only freed when the scope is dropped
+        unsafe { &mut *gen }
+    }
+
+    /// create a scoped generator with default stack size that lives in this scope
+    pub fn new_scoped<A, T, F>(&'scope self, f: F) -> &'scope mut Generator<'scope, A, T>
+    where
+        for<'s> F: FnOnce(Scope<'s, 'scope, A, T>) -> T + Send + 'scope,
+        T: Send + 'scope,
+        A: Send + 'scope,
+    {
+        self.own(Gn::new_scoped(f))
+    }
+
+    /// create a scoped generator with specified stack size that lives in this scope
+    pub fn new_scoped_opt<A, T, F>(
+        &'scope self,
+        size: usize,
+        f: F,
+    ) -> &'scope mut Generator<'scope, A, T>
+    where
+        for<'s> F: FnOnce(Scope<'s, 'scope, A, T>) -> T + Send + 'scope,
+        T: Send + 'scope,
+        A: Send + 'scope,
+    {
+        self.own(Gn::new_scoped_opt(size, f))
+    }
+
+    /// create a scoped local generator with default stack size that lives in this scope
+    pub fn new_scoped_local<A, T, F>(&'scope self, f: F) -> &'scope mut LocalGenerator<'scope, A, T>
+    where
+        F: FnOnce(Scope<A, T>) -> T + 'scope,
+        T: 'scope,
+        A: 'scope,
+    {
+        self.own(Gn::new_scoped_local(f))
+    }
+}
+
+/// Create a scope for generators that borrow from the enclosing frame.
+///
+/// Like `std::thread::scope`, all the generators created through the
+/// [`GenScope`] are finished or cancelled before this function returns.
+/// Inside the generators, use `yield_with` and `get_yield` to exchange data.
+///
+/// ```
+/// let mut data = vec![1, 2, 3];
+/// let sum = generator::scope(|s| {
+///     let g = s.new_scoped(|mut co| {
+///         for v in data.iter_mut() {
+///             *v *= 2;
+///             co.yield_with(*v);
+///         }
+///         0
+///     });
+///     g.sum::<i32>()
+/// });
+/// assert_eq!(sum, 12);
+/// assert_eq!(data, [2, 4, 6]);
+/// ```
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: for<'scope> FnOnce(&'scope GenScope<'scope, 'env>) -> R,
+{
+    let scope = GenScope {
+        gens: RefCell::default(),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    f(&scope)
+}
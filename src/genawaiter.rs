@@ -0,0 +1,209 @@
+//! # genawaiter compatible API
+//!
+//! A shim that mirrors the `genawaiter` API on top of stackful generators,
+//! so migrating is mostly an import change:
+//!
+//! ```
+//! use generator::genawaiter::{Gen, GeneratorState};
+//!
+//! let mut gen = Gen::new(|co| async move {
+//!     let mut sum = 0;
+//!     for i in 1..=3 {
+//!         sum += co.yield_(i).await;
+//!     }
+//!     sum
+//! });
+//!
+//! assert_eq!(gen.resume_with(0), GeneratorState::Yielded(1));
+//! assert_eq!(gen.resume_with(10), GeneratorState::Yielded(2));
+//! assert_eq!(gen.resume_with(20), GeneratorState::Yielded(3));
+//! assert_eq!(gen.resume_with(30), GeneratorState::Complete(60));
+//! ```
+//!
+//! Behavioral differences from `genawaiter`:
+//!
+//! - the producer runs on its own stack, a yield suspends the whole stack, so
+//!   `yield_!` and `Co::yield_` work from any nested (even non-async) function
+//! - the producer future may only await `Co::yield_`, awaiting any other
+//!   pending future panics since there is no executor to wake it up
+//! - yielded, resume and completion types must be `'static`
+//! - `yield_!` only supports the `()` resume type
+//! - resuming a completed generator panics
+//! - `generator!` is an alias of `gen!` for the 2024 edition crates, where
+//!   `gen` is a reserved keyword
+
+use std::any::Any;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::gen_impl::{default_stack_words, Generator, Gn};
+
+/// The result of resuming a generator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GeneratorState<Y, C> {
+    /// the generator suspended with a value
+    Yielded(Y),
+    /// the generator completed with a return value
+    Complete(C),
+}
+
+/// The yield handle passed to the producer
+pub struct Co<Y, R = ()> {
+    phantom: PhantomData<fn(Y) -> R>,
+}
+
+impl<Y: Any, R: Any> Co<Y, R> {
+    /// yield a value, the returned future resolves to the resume arg
+    pub fn yield_(&self, value: Y) -> impl Future<Output = R> + '_ {
+        let mut value = Some(value);
+        std::future::poll_fn(move |_| match value.take() {
+            Some(v) => Poll::Ready(__yield(v)),
+            None => unreachable!("yield future polled after ready"),
+        })
+    }
+}
+
+/// don't use it directly, use yield_!() macro instead
+#[doc(hidden)]
+#[inline]
+pub fn __yield<Y: Any, R: Any>(v: Y) -> R {
+    crate::yield_::yield_(v).expect("generator resumed without arg")
+}
+
+/// yield a value from the body of the `gen!` macro
+///
+/// only for generators that resumed with `()`, use `Co::yield_` to get
+/// other kinds of resume arg
+#[macro_export]
+macro_rules! yield_ {
+    ($value:expr) => {
+        $crate::genawaiter::__yield::<_, ()>($value)
+    };
+}
+
+/// create a `Gen` from the body, use `yield_!()` to yield inside it
+///
+/// ```
+/// use generator::{gen, yield_};
+///
+/// let gen = gen!({
+///     yield_!(1);
+///     yield_!(2);
+/// });
+/// assert_eq!(gen.collect::<Vec<i32>>(), [1, 2]);
+/// ```
+#[macro_export]
+macro_rules! gen {
+    ($body:block) => {
+        $crate::genawaiter::Gen::new(|_co| async move $body)
+    };
+}
+
+/// the same as `gen!`, `gen` is a reserved keyword in the 2024 edition
+#[macro_export]
+macro_rules! generator {
+    ($body:block) => {
+        $crate::gen!($body)
+    };
+}
+
+pub(crate) fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+    const RAW: RawWaker = RawWaker::new(std::ptr::null(), &VTABLE);
+    unsafe { Waker::from_raw(RAW) }
+}
+
+// the returned value is saved in a heap slot that outlives the generator
+struct RetSlot<C>(*mut Option<C>);
+unsafe impl<C: Send> Send for RetSlot<C> {}
+
+/// A generator created from an async producer
+pub struct Gen<Y: 'static, R: 'static = (), C = ()> {
+    gen: Generator<'static, R, Y>,
+    // the slot written by the producer, freed after the generator
+    ret: RetSlot<C>,
+}
+
+impl<Y: 'static, R: 'static, C> Drop for Gen<Y, R, C> {
+    fn drop(&mut self) {
+        // cancel the producer before its slot is gone
+        self.gen.cancel();
+        drop(unsafe { Box::from_raw(self.ret.0) });
+    }
+}
+
+impl<Y, R, C> Gen<Y, R, C>
+where
+    Y: Any + Send,
+    R: Any + Send,
+    C: Send + 'static,
+{
+    /// create a generator from the producer
+    pub fn new<P, F>(producer: P) -> Self
+    where
+        P: FnOnce(Co<Y, R>) -> F + Send + 'static,
+        F: Future<Output = C>,
+    {
+        let ret = RetSlot(Box::into_raw(Box::new(None)));
+        let slot = RetSlot(ret.0);
+        let gen = Gn::<R>::new_opt(default_stack_words(), move || {
+            let slot = slot;
+            let co = Co {
+                phantom: PhantomData,
+            };
+            let mut fut = pin!(producer(co));
+            let waker = noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            let r = match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => panic!("only `Co::yield_` can be awaited in the producer"),
+            };
+            unsafe { *slot.0 = Some(r) };
+            crate::yield_::done()
+        });
+        Gen { gen, ret }
+    }
+
+    /// resume the generator with the arg, the first arg is discarded
+    pub fn resume_with(&mut self, arg: R) -> GeneratorState<Y, C> {
+        assert!(!self.gen.is_done(), "generator resumed after completion");
+        match self.gen.raw_send(Some(arg)) {
+            Some(v) => GeneratorState::Yielded(v),
+            None => {
+                let ret = unsafe { (*self.ret.0).take() };
+                GeneratorState::Complete(ret.expect("producer has no return"))
+            }
+        }
+    }
+}
+
+impl<Y, C> Gen<Y, (), C>
+where
+    Y: Any + Send,
+    C: Send + 'static,
+{
+    /// resume the generator
+    pub fn resume(&mut self) -> GeneratorState<Y, C> {
+        self.resume_with(())
+    }
+}
+
+impl<Y, C> Iterator for Gen<Y, (), C>
+where
+    Y: Any + Send,
+    C: Send + 'static,
+{
+    type Item = Y;
+
+    fn next(&mut self) -> Option<Y> {
+        if self.gen.is_done() {
+            return None;
+        }
+        match self.resume() {
+            GeneratorState::Yielded(v) => Some(v),
+            GeneratorState::Complete(_) => None,
+        }
+    }
+}
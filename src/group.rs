@@ -0,0 +1,84 @@
+//! # generator group
+//!
+//! the deadline shared by a set of generators
+//!
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::sync::Arc;
+
+/// a set of generators sharing a deadline, e.g. the coroutines serving a
+/// request
+///
+/// the members joined by `GeneratorObj::join_group`, and the generators
+/// created inside them, are cancelled at their next resume once the group
+/// expires, `GeneratorObj::try_resume` tells it by
+/// `ResumeError::DeadlineExceeded`
+#[derive(Clone, Default)]
+pub struct GeneratorGroup {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    deadline: Option<Instant>,
+    // latched by `expire` or by the first check after the deadline
+    expired: AtomicBool,
+}
+
+impl GeneratorGroup {
+    /// create a group without a deadline, it only expires by `expire`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// create a group that expires at the deadline
+    pub fn with_deadline(deadline: Instant) -> Self {
+        GeneratorGroup {
+            inner: Arc::new(Inner {
+                deadline: Some(deadline),
+                expired: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// create a group that expires after the timeout from now
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    /// get the deadline of the group
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// expire the group now
+    pub fn expire(&self) {
+        self.inner.expired.store(true, Ordering::Release);
+    }
+
+    /// check if the group is expired
+    pub fn is_expired(&self) -> bool {
+        if self.inner.expired.load(Ordering::Acquire) {
+            return true;
+        }
+        match self.inner.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.expire();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for GeneratorGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GeneratorGroup")
+            .field("deadline", &self.inner.deadline)
+            .field("expired", &self.inner.expired.load(Ordering::Relaxed))
+            .finish()
+    }
+}
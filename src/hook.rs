@@ -0,0 +1,100 @@
+//! # switch hooks
+//!
+//! observe the generator context switches and life cycle
+//!
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// the context switch event passed to the hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    /// the generator is about to be resumed
+    Resume,
+    /// the generator yielded back to the resumer
+    Yield,
+    /// the generator finished, cancelled, or panicked back to the resumer
+    Done,
+}
+
+/// the hook called with the generator id and the switch event
+pub type SwitchHook = fn(usize, Switch);
+
+// the process wide hook, 0 means not set
+static GLOBAL_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+// set once a process wide switch feature is used, the hooks or the nesting
+// limit, it's never cleared and the slow switch path copes with them off
+static SWITCH_EXTRAS: AtomicBool = AtomicBool::new(false);
+
+// route the switches of all generators to the slow path
+pub(crate) fn enable_switch_extras() {
+    SWITCH_EXTRAS.store(true, Ordering::Relaxed);
+}
+
+// any process wide switch feature is used
+#[inline]
+pub(crate) fn switch_extras() -> bool {
+    SWITCH_EXTRAS.load(Ordering::Relaxed)
+}
+
+/// set the process wide hook called on each switch of every generator
+///
+/// the hook runs on the resumer side of the switch, it should be cheap and
+/// must not resume the switching generator. `None` removes the hook
+pub fn set_switch_hook(hook: Option<SwitchHook>) {
+    if hook.is_some() {
+        enable_switch_extras();
+    }
+    GLOBAL_HOOK.store(hook.map_or(0, |f| f as usize), Ordering::Release);
+}
+
+// get the process wide hook
+#[inline]
+pub(crate) fn global_hook() -> Option<SwitchHook> {
+    match GLOBAL_HOOK.load(Ordering::Acquire) {
+        0 => None,
+        // only set from a `SwitchHook` by `set_switch_hook`
+        f => Some(unsafe { std::mem::transmute::<usize, SwitchHook>(f) }),
+    }
+}
+
+/// the callbacks for a coroutine runtime to follow the generators, each
+/// gets the generator id, the same one passed to the switch hooks
+///
+/// they are registered once per process by `set_runtime_hooks`, run on the
+/// thread doing the operation, and should be cheap. all do nothing by default
+pub trait RuntimeHooks: Sync {
+    /// the generator is created
+    fn on_spawn(&self, _id: usize) {}
+
+    /// the generator yielded back to the resumer
+    fn on_yield(&self, _id: usize) {}
+
+    /// the generator finished, cancelled, or panicked back to the resumer
+    fn on_done(&self, _id: usize) {}
+
+    /// the stack of `size` words is taken for the generator, before `on_spawn`
+    fn on_stack_acquire(&self, _id: usize, _size: usize) {}
+
+    /// the stack of `size` words is given back when the generator is dropped
+    fn on_stack_release(&self, _id: usize, _size: usize) {}
+}
+
+static RUNTIME_HOOKS: OnceLock<&'static dyn RuntimeHooks> = OnceLock::new();
+
+/// register the runtime hooks for the process, only the first call takes
+/// effect, the later ones get their hooks back
+pub fn set_runtime_hooks(
+    hooks: &'static dyn RuntimeHooks,
+) -> Result<(), &'static dyn RuntimeHooks> {
+    RUNTIME_HOOKS.set(hooks)?;
+    enable_switch_extras();
+    Ok(())
+}
+
+// get the registered runtime hooks
+#[inline]
+pub(crate) fn runtime_hooks() -> Option<&'static dyn RuntimeHooks> {
+    RUNTIME_HOOKS.get().copied()
+}
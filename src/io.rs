@@ -0,0 +1,112 @@
+//! # io adapters
+//!
+//! use byte generators as readers and writers
+//!
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::gen_impl::GeneratorObj;
+
+impl<'a, const LOCAL: bool> GeneratorObj<'a, (), Vec<u8>, LOCAL> {
+    /// turn the generator of byte chunks into a buffered reader
+    ///
+    /// the chunks are read in order, the reader hits the end when the
+    /// generator is done
+    pub fn into_reader(self) -> GenReader<'a, LOCAL> {
+        GenReader {
+            gen: self,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+/// A `Read`/`BufRead` of the bytes yielded by a generator, created by
+/// `into_reader`
+pub struct GenReader<'a, const LOCAL: bool> {
+    gen: GeneratorObj<'a, (), Vec<u8>, LOCAL>,
+    // the current chunk and the read position in it
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a, const LOCAL: bool> GenReader<'a, LOCAL> {
+    /// get the generator back, the unread part of the current chunk is lost
+    pub fn into_inner(self) -> GeneratorObj<'a, (), Vec<u8>, LOCAL> {
+        self.gen
+    }
+}
+
+impl<const LOCAL: bool> BufRead for GenReader<'_, LOCAL> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // skip the empty chunks
+        while self.pos == self.buf.len() {
+            match self.gen.resume() {
+                Some(chunk) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                None => break,
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = usize::min(self.pos + amt, self.buf.len());
+    }
+}
+
+impl<const LOCAL: bool> Read for GenReader<'_, LOCAL> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, const LOCAL: bool> GeneratorObj<'a, Vec<u8>, usize, LOCAL> {
+    /// turn the generator consuming byte chunks into a writer
+    ///
+    /// each write sends a copy of the buffer as the para and the generator
+    /// yields how many bytes of it are consumed, a `None` para tells the
+    /// generator that the writer is finished, see `GenWriter::finish`
+    pub fn into_writer(self) -> GenWriter<'a, LOCAL> {
+        GenWriter { gen: self }
+    }
+}
+
+/// A `Write` that feeds a generator by `send`, created by `into_writer`
+pub struct GenWriter<'a, const LOCAL: bool> {
+    gen: GeneratorObj<'a, Vec<u8>, usize, LOCAL>,
+}
+
+impl<const LOCAL: bool> GenWriter<'_, LOCAL> {
+    /// resume the generator without para to finish it and get its return
+    /// value
+    pub fn finish(mut self) -> Option<usize> {
+        if self.gen.is_done() {
+            return None;
+        }
+        let ret = self.gen.raw_send(None);
+        // the generator may still yield, don't leave it suspended
+        self.gen.cancel();
+        ret
+    }
+}
+
+impl<const LOCAL: bool> Write for GenWriter<'_, LOCAL> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.gen.is_done() {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        match self.gen.raw_send(Some(buf.to_vec())) {
+            Some(n) => Ok(usize::min(n, buf.len())),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! # lending generator
+//!
+//! yield references into the generator owned state
+//!
+
+use std::fmt;
+use std::ptr::NonNull;
+
+use crate::gen_impl::GeneratorObj;
+use crate::scope::Scope;
+
+/// The output type of a lending generator
+///
+/// It's an opaque reference to the data yielded by `Scope::yield_ref`, which
+/// is only accessible through `resume_lend`/`send_lend`, so the borrow can't
+/// outlive the next resume of the generator.
+pub struct Lend<U: ?Sized> {
+    ptr: NonNull<U>,
+}
+
+// behaves like `&U`
+unsafe impl<U: ?Sized + Sync> Send for Lend<U> {}
+unsafe impl<U: ?Sized + Sync> Sync for Lend<U> {}
+
+impl<U: ?Sized> fmt::Debug for Lend<U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Lend<{}> {{ ... }}", std::any::type_name::<U>())
+    }
+}
+
+/// the lending generator type, yield `&U` that valid until the next resume
+///
+/// the `Lend<U>` can't be constructed by user code, so the generator body
+/// should finish with `done!()`
+///
+/// ```
+/// use generator::{done, Gn};
+///
+/// let mut g = Gn::<()>::new_scoped(|mut s| {
+///     let mut buf = [0u8; 4];
+///     for i in 0..3 {
+///         buf.fill(i);
+///         s.yield_ref(&buf[..]);
+///     }
+///     done!();
+/// });
+///
+/// while let Some(buf) = g.resume_lend() {
+///     assert!(buf.iter().all(|&b| b == buf[0]));
+/// }
+/// ```
+pub type LendingGenerator<'a, A, U> = crate::Generator<'a, A, Lend<U>>;
+
+impl<A, U: ?Sized> Scope<'_, '_, A, Lend<U>> {
+    /// yield a reference into the generator owned state and get the send para
+    ///
+    /// the reference is valid until the generator is resumed again, because
+    /// the generator is suspended inside this call while the caller holds it
+    #[inline]
+    pub fn yield_ref(&mut self, v: &U) -> Option<A> {
+        self.yield_with(Lend {
+            ptr: NonNull::from(v),
+        });
+        self.get_yield()
+    }
+}
+
+impl<A, U: ?Sized, const LOCAL: bool> GeneratorObj<'_, A, Lend<U>, LOCAL> {
+    /// resume the lending generator and borrow the yielded reference
+    ///
+    /// the returned reference borrows the generator, so it must be released
+    /// before the generator can be resumed again
+    #[inline]
+    pub fn resume_lend(&mut self) -> Option<&U> {
+        // the generator is suspended in `yield_ref` until the next resume
+        self.resume().map(|v| unsafe { &*v.ptr.as_ptr() })
+    }
+
+    /// send the para to the lending generator and borrow the yielded reference
+    #[inline]
+    pub fn send_lend(&mut self, para: A) -> Option<&U> {
+        self.raw_send(Some(para))
+            .map(|v| unsafe { &*v.ptr.as_ptr() })
+    }
+}
+
+/// An iterator that lends its items, each item borrows the iterator itself
+///
+/// this mirrors the `LendingIterator` trait used by lending iteration
+/// libraries, so lending generators can be driven by generic code.
+/// Since generators also implement `Iterator`, call the methods in the
+/// fully qualified form, e.g. `LendingIterator::next(&mut g)`
+pub trait LendingIterator {
+    /// The type of the items being lent
+    type Item<'b>
+    where
+        Self: 'b;
+
+    /// advance the iterator and lend the next item
+    fn next(&mut self) -> Option<Self::Item<'_>>;
+
+    /// call the closure on each lent item
+    fn for_each<F>(mut self, mut f: F)
+    where
+        Self: Sized,
+        F: FnMut(Self::Item<'_>),
+    {
+        while let Some(item) = self.next() {
+            f(item);
+        }
+    }
+}
+
+impl<U: ?Sized, const LOCAL: bool> LendingIterator for GeneratorObj<'_, (), Lend<U>, LOCAL> {
+    type Item<'b>
+        = &'b U
+    where
+        Self: 'b;
+
+    #[inline]
+    fn next(&mut self) -> Option<&U> {
+        self.resume_lend()
+    }
+}
@@ -3,23 +3,79 @@
 //! Rust generator library
 //!
 
+#![cfg_attr(feature = "nightly", feature(coroutine_trait))]
 #![deny(warnings, missing_docs)]
 #![allow(deprecated)]
 
 #[macro_use]
 extern crate log;
 
+mod batch;
+mod block_on;
+mod combinator;
+#[cfg(feature = "nightly")]
+mod coroutine;
 mod detail;
+#[cfg(feature = "deterministic")]
+pub mod deterministic;
+#[cfg(feature = "dump")]
+pub mod dump;
+mod executor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 mod gen_impl;
+mod gen_scope;
+pub mod genawaiter;
+mod group;
+mod hook;
+mod io;
+mod lending;
+mod local;
+mod object;
+mod pipeline;
+#[cfg(all(feature = "reactor", unix))]
+pub mod reactor;
 mod reg_context;
 mod rt;
 mod scope;
+pub mod session;
+#[cfg(feature = "spawn")]
+mod spawn;
 mod stack;
+#[cfg(feature = "stream")]
+pub mod stream;
+mod sync;
+mod timer;
 mod yield_;
 
-pub use crate::gen_impl::{Generator, Gn, LocalGenerator, DEFAULT_STACK_SIZE};
-pub use crate::rt::{get_local_data, is_generator, Error};
-pub use crate::scope::Scope;
+pub use crate::combinator::SendIter;
+pub use crate::executor::Executor;
+pub use crate::gen_impl::{
+    default_stack_size, set_default_stack_size, Builder, Generator, Gn, Inner, LocalGenerator,
+    ResetError, Resume, ResumeError, ScopedFn, State, SuspendedStack, DEFAULT_STACK_SIZE,
+};
+pub use crate::gen_scope::{scope, GenScope};
+pub use crate::group::GeneratorGroup;
+pub use crate::hook::{set_runtime_hooks, set_switch_hook, RuntimeHooks, Switch, SwitchHook};
+pub use crate::io::{GenReader, GenWriter};
+pub use crate::lending::{Lend, LendingGenerator, LendingIterator};
+pub use crate::local::GeneratorLocalKey;
+pub use crate::object::GeneratorTrait;
+pub use crate::pipeline::{ParPipeline, Pipeline, Stage};
+pub use crate::rt::{
+    current_id, current_name, foreign_call, get_local_data, is_cancel_payload, is_generator,
+    max_nesting_depth, nesting_depth, parent_id, parent_name, set_max_nesting_depth, Error,
+    ForeignCancel,
+};
+pub use crate::scope::{Scope, StackRef};
+#[cfg(feature = "spawn")]
+pub use crate::spawn::Spawned;
+pub use crate::stack::{
+    default_guard_size, pool, region, set_default_guard_size, Stack, StackError,
+};
 pub use crate::yield_::{
-    co_get_yield, co_set_para, co_yield_with, done, get_yield, yield_, yield_from, yield_with,
+    co_get_yield, co_set_para, co_yield_with, done, get_yield, try_get_yield, try_yield_,
+    try_yield_with, yield_, yield_from, yield_with, NotInGenerator,
 };
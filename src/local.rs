@@ -0,0 +1,112 @@
+//! # generator local storage
+//!
+//! the per generator slots declared by `generator_local!`
+//!
+
+use std::any::Any;
+use std::fmt;
+
+use crate::rt::ContextStack;
+use crate::yield_::NotInGenerator;
+
+/// declare the generator local keys, it's like `thread_local!` for the
+/// generator running on the current thread
+///
+/// each generator gets its own value, initialized on the first access and
+/// dropped with the generator or when it's re-initialized
+///
+/// ```
+/// use generator::{generator_local, Gn};
+/// use std::cell::Cell;
+///
+/// generator_local! {
+///     static COUNT: Cell<u32> = Cell::new(0);
+/// }
+///
+/// let mut g = Gn::<()>::new_scoped(|mut s| {
+///     COUNT.with(|c| c.set(c.get() + 1));
+///     s.yield_(COUNT.with(Cell::get));
+///     COUNT.with(|c| c.set(c.get() + 1));
+///     COUNT.with(Cell::get)
+/// });
+/// assert_eq!(g.next(), Some(1));
+/// assert_eq!(g.next(), Some(2));
+/// ```
+#[macro_export]
+macro_rules! generator_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::generator_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::generator_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::GeneratorLocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::GeneratorLocalKey::new(__init)
+        };
+    };
+}
+
+/// the key of a generator local value, see `generator_local!`
+pub struct GeneratorLocalKey<T: 'static> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> GeneratorLocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        GeneratorLocalKey { init }
+    }
+
+    /// access the value of the running generator
+    ///
+    /// panics outside of a generator, see `try_with`
+    #[track_caller]
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        match self.try_with(f) {
+            Ok(r) => r,
+            Err(_) => panic!("the generator local is accessed outside of a generator"),
+        }
+    }
+
+    /// access the value of the running generator, `Err(NotInGenerator)`
+    /// outside of a generator
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, NotInGenerator> {
+        let context = ContextStack::current().top() as *mut crate::rt::Context;
+        if !unsafe { (*context).is_generator() } {
+            return Err(NotInGenerator);
+        }
+        // the key is a static, its address is unique
+        let key = self as *const Self as usize;
+        let find = || unsafe {
+            (*context)
+                .locals
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| &**v as *const (dyn Any + Send))
+        };
+        let value = match find() {
+            Some(v) => v,
+            None => {
+                // the init may access the other keys, no borrow is held
+                let v: Box<dyn Any + Send> = Box::new((self.init)());
+                let p = &*v as *const (dyn Any + Send);
+                unsafe { (*context).locals.push((key, v)) };
+                p
+            }
+        };
+        // the boxed value stays at its place until the generator is done
+        // with it, the slots may be moved by the other keys
+        let value = unsafe { &*value }.downcast_ref::<T>().unwrap();
+        Ok(f(value))
+    }
+}
+
+impl<T: 'static> fmt::Debug for GeneratorLocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GeneratorLocalKey").finish_non_exhaustive()
+    }
+}
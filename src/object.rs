@@ -0,0 +1,84 @@
+//! # object safe generator
+//!
+//! drive generators of different closures and lifetimes uniformly
+//!
+
+use crate::gen_impl::GeneratorObj;
+
+/// The object safe interface of the generators
+///
+/// it allows to keep generators created from different closures, lifetimes
+/// and locality in one collection, e.g.
+/// `Vec<Box<dyn GeneratorTrait<In = A, Out = T> + 'a>>`
+///
+/// ```
+/// use generator::{Gn, GeneratorTrait};
+///
+/// let mut gens: Vec<Box<dyn GeneratorTrait<In = (), Out = u32>>> = vec![
+///     Box::new(Gn::<()>::new_scoped(|mut s| {
+///         s.yield_(1);
+///         2
+///     })),
+///     Box::new(Gn::<()>::new_scoped_local(|_| 3)),
+/// ];
+///
+/// let mut out = vec![];
+/// while !gens.is_empty() {
+///     gens.retain_mut(|g| {
+///         out.extend(g.resume());
+///         !g.is_done()
+///     });
+/// }
+/// assert_eq!(out, [1, 3, 2]);
+/// ```
+pub trait GeneratorTrait {
+    /// the send para type
+    type In;
+    /// the output type
+    type Out;
+
+    /// resume the generator without para
+    fn resume(&mut self) -> Option<Self::Out>;
+
+    /// resume the generator with an optional para
+    fn raw_send(&mut self, para: Option<Self::In>) -> Option<Self::Out>;
+
+    /// send the para and get the output, panics if there is no output
+    fn send(&mut self, para: Self::In) -> Self::Out;
+
+    /// is finished
+    fn is_done(&self) -> bool;
+
+    /// cancel the generator, see `GeneratorObj::cancel`
+    fn cancel(&mut self);
+}
+
+impl<A, T, const LOCAL: bool> GeneratorTrait for GeneratorObj<'_, A, T, LOCAL> {
+    type In = A;
+    type Out = T;
+
+    #[inline]
+    fn resume(&mut self) -> Option<T> {
+        GeneratorObj::resume(self)
+    }
+
+    #[inline]
+    fn raw_send(&mut self, para: Option<A>) -> Option<T> {
+        GeneratorObj::raw_send(self, para)
+    }
+
+    #[inline]
+    fn send(&mut self, para: A) -> T {
+        GeneratorObj::send(self, para)
+    }
+
+    #[inline]
+    fn is_done(&self) -> bool {
+        GeneratorObj::is_done(self)
+    }
+
+    #[inline]
+    fn cancel(&mut self) {
+        GeneratorObj::cancel(self)
+    }
+}
@@ -0,0 +1,551 @@
+//! # pipeline
+//!
+//! chain the generators with the bounded buffers between them, on the
+//! current thread or on a worker thread per stage
+//!
+
+use std::any::Any;
+use std::cell::{RefCell, UnsafeCell};
+use std::collections::VecDeque;
+use std::mem::{self, MaybeUninit};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::task::Poll;
+use std::thread::{self, JoinHandle, Thread};
+
+use crate::gen_impl::{Generator, Gn, LocalGenerator};
+use crate::scope::Scope;
+use crate::sync::{Arc, Mutex};
+use crate::yield_::done;
+
+// where a stage takes its input from
+trait Source<T> {
+    // the next item, `None` once it's finished
+    fn pull(&mut self) -> Option<T>;
+}
+
+impl<T> Source<T> for Generator<'_, (), T> {
+    fn pull(&mut self) -> Option<T> {
+        self.resume()
+    }
+}
+
+// the input buffer of a stage
+trait Inlet<T> {
+    // the next item, `Poll::Ready(None)` once the upstream is finished
+    fn poll_recv(&mut self) -> Poll<Option<T>>;
+}
+
+// the buffer between two stages
+struct Buffer<T> {
+    items: VecDeque<T>,
+    // the upstream is finished
+    closed: bool,
+}
+
+impl<T> Inlet<T> for Rc<RefCell<Buffer<T>>> {
+    fn poll_recv(&mut self) -> Poll<Option<T>> {
+        let mut buf = self.borrow_mut();
+        match buf.items.pop_front() {
+            Some(v) => Poll::Ready(Some(v)),
+            None if buf.closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+// a stage with its input buffer and the upstream filling it
+struct Link<'a, T, U> {
+    upstream: Box<dyn Source<T> + 'a>,
+    buf: Rc<RefCell<Buffer<T>>>,
+    cap: usize,
+    // yields `Poll::Pending` when it waits for the input
+    gen: LocalGenerator<'a, (), Poll<U>>,
+}
+
+impl<T, U> Source<U> for Link<'_, T, U> {
+    fn pull(&mut self) -> Option<U> {
+        loop {
+            match self.gen.resume()? {
+                Poll::Ready(v) => return Some(v),
+                Poll::Pending => {
+                    // the upstream is only resumed while there is room
+                    let mut buf = self.buf.borrow_mut();
+                    while buf.items.len() < self.cap && !buf.closed {
+                        match self.upstream.pull() {
+                            Some(v) => buf.items.push_back(v),
+                            None => buf.closed = true,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the input and the output of a pipeline stage, see `Pipeline::then` and
+/// `ParPipeline::then`
+pub struct Stage<'s, 'a, T, U> {
+    scope: Scope<'s, 'a, (), Poll<U>>,
+    input: Box<dyn Inlet<T> + 'a>,
+}
+
+impl<T, U> Stage<'_, '_, T, U> {
+    /// take the next input, the stage is suspended while the buffer is
+    /// empty, `None` once the upstream is finished
+    pub fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.input.poll_recv() {
+                Poll::Ready(v) => return v,
+                Poll::Pending => self.scope.yield_with(Poll::Pending),
+            }
+        }
+    }
+
+    /// pass the output downstream, the stage is suspended until the
+    /// downstream has room for the next one
+    pub fn send(&mut self, v: U) {
+        self.scope.yield_with(Poll::Ready(v));
+    }
+}
+
+/// the generators chained by the bounded buffers, from a producer through
+/// the transformer stages, the outputs of the last one are iterated
+///
+/// a stage is only resumed while the buffer after it has room, so a slow
+/// consumer holds back the whole pipeline instead of buffering its input
+///
+/// ```
+/// use generator::{Gn, Pipeline};
+///
+/// let producer = Gn::<()>::new_scoped(|mut s| {
+///     for i in 0..5 {
+///         s.yield_(i);
+///     }
+///     generator::done()
+/// });
+/// let odd = Pipeline::new(producer)
+///     .then(2, |mut stage| {
+///         while let Some(v) = stage.recv() {
+///             stage.send(v * 2 + 1);
+///         }
+///     })
+///     .then(2, |mut stage| {
+///         while let Some(v) = stage.recv() {
+///             stage.send(v.to_string());
+///         }
+///     });
+/// assert_eq!(odd.collect::<Vec<_>>(), ["1", "3", "5", "7", "9"]);
+/// ```
+pub struct Pipeline<'a, T> {
+    last: Box<dyn Source<T> + 'a>,
+}
+
+impl<'a, T: 'a> Pipeline<'a, T> {
+    /// start the pipeline from the producer
+    pub fn new(producer: Generator<'a, (), T>) -> Self {
+        Pipeline {
+            last: Box::new(producer),
+        }
+    }
+
+    /// add a stage running `f` on its own generator, fed by a buffer of up
+    /// to `capacity` outputs of the current last stage
+    ///
+    /// panics if `capacity` is 0
+    pub fn then<U: 'a, F>(self, capacity: usize, f: F) -> Pipeline<'a, U>
+    where
+        F: FnOnce(Stage<T, U>) + 'a,
+    {
+        assert!(capacity > 0, "the pipeline buffer capacity is 0");
+        let buf = Rc::new(RefCell::new(Buffer {
+            items: VecDeque::with_capacity(capacity),
+            closed: false,
+        }));
+        let gen = stage_gen(f, buf.clone());
+        Pipeline {
+            last: Box::new(Link {
+                upstream: self.last,
+                buf,
+                cap: capacity,
+                gen,
+            }),
+        }
+    }
+}
+
+impl<T> Iterator for Pipeline<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.last.pull()
+    }
+}
+
+// the generator running a stage on its input
+fn stage_gen<'a, T: 'a, U: 'a, F>(
+    f: F,
+    input: impl Inlet<T> + 'a,
+) -> LocalGenerator<'a, (), Poll<U>>
+where
+    F: FnOnce(Stage<T, U>) + 'a,
+{
+    Gn::<()>::new_scoped_local(move |scope| {
+        let input = Box::new(input);
+        f(Stage { scope, input });
+        done()
+    })
+}
+
+// a parked thread waiting on a ring
+#[derive(Default)]
+struct Waiter {
+    parked: AtomicBool,
+    thread: Mutex<Option<Thread>>,
+}
+
+impl Waiter {
+    // park the current thread until `ready`
+    fn wait(&self, ready: impl Fn() -> bool) {
+        while !ready() {
+            *self.thread.lock().unwrap() = Some(thread::current());
+            self.parked.store(true, Ordering::SeqCst);
+            // pairs with the fence in `notify`, one of them sees the other
+            fence(Ordering::SeqCst);
+            if ready() {
+                self.parked.store(false, Ordering::SeqCst);
+                return;
+            }
+            thread::park();
+        }
+    }
+
+    // unpark the waiting thread after the state is changed
+    fn notify(&self) {
+        fence(Ordering::SeqCst);
+        if self.parked.swap(false, Ordering::SeqCst) {
+            if let Some(t) = self.thread.lock().unwrap().as_ref() {
+                t.unpark();
+            }
+        }
+    }
+}
+
+// the lock free single producer single consumer queue between two stages,
+// the locks are only taken by a side about to park
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // the next slot to pop, only stored by the receiver
+    head: AtomicUsize,
+    // the next slot to push, only stored by the sender
+    tail: AtomicUsize,
+    sender_gone: AtomicBool,
+    receiver_gone: AtomicBool,
+    // the sender waiting for room and the receiver waiting for items
+    send_wait: Waiter,
+    recv_wait: Waiter,
+}
+
+// the slots are handed over by the head and the tail
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn new(capacity: usize) -> Self {
+        Ring {
+            slots: (0..capacity)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            sender_gone: AtomicBool::new(false),
+            receiver_gone: AtomicBool::new(false),
+            send_wait: Waiter::default(),
+            recv_wait: Waiter::default(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Relaxed).wrapping_sub(head) == self.slots.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) == self.tail.load(Ordering::Acquire)
+    }
+
+    // push from the sender, wait while it's full, `Err` once the receiver
+    // is gone
+    fn push(&self, v: T) -> Result<(), T> {
+        self.send_wait
+            .wait(|| !self.is_full() || self.receiver_gone.load(Ordering::Acquire));
+        if self.receiver_gone.load(Ordering::Acquire) {
+            return Err(v);
+        }
+        let tail = self.tail.load(Ordering::Relaxed);
+        unsafe { (*self.slots[tail % self.slots.len()].get()).write(v) };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        self.recv_wait.notify();
+        Ok(())
+    }
+
+    // pop from the receiver without waiting
+    fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let v = unsafe { (*self.slots[head % self.slots.len()].get()).assume_init_read() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        self.send_wait.notify();
+        Some(v)
+    }
+
+    // wait on the receiver side for an item or the end
+    fn wait_recv(&self) {
+        self.recv_wait.wait(|| {
+            !self.is_empty()
+                || self.sender_gone.load(Ordering::Acquire)
+                || self.receiver_gone.load(Ordering::Acquire)
+        });
+    }
+
+    fn close_sender(&self) {
+        self.sender_gone.store(true, Ordering::Release);
+        self.recv_wait.notify();
+    }
+
+    fn close_receiver(&self) {
+        self.receiver_gone.store(true, Ordering::Release);
+        self.send_wait.notify();
+    }
+}
+
+impl<T> Inlet<T> for Arc<Ring<T>> {
+    fn poll_recv(&mut self) -> Poll<Option<T>> {
+        // the pushes before the close are seen with it
+        let closed = self.sender_gone.load(Ordering::Acquire);
+        match self.pop() {
+            Some(v) => Poll::Ready(Some(v)),
+            None if closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+// close both sides of a ring of any item type
+trait Close: Send + Sync {
+    fn close(&self);
+}
+
+impl<T: Send> Close for Arc<Ring<T>> {
+    fn close(&self) {
+        self.close_receiver();
+        self.close_sender();
+    }
+}
+
+// the state shared by the workers of a parallel pipeline
+#[derive(Default)]
+struct Shared {
+    cancelled: AtomicBool,
+    // the first panic of the stages
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    rings: Mutex<Vec<Box<dyn Close>>>,
+}
+
+impl Shared {
+    // stop all the stages, the waiting ones are woken by the closed rings
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        for ring in self.rings.lock().unwrap().iter() {
+            ring.close();
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    // keep the first panic to propagate it to the consumer
+    fn fail(&self, e: Box<dyn Any + Send>) {
+        self.panic.lock().unwrap().get_or_insert(e);
+        self.cancel();
+    }
+}
+
+// run a worker body, its output is closed when it's finished
+fn work<T: Send + 'static>(
+    shared: Arc<Shared>,
+    out: Arc<Ring<T>>,
+    body: impl FnOnce(&Shared, &Ring<T>) + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| body(&shared, &out))) {
+            shared.fail(e);
+        }
+        out.close_sender();
+    })
+}
+
+/// the `Pipeline` running each stage on its own worker thread, the stages
+/// are linked by the lock free bounded queues
+///
+/// the workers start as the stages are added. A panic in a stage cancels
+/// all of them and is propagated to the consumer, dropping the pipeline
+/// cancels the stages and waits for the workers
+///
+/// ```
+/// use generator::{Gn, ParPipeline};
+///
+/// let producer = Gn::<()>::new_scoped(|mut s| {
+///     for i in 0..100u64 {
+///         s.yield_(i);
+///     }
+///     generator::done()
+/// });
+/// let squares = ParPipeline::new(16, producer).then(16, |mut stage| {
+///     while let Some(v) = stage.recv() {
+///         stage.send(v * v);
+///     }
+/// });
+/// assert_eq!(squares.sum::<u64>(), 328350);
+/// ```
+pub struct ParPipeline<T> {
+    // `None` once it's handed to the next stage
+    last: Option<Arc<Ring<T>>>,
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> ParPipeline<T> {
+    /// start the pipeline from the producer running on a worker, with a
+    /// buffer of up to `capacity` outputs
+    ///
+    /// panics if `capacity` is 0
+    pub fn new(capacity: usize, mut producer: Generator<'static, (), T>) -> Self {
+        let shared = Arc::new(Shared::default());
+        let out = Self::ring(&shared, capacity);
+        let worker = work(shared.clone(), out.clone(), move |shared, out| {
+            while !shared.is_cancelled() {
+                match producer.resume() {
+                    Some(v) => {
+                        if out.push(v).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+        });
+        ParPipeline {
+            last: Some(out),
+            shared,
+            workers: vec![worker],
+        }
+    }
+
+    // a new ring closed by the cancel
+    fn ring(shared: &Shared, capacity: usize) -> Arc<Ring<T>> {
+        assert!(capacity > 0, "the pipeline buffer capacity is 0");
+        let ring = Arc::new(Ring::new(capacity));
+        shared.rings.lock().unwrap().push(Box::new(ring.clone()));
+        ring
+    }
+
+    /// add a stage running `f` on the generator of a new worker, its
+    /// outputs are buffered up to `capacity` for the next stage
+    ///
+    /// panics if `capacity` is 0
+    pub fn then<U: Send + 'static, F>(mut self, capacity: usize, f: F) -> ParPipeline<U>
+    where
+        F: FnOnce(Stage<T, U>) + Send + 'static,
+    {
+        let input = self.last.take().unwrap();
+        let shared = self.shared.clone();
+        let out = ParPipeline::ring(&shared, capacity);
+        let worker = work(shared.clone(), out.clone(), move |shared, out| {
+            // the generator is created on the worker, it never leaves it
+            let mut gen = stage_gen(f, input.clone());
+            while !shared.is_cancelled() {
+                match gen.resume() {
+                    Some(Poll::Ready(v)) => {
+                        if out.push(v).is_err() {
+                            break;
+                        }
+                    }
+                    Some(Poll::Pending) => input.wait_recv(),
+                    None => break,
+                }
+            }
+            // the upstream stops when the stage doesn't take any more
+            input.close_receiver();
+        });
+        let mut workers = mem::take(&mut self.workers);
+        workers.push(worker);
+        ParPipeline {
+            last: Some(out),
+            shared,
+            workers,
+        }
+    }
+
+    /// cancel all the stages and wait for the workers, the consumer gets no
+    /// more outputs. A panic of the stages is still propagated
+    pub fn cancel(&mut self) {
+        self.shared.cancel();
+        self.join();
+    }
+
+    // wait for the workers, then propagate the panic of the stages if any
+    fn join(&mut self) {
+        for worker in self.workers.drain(..) {
+            // the panics of the bodies are caught
+            let _ = worker.join();
+        }
+        let panic = self.shared.panic.lock().unwrap().take();
+        if let Some(e) = panic {
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+impl<T: Send + 'static> Iterator for ParPipeline<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let out = self.last.as_mut()?;
+        loop {
+            if self.shared.is_cancelled() {
+                break;
+            }
+            match out.poll_recv() {
+                Poll::Ready(Some(v)) => return Some(v),
+                Poll::Ready(None) => break,
+                Poll::Pending => out.wait_recv(),
+            }
+        }
+        self.join();
+        None
+    }
+}
+
+impl<T> Drop for ParPipeline<T> {
+    fn drop(&mut self) {
+        // handed to the next stage, which owns the workers
+        if self.last.is_none() {
+            return;
+        }
+        self.shared.cancel();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
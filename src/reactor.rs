@@ -0,0 +1,534 @@
+//! # reactor
+//!
+//! suspend the generators on the fd readiness, the `Executor` made by
+//! `Executor::with_driver` parks in the driver while all its tasks wait
+//!
+//! ```
+//! use generator::reactor::Reactor;
+//! use generator::{Executor, Gn};
+//! use std::io::{Read, Write};
+//! use std::os::unix::io::AsRawFd;
+//! use std::os::unix::net::UnixStream;
+//! use std::task::Poll;
+//!
+//! let (mut a, mut b) = UnixStream::pair().unwrap();
+//! a.set_nonblocking(true).unwrap();
+//! let mut exec = Executor::with_driver(Reactor::new().unwrap());
+//! exec.spawn(Gn::new_scoped(move |mut s| {
+//!     let mut buf = [0; 5];
+//!     s.wait_readable(a.as_raw_fd()).unwrap();
+//!     a.read_exact(&mut buf).unwrap();
+//!     assert_eq!(&buf, b"hello");
+//!     Poll::Ready(())
+//! }));
+//! exec.spawn(Gn::new_scoped(move |mut s| {
+//!     s.wait_writable(b.as_raw_fd()).unwrap();
+//!     b.write_all(b"hello").unwrap();
+//!     Poll::Ready(())
+//! }));
+//! exec.run();
+//! ```
+//!
+
+use std::cell::Cell;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+use crate::scope::Scope;
+use crate::sync::thread_local;
+
+/// the readiness a generator waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interest {
+    /// the fd can be read without blocking, or is closed
+    Readable,
+    /// the fd can be written without blocking, or is closed
+    Writable,
+}
+
+/// the readiness source of an `Executor`, see `Executor::with_driver`
+pub trait Driver {
+    /// wake `waker` once `fd` is ready for `interest`, the registration is
+    /// one shot and replaces the previous one of the fd and interest
+    fn register(&mut self, fd: RawFd, interest: Interest, waker: Waker) -> io::Result<()>;
+
+    /// block until a registered fd is ready, the timeout passes, or the
+    /// `unparker` is woken, then wake the wakers of the ready fds
+    fn park(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// the waker that interrupts a `park` from any thread
+    fn unparker(&self) -> Waker;
+}
+
+thread_local! {
+    // the driver of the executor running on the thread
+    static DRIVER: Cell<Option<*mut dyn Driver>> = const { Cell::new(None) };
+}
+
+// restore the driver of the outer executor
+struct Restore(Option<*mut dyn Driver>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        DRIVER.with(|d| d.set(self.0));
+    }
+}
+
+// run `f` with `driver` as the one the generators register on
+pub(crate) fn with_driver<R>(driver: *mut dyn Driver, f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(DRIVER.with(|d| d.replace(Some(driver))));
+    f()
+}
+
+impl<A, U> Scope<'_, '_, A, Poll<U>> {
+    /// suspend the generator until `fd` is readable
+    ///
+    /// the generator should be run by an `Executor` with a driver, the fd
+    /// should be non blocking since the readiness may be spurious
+    pub fn wait_readable(&mut self, fd: RawFd) -> io::Result<()> {
+        self.wait_ready(fd, Interest::Readable)
+    }
+
+    /// suspend the generator until `fd` is writable, see `wait_readable`
+    pub fn wait_writable(&mut self, fd: RawFd) -> io::Result<()> {
+        self.wait_ready(fd, Interest::Writable)
+    }
+
+    /// suspend the generator until `fd` is ready for `interest`, the paras
+    /// sent while waiting are dropped
+    pub fn wait_ready(&mut self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        let driver = match DRIVER.with(Cell::get) {
+            Some(driver) => driver,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the generator is not run by an executor with a driver",
+                ))
+            }
+        };
+        let mut ret = Ok(());
+        self.yield_pending(|waker| {
+            // the executor is not using the driver while the task runs
+            if let Err(e) = unsafe { (*driver).register(fd, interest, waker.clone()) } {
+                // resume the task to report the error
+                waker.wake();
+                ret = Err(e);
+            }
+        });
+        ret
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub use self::sys::Reactor;
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod unpark {
+    use std::io;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+
+    // the write end of the self pipe that wakes the parked reactor, the
+    // wakers keep it open
+    pub(super) struct Unpark {
+        fd: OwnedFd,
+        notified: AtomicBool,
+    }
+
+    impl Unpark {
+        // create the pipe, return the read end with the unparker
+        pub(super) fn pipe() -> io::Result<(OwnedFd, Unpark)> {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let (read, write) =
+                unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+            for fd in [&read, &write] {
+                set_flags(fd.as_raw_fd())?;
+            }
+            let unpark = Unpark {
+                fd: write,
+                notified: AtomicBool::new(false),
+            };
+            Ok((read, unpark))
+        }
+
+        // drain the read end after it's ready, the flag is cleared after
+        // the drain so a wake in between writes a byte that stays readable
+        pub(super) fn reset(&self, read: RawFd) {
+            let mut buf = [0u8; 64];
+            while unsafe { libc::read(read, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+            self.notified.store(false, Ordering::SeqCst);
+        }
+    }
+
+    impl Wake for Unpark {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &std::sync::Arc<Self>) {
+            // a full pipe or a pending byte wakes the reactor anyway
+            if !self.notified.swap(true, Ordering::SeqCst) {
+                unsafe { libc::write(self.fd.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+            }
+        }
+    }
+
+    // the pipe is non blocking and not inherited
+    fn set_flags(fd: RawFd) -> io::Result<()> {
+        unsafe {
+            let fl = libc::fcntl(fd, libc::F_GETFL);
+            if fl < 0 || libc::fcntl(fd, libc::F_SETFL, fl | libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd_fl = libc::fcntl(fd, libc::F_GETFD);
+            if fd_fl < 0 || libc::fcntl(fd, libc::F_SETFD, fd_fl | libc::FD_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::collections::HashMap;
+    use std::io;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::sync::Arc;
+    use std::task::Waker;
+    use std::time::Duration;
+
+    use super::unpark::Unpark;
+    use super::{Driver, Interest};
+
+    // the token of the unpark pipe, the fds are never negative
+    const UNPARK: u64 = u64::MAX;
+
+    // the wakers waiting on an fd
+    #[derive(Default)]
+    struct Slot {
+        read: Option<Waker>,
+        write: Option<Waker>,
+    }
+
+    impl Slot {
+        fn events(&self) -> u32 {
+            let mut events = libc::EPOLLONESHOT as u32;
+            if self.read.is_some() {
+                events |= libc::EPOLLIN as u32 | libc::EPOLLRDHUP as u32;
+            }
+            if self.write.is_some() {
+                events |= libc::EPOLLOUT as u32;
+            }
+            events
+        }
+    }
+
+    /// the `Driver` on the os readiness api, epoll on linux and kqueue on
+    /// the BSDs and macOS
+    pub struct Reactor {
+        epoll: OwnedFd,
+        pipe: OwnedFd,
+        unpark: Arc<Unpark>,
+        slots: HashMap<RawFd, Slot>,
+        events: Vec<libc::epoll_event>,
+    }
+
+    impl Reactor {
+        /// create the reactor
+        pub fn new() -> io::Result<Self> {
+            let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+            if epoll < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+            let (pipe, unpark) = Unpark::pipe()?;
+            let mut ev = libc::epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: UNPARK,
+            };
+            let fd = pipe.as_raw_fd();
+            ctl(epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut ev)?;
+            Ok(Reactor {
+                epoll,
+                pipe,
+                unpark: Arc::new(unpark),
+                slots: HashMap::new(),
+                events: Vec::with_capacity(64),
+            })
+        }
+
+        // arm the fd for the wakers of its slot
+        fn arm(&mut self, fd: RawFd) -> io::Result<()> {
+            let mut ev = libc::epoll_event {
+                events: self.slots[&fd].events(),
+                u64: fd as u64,
+            };
+            let epoll = self.epoll.as_raw_fd();
+            // the fd stays added after its oneshot fires, until it's closed
+            match ctl(epoll, libc::EPOLL_CTL_MOD, fd, &mut ev) {
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+                    ctl(epoll, libc::EPOLL_CTL_ADD, fd, &mut ev)
+                }
+                ret => ret,
+            }
+        }
+    }
+
+    // the timeout in milliseconds for epoll, -1 blocks
+    fn timeout_ms(timeout: Option<Duration>) -> libc::c_int {
+        match timeout {
+            // round up so a short timeout doesn't spin
+            Some(d) => d
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .try_into()
+                .unwrap_or(libc::c_int::MAX),
+            None => -1,
+        }
+    }
+
+    fn ctl(epoll: RawFd, op: libc::c_int, fd: RawFd, ev: &mut libc::epoll_event) -> io::Result<()> {
+        if unsafe { libc::epoll_ctl(epoll, op, fd, ev) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    impl Driver for Reactor {
+        fn register(&mut self, fd: RawFd, interest: Interest, waker: Waker) -> io::Result<()> {
+            let slot = self.slots.entry(fd).or_default();
+            match interest {
+                Interest::Readable => slot.read = Some(waker),
+                Interest::Writable => slot.write = Some(waker),
+            }
+            if let Err(e) = self.arm(fd) {
+                let slot = self.slots.get_mut(&fd).unwrap();
+                match interest {
+                    Interest::Readable => slot.read = None,
+                    Interest::Writable => slot.write = None,
+                }
+                if slot.read.is_none() && slot.write.is_none() {
+                    self.slots.remove(&fd);
+                }
+                return Err(e);
+            }
+            Ok(())
+        }
+
+        fn park(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+            self.events.clear();
+            let n = unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    self.events.as_mut_ptr(),
+                    self.events.capacity() as libc::c_int,
+                    timeout_ms(timeout),
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                // a signal is a spurious wakeup
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(()),
+                    _ => Err(e),
+                };
+            }
+            unsafe { self.events.set_len(n as usize) };
+            for i in 0..self.events.len() {
+                let ev = self.events[i];
+                if ev.u64 == UNPARK {
+                    self.unpark.reset(self.pipe.as_raw_fd());
+                    continue;
+                }
+                let fd = ev.u64 as RawFd;
+                let slot = match self.slots.get_mut(&fd) {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                let (events, closed) = (ev.events, (libc::EPOLLERR | libc::EPOLLHUP) as u32);
+                let readable = (libc::EPOLLIN | libc::EPOLLRDHUP) as u32 | closed;
+                if events & readable != 0 {
+                    if let Some(waker) = slot.read.take() {
+                        waker.wake();
+                    }
+                }
+                if events & (libc::EPOLLOUT as u32 | closed) != 0 {
+                    if let Some(waker) = slot.write.take() {
+                        waker.wake();
+                    }
+                }
+                if slot.read.is_none() && slot.write.is_none() {
+                    self.slots.remove(&fd);
+                } else {
+                    // rearm for the other interest
+                    self.arm(fd)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn unparker(&self) -> Waker {
+            Waker::from(self.unpark.clone())
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod sys {
+    use std::collections::HashMap;
+    use std::io;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::sync::Arc;
+    use std::task::Waker;
+    use std::time::Duration;
+    use std::{mem, ptr};
+
+    use super::unpark::Unpark;
+    use super::{Driver, Interest};
+
+    /// the `Driver` on the os readiness api, epoll on linux and kqueue on
+    /// the BSDs and macOS
+    pub struct Reactor {
+        kq: OwnedFd,
+        pipe: OwnedFd,
+        unpark: Arc<Unpark>,
+        // the oneshot filters are independent, one waker per fd and filter
+        wakers: HashMap<(RawFd, Interest), Waker>,
+        events: Vec<libc::kevent>,
+    }
+
+    fn kevent(fd: RawFd, filter: Interest, flags: u32) -> libc::kevent {
+        let mut ev: libc::kevent = unsafe { mem::zeroed() };
+        ev.ident = fd as _;
+        ev.filter = match filter {
+            Interest::Readable => libc::EVFILT_READ,
+            Interest::Writable => libc::EVFILT_WRITE,
+        } as _;
+        ev.flags = flags as _;
+        ev
+    }
+
+    impl Reactor {
+        /// create the reactor
+        pub fn new() -> io::Result<Self> {
+            let kq = unsafe { libc::kqueue() };
+            if kq < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let kq = unsafe { OwnedFd::from_raw_fd(kq) };
+            if unsafe { libc::fcntl(kq.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let (pipe, unpark) = Unpark::pipe()?;
+            let mut reactor = Reactor {
+                kq,
+                pipe,
+                unpark: Arc::new(unpark),
+                wakers: HashMap::new(),
+                events: Vec::with_capacity(64),
+            };
+            let fd = reactor.pipe.as_raw_fd();
+            reactor.change(kevent(fd, Interest::Readable, libc::EV_ADD as u32))?;
+            Ok(reactor)
+        }
+
+        fn change(&mut self, ev: libc::kevent) -> io::Result<()> {
+            let n = unsafe {
+                libc::kevent(self.kq.as_raw_fd(), &ev, 1, ptr::null_mut(), 0, ptr::null())
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Driver for Reactor {
+        fn register(&mut self, fd: RawFd, interest: Interest, waker: Waker) -> io::Result<()> {
+            let flags = (libc::EV_ADD | libc::EV_ONESHOT) as u32;
+            self.change(kevent(fd, interest, flags))?;
+            self.wakers.insert((fd, interest), waker);
+            Ok(())
+        }
+
+        fn park(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+            let ts = timeout.map(|d| libc::timespec {
+                tv_sec: d.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+                tv_nsec: d.subsec_nanos() as _,
+            });
+            self.events.clear();
+            let n = unsafe {
+                libc::kevent(
+                    self.kq.as_raw_fd(),
+                    ptr::null(),
+                    0,
+                    self.events.as_mut_ptr(),
+                    self.events.capacity() as _,
+                    ts.as_ref().map_or(ptr::null(), |ts| ts as *const _),
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                // a signal is a spurious wakeup
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(()),
+                    _ => Err(e),
+                };
+            }
+            unsafe { self.events.set_len(n as usize) };
+            let pipe = self.pipe.as_raw_fd();
+            for ev in &self.events {
+                let fd = ev.ident as RawFd;
+                if fd == pipe {
+                    self.unpark.reset(pipe);
+                    continue;
+                }
+                let interest = if ev.filter == libc::EVFILT_READ as _ {
+                    Interest::Readable
+                } else {
+                    Interest::Writable
+                };
+                if let Some(waker) = self.wakers.remove(&(fd, interest)) {
+                    waker.wake();
+                }
+            }
+            Ok(())
+        }
+
+        fn unparker(&self) -> Waker {
+            Waker::from(self.unpark.clone())
+        }
+    }
+}
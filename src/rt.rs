@@ -2,12 +2,36 @@
 //!
 //! generator run time context management
 //!
-use std::any::Any;
+use std::any::{Any, TypeId};
 use std::cell::Cell;
-use std::mem::MaybeUninit;
+use std::ffi::CStr;
+use std::fmt;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use crate::group::GeneratorGroup;
 use crate::reg_context::RegContext;
+use crate::sync::{thread_local, Arc};
+
+// the max nesting depth of the generators, 0 for no limit
+static MAX_NESTING: AtomicUsize = AtomicUsize::new(0);
+
+/// set the max number of generators on the resume chain of a thread, see
+/// `nesting_depth`, 0 means no limit
+///
+/// a resume going deeper panics with the depth and the limit instead of
+/// growing the chain, the cancel of a generator is never refused
+pub fn set_max_nesting_depth(depth: usize) {
+    if depth != 0 {
+        crate::hook::enable_switch_extras();
+    }
+    MAX_NESTING.store(depth, Ordering::Relaxed);
+}
+
+/// get the max nesting depth set by `set_max_nesting_depth`, 0 for no limit
+pub fn max_nesting_depth() -> usize {
+    MAX_NESTING.load(Ordering::Relaxed)
+}
 
 thread_local! {
     // each thread has it's own generator context stack
@@ -15,6 +39,10 @@ thread_local! {
 }
 
 /// yield panic error types
+///
+/// `Cancel` and `Done` are used as panic payloads to unwind the generator
+/// stack, the code that catches panics inside a generator should rethrow
+/// them, see `is_cancel_payload`
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Error {
     /// Done panic
@@ -29,28 +57,148 @@ pub enum Error {
     ContextErr,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Error::Done => "generator is done",
+            Error::Cancel => "generator is cancelled",
+            Error::TypeErr => "generator para or output type mismatch",
+            Error::StackErr => "generator stack overflow",
+            Error::ContextErr => "not in a generator context",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// what a cancel does to a generator suspended inside `foreign_call`, the
+/// foreign frames on its stack can't be unwound
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ForeignCancel {
+    /// abort the process
+    #[default]
+    Abort,
+    /// abandon the frames without dropping anything, the stack is never
+    /// freed in case the foreign code still points into it, and the
+    /// generator can't be re-initialized
+    Leak,
+    /// resume the generator with the yields returning at once without a
+    /// value, the cancel unwinds the generator when the outermost
+    /// `foreign_call` returns
+    Defer,
+}
+
+/// run `f` that calls into foreign code, e.g. a C library calling back
+/// into the Rust code that yields
+///
+/// the frames of `f` are marked, a generator cancelled while it's suspended
+/// in them is handled by its `ForeignCancel` policy instead of unwinding
+/// through the foreign frames, see `Generator::set_foreign_cancel`
+pub fn foreign_call<R>(f: impl FnOnce() -> R) -> R {
+    // the depth is restored even if `f` panics before calling into the
+    // foreign code
+    struct Depth(*mut Context);
+
+    impl Drop for Depth {
+        fn drop(&mut self) {
+            unsafe { (*self.0).foreign -= 1 };
+        }
+    }
+
+    let context = ContextStack::current().top() as *mut Context;
+    if !unsafe { (*context).is_generator() } {
+        return f();
+    }
+    unsafe { (*context).foreign += 1 };
+    let depth = Depth(context);
+    let r = f();
+    drop(depth);
+    // the frames left are all ours, finish the deferred cancel
+    let context = unsafe { &*context };
+    if context.foreign == 0 && context._ref == 2 {
+        std::panic::panic_any(Error::Cancel);
+    }
+    r
+}
+
+/// the error set by the overflow handler for a fault in the guard region,
+/// the resumer raises it as a panic with the details
+pub(crate) struct GuardFault;
+
+// the overflow report naming the generator and its stack size in bytes
+pub(crate) fn overflow_message(name: Option<&CStr>, size: usize) -> String {
+    let size = if size % (1 << 20) == 0 {
+        format!("{} MiB", size >> 20)
+    } else {
+        format!("{} KiB", size >> 10)
+    };
+    match name {
+        Some(name) => format!(
+            "generator '{}' overflowed its {size} stack",
+            name.to_string_lossy()
+        ),
+        None => format!("generator overflowed its {size} stack"),
+    }
+}
+
+/// check if the panic payload is the cancellation of a generator
+///
+/// it should be rethrown by `resume_unwind` to finish the cancellation,
+/// so does the `Error::Done` payload of `done!()`
+// take the `Box` to not coerce the box itself into `dyn Any`
+#[allow(clippy::borrowed_box)]
+pub fn is_cancel_payload(payload: &Box<dyn Any + Send>) -> bool {
+    matches!(payload.downcast_ref::<Error>(), Some(Error::Cancel))
+}
+
 /// generator context
+// the fields touched by every switch share the first cache line, followed
+// by the registers, so a resume on x86_64 touches one 128 bytes block, the
+// line pair the spatial prefetcher loads together. The cold fields are at
+// the end
 #[repr(C)]
 #[repr(align(128))]
 pub struct Context {
-    /// generator regs context
-    pub regs: RegContext,
-    /// child context
-    child: *mut Context,
     /// parent context
     pub parent: *mut Context,
-    /// passed in para for send
-    pub para: MaybeUninit<*mut dyn Any>,
-    /// this is just a buffer for the return value
-    pub ret: MaybeUninit<*mut dyn Any>,
     /// track generator ref, yield will -1, send will +1
     pub _ref: usize,
+    /// the nesting depth of `foreign_call` in the generator
+    pub foreign: usize,
+    /// the nesting depth while it's running, 0 for the root
+    pub depth: usize,
+    /// passed in para for send, points to the `Option<A>` of the generator
+    pub para: *mut (),
+    /// this is just a buffer for the return value, the `Option<T>` one
+    pub ret: *mut (),
     /// context local storage
     pub local_data: *mut u8,
+    /// child context
+    child: *mut Context,
+    /// generator regs context
+    pub regs: RegContext,
+    /// the types of `para` and `ret` for the checked access, set with them
+    pub slot_types: Option<[TypeId; 2]>,
     /// propagate panic
     pub err: Option<Box<dyn Any + Send>>,
     /// cached stack guard for fast path
     pub stack_guard: (usize, usize),
+    /// type tag written by `into_raw` and checked by `from_raw`
+    pub raw_tag: usize,
+    /// the name reported by `current_name`
+    pub name: Option<&'static CStr>,
+    /// the payload passed by `throw`, raised or returned by the next yield
+    pub thrown: Option<Box<dyn Any + Send>>,
+    /// the size of the guard region below the stack in bytes
+    pub guard_size: usize,
+    /// the values of `generator_local!` keyed by the key address
+    pub locals: Vec<(usize, Box<dyn Any + Send>)>,
+    /// set when the generator is cancelled, shared with the generators
+    /// attached to it
+    pub cancel_token: Option<Arc<AtomicBool>>,
+    /// the group of the generator, inherited by the ones created in it
+    pub group: Option<GeneratorGroup>,
 }
 
 impl Context {
@@ -58,21 +206,50 @@ impl Context {
     pub fn new() -> Context {
         Context {
             regs: RegContext::empty(),
-            para: MaybeUninit::zeroed(),
-            ret: MaybeUninit::zeroed(),
+            para: ptr::null_mut(),
+            ret: ptr::null_mut(),
+            slot_types: None,
             _ref: 1, // none zero means it's not running
             err: None,
             child: ptr::null_mut(),
             parent: ptr::null_mut(),
             local_data: ptr::null_mut(),
             stack_guard: (0, 0),
+            raw_tag: 0,
+            name: None,
+            thrown: None,
+            guard_size: 0,
+            foreign: 0,
+            locals: Vec::new(),
+            cancel_token: None,
+            depth: 0,
+            group: None,
         }
     }
 
+    /// check if the generator is cancelled by a yield, a deferred cancel
+    /// waits for the end of the `foreign_call`
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self._ref != 1 && self.foreign == 0
+    }
+
+    /// check if the yield should return at once for a deferred cancel
+    #[inline]
+    pub fn cancel_deferred(&self) -> bool {
+        self._ref == 2 && self.foreign != 0
+    }
+
     /// judge it's generator context
     #[inline]
     pub fn is_generator(&self) -> bool {
-        self.parent != self as *const _ as *mut _
+        !std::ptr::eq(self.parent, self)
+    }
+
+    // check the type of the slot at `i` of `slot_types`
+    #[inline]
+    fn slot_is<X: Any>(&self, i: usize) -> bool {
+        self.slot_types.is_some_and(|t| t[i] == TypeId::of::<X>())
     }
 
     /// get current generator send para
@@ -81,51 +258,26 @@ impl Context {
     where
         A: Any,
     {
-        let para = unsafe {
-            let para_ptr = *self.para.as_mut_ptr();
-            assert!(!para_ptr.is_null());
-            &mut *para_ptr
-        };
-        match para.downcast_mut::<Option<A>>() {
-            Some(v) => v.take(),
-            None => type_error::<A>("get yield type mismatch error detected"),
+        assert!(!self.para.is_null());
+        if !self.slot_is::<A>(0) {
+            type_error::<A>("get yield type mismatch error detected");
         }
+        unsafe { (*(self.para as *mut Option<A>)).take() }
     }
 
     /// get coroutine send para
     #[inline]
     pub fn co_get_para<A>(&mut self) -> Option<A> {
-        let para = unsafe {
-            let para_ptr = *self.para.as_mut_ptr();
-            debug_assert!(!para_ptr.is_null());
-            &mut *(para_ptr as *mut Option<A>)
-        };
-        para.take()
+        debug_assert!(!self.para.is_null());
+        unsafe { (*(self.para as *mut Option<A>)).take() }
     }
 
-    // /// set current generator send para
-    // #[inline]
-    // pub fn set_para<A>(&self, data: A)
-    // where
-    //     A: Any,
-    // {
-    //     let para = unsafe { &mut *self.para };
-    //     match para.downcast_mut::<Option<A>>() {
-    //         Some(v) => *v = Some(data),
-    //         None => type_error::<A>("set yield type mismatch error detected"),
-    //     }
-    // }
-
     /// set coroutine send para
     /// without check the data type for coroutine performance reason
     #[inline]
     pub fn co_set_para<A>(&mut self, data: A) {
-        let para = unsafe {
-            let para_ptr = *self.para.as_mut_ptr();
-            debug_assert!(!para_ptr.is_null());
-            &mut *(para_ptr as *mut Option<A>)
-        };
-        *para = Some(data);
+        debug_assert!(!self.para.is_null());
+        unsafe { *(self.para as *mut Option<A>) = Some(data) };
     }
 
     /// set current generator return value
@@ -134,27 +286,19 @@ impl Context {
     where
         T: Any,
     {
-        let ret = unsafe {
-            let ret_ptr = *self.ret.as_mut_ptr();
-            assert!(!ret_ptr.is_null());
-            &mut *ret_ptr
-        };
-        match ret.downcast_mut::<Option<T>>() {
-            Some(r) => *r = Some(v),
-            None => type_error::<T>("yield type mismatch error detected"),
+        assert!(!self.ret.is_null());
+        if !self.slot_is::<T>(1) {
+            type_error::<T>("yield type mismatch error detected");
         }
+        unsafe { *(self.ret as *mut Option<T>) = Some(v) };
     }
 
     /// set coroutine return value
     /// without check the data type for coroutine performance reason
     #[inline]
     pub fn co_set_ret<T>(&mut self, v: T) {
-        let ret = unsafe {
-            let ret_ptr = *self.ret.as_mut_ptr();
-            debug_assert!(!ret_ptr.is_null());
-            &mut *(ret_ptr as *mut Option<T>)
-        };
-        *ret = Some(v);
+        debug_assert!(!self.ret.is_null());
+        unsafe { *(self.ret as *mut Option<T>) = Some(v) };
     }
 }
 
@@ -172,13 +316,13 @@ impl ContextStack {
             root.parent = p; // init top to current
             Box::leak(root)
         };
-        ROOT_CONTEXT_P.set(root);
+        ROOT_CONTEXT_P.with(|p| p.set(root));
         root
     }
 
     /// get the current context stack
     pub fn current() -> ContextStack {
-        let mut root = ROOT_CONTEXT_P.get();
+        let mut root = ROOT_CONTEXT_P.with(Cell::get);
 
         if root.is_null() {
             root = Self::init_root();
@@ -200,7 +344,7 @@ impl ContextStack {
 
         // search from top
         let mut ctx = unsafe { &mut *root.parent };
-        while ctx as *const _ != root as *const _ {
+        while !std::ptr::eq(ctx, root) {
             if !ctx.local_data.is_null() {
                 return Some(ctx);
             }
@@ -221,6 +365,7 @@ impl ContextStack {
         // link top and new ctx
         top.child = ctx;
         ctx.parent = top;
+        ctx.depth = top.depth + 1;
 
         // save the new top
         root.parent = new_top;
@@ -260,6 +405,80 @@ pub fn is_generator() -> bool {
     !root.child.is_null()
 }
 
+// the running generator contexts of the current thread, the innermost
+// one first
+#[inline]
+fn running_contexts() -> impl Iterator<Item = &'static Context> {
+    // no lazy init, it's safe to be called from a signal handler
+    let root = ROOT_CONTEXT_P.with(Cell::get);
+    let mut ctx = if root.is_null() {
+        root
+    } else {
+        unsafe { (*root).parent }
+    };
+    std::iter::from_fn(move || {
+        if ctx.is_null() || std::ptr::eq(ctx, root) {
+            return None;
+        }
+        let context = unsafe { &*ctx };
+        // a running context links to its resumer
+        ctx = context.parent;
+        Some(context)
+    })
+}
+
+// the running generator context of the current thread
+#[inline]
+fn current_context() -> Option<&'static Context> {
+    running_contexts().next()
+}
+
+/// get the id of the generator running on the current thread, see
+/// `GeneratorObj::id`
+///
+/// the context stack of the thread is updated on each switch, so it's cheap
+/// and can be called by a sampling profiler from a signal handler
+#[inline]
+pub fn current_id() -> Option<usize> {
+    // the context is the first field of the generator
+    current_context().map(|ctx| ctx as *const Context as usize)
+}
+
+/// get the name of the generator running on the current thread, see
+/// `GeneratorObj::set_name`
+#[inline]
+pub fn current_name() -> Option<&'static CStr> {
+    current_context().and_then(|ctx| ctx.name)
+}
+
+/// get the number of generators on the resume chain of the current thread,
+/// 0 outside of a generator and 1 in a generator resumed by the thread
+///
+/// see `set_max_nesting_depth` to limit it
+#[inline]
+pub fn nesting_depth() -> usize {
+    current_context().map_or(0, |ctx| ctx.depth)
+}
+
+/// get the id of the generator that resumed the running one, `None` if
+/// the running one is resumed by the thread or there is none
+///
+/// with `current_id`, it's enough to rebuild the resume tree of the
+/// generators at run time
+#[inline]
+pub fn parent_id() -> Option<usize> {
+    running_contexts()
+        .nth(1)
+        .map(|ctx| ctx as *const Context as usize)
+}
+
+/// get the name of the generator that resumed the running one, see
+/// `parent_id` and `GeneratorObj::set_name`
+#[inline]
+pub fn parent_name() -> Option<&'static CStr> {
+    running_contexts().nth(1).and_then(|ctx| ctx.name)
+}
+
 /// get the current context local data
 /// only coroutine support local data
 #[inline]
@@ -269,7 +488,7 @@ pub fn get_local_data() -> *mut u8 {
 
     // search from top
     let mut ctx = unsafe { &mut *root.parent };
-    while ctx as *const _ != root as *const _ {
+    while !std::ptr::eq(ctx, root) {
         if !ctx.local_data.is_null() {
             return ctx.local_data;
         }
@@ -282,22 +501,22 @@ pub fn get_local_data() -> *mut u8 {
 pub mod guard {
     use crate::is_generator;
     use crate::rt::ContextStack;
-    use crate::stack::sys::page_size;
     use std::ops::Range;
 
     pub type Guard = Range<usize>;
 
     pub fn current() -> Guard {
         assert!(is_generator());
-        let guard = unsafe { (*(*ContextStack::current().root).child).stack_guard };
+        let ctx = unsafe { &*(*ContextStack::current().root).child };
+        let guard = ctx.stack_guard;
 
-        guard.0 - page_size()..guard.1
+        guard.0 - ctx.guard_size..guard.1
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::is_generator;
+    use super::{is_generator, Context};
 
     #[test]
     fn test_is_context() {
@@ -305,6 +524,39 @@ mod test {
         assert!(!is_generator());
     }
 
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_context_layout() {
+        let ctx = Context::new();
+        let base = &ctx as *const Context as usize;
+        let offset = |p: usize| p - base;
+
+        // the fields touched by every switch share the first cache line
+        macro_rules! end {
+            ($f:ident) => {
+                offset(&ctx.$f as *const _ as usize) + std::mem::size_of_val(&ctx.$f)
+            };
+        }
+        assert_eq!(offset(&ctx.parent as *const _ as usize), 0);
+        assert!(end!(_ref) <= 64);
+        assert!(end!(foreign) <= 64);
+        assert!(end!(depth) <= 64);
+        assert!(end!(para) <= 64);
+        assert!(end!(ret) <= 64);
+        assert!(end!(local_data) <= 64);
+        assert!(end!(child) <= 64);
+        // and the registers follow in the same 128 bytes block
+        assert_eq!(offset(&ctx.regs as *const _ as usize), 64);
+        #[cfg(all(target_arch = "x86_64", unix))]
+        assert_eq!(std::mem::size_of_val(&ctx.regs), 64);
+        assert_eq!(std::mem::align_of::<Context>(), 128);
+
+        // the debugger scripts read the saved registers at this offset
+        let regs = format!("REGS_OFFSET = {}", offset(&ctx.regs as *const _ as usize));
+        assert!(include_str!("../scripts/gdb/generator.py").contains(&regs));
+        assert!(include_str!("../scripts/lldb/generator.py").contains(&regs));
+    }
+
     #[test]
     fn test_overflow() {
         use crate::*;
@@ -326,10 +578,68 @@ mod test {
                 g.next();
             });
 
-            assert!(matches!(
-                result.map_err(|err| *err.downcast::<Error>().unwrap()),
-                Err(Error::StackErr)
-            ));
+            let msg = *result.unwrap_err().downcast::<String>().unwrap();
+            assert!(msg.starts_with("generator overflowed its"), "{msg}");
         }
     }
+
+    #[test]
+    fn test_overflow_large_guard() {
+        use crate::*;
+        use std::panic::catch_unwind;
+
+        let result = catch_unwind(|| {
+            let mut g = Gn::<()>::builder()
+                .guard_size(0x10000)
+                .scoped(move |_s: Scope<(), ()>| {
+                    let guard = super::guard::current();
+                    assert!(guard.end - guard.start > 0x10000);
+
+                    // the lowest page of the guard region
+                    std::hint::black_box(unsafe { *(guard.start as *const usize) });
+
+                    eprintln!("entered unreachable code");
+                    std::process::abort();
+                })
+                .unwrap();
+
+            g.resume();
+        });
+
+        let msg = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(msg.starts_with("generator overflowed its"), "{msg}");
+    }
+
+    #[test]
+    fn test_overflow_named() {
+        use crate::*;
+        use std::ffi::CStr;
+        use std::panic::catch_unwind;
+
+        #[inline(never)]
+        fn recurse(n: usize) -> usize {
+            let buf = std::hint::black_box([n as u8; 0x100]);
+            if n == usize::MAX {
+                return 0;
+            }
+            recurse(n + 1) + usize::from(buf[0])
+        }
+
+        let result = catch_unwind(|| {
+            let name = CStr::from_bytes_with_nul(b"foo\0").unwrap();
+            let mut g = Gn::<()>::new_scoped_opt(0x4000, |_s| recurse(0));
+            g.set_name(name);
+            g.resume();
+        });
+
+        // the panic message names the generator like the one of a thread
+        let msg = *result.unwrap_err().downcast::<String>().unwrap();
+        let kib = (0x4000 * std::mem::size_of::<usize>()) >> 10;
+        assert_eq!(
+            msg,
+            format!("generator 'foo' overflowed its {kib} KiB stack")
+        );
+        let msg = super::overflow_message(None, 1 << 20);
+        assert_eq!(msg, "generator overflowed its 1 MiB stack");
+    }
 }
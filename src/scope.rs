@@ -3,54 +3,119 @@
 //! generator yield implementation
 //!
 
+use std::alloc::Layout;
+use std::any::Any;
+use std::fmt;
 use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
 use std::sync::atomic;
 
 use crate::gen_impl::Generator;
 use crate::rt::{Context, ContextStack, Error};
-use crate::yield_::raw_yield_now;
+use crate::yield_::{done_now, raw_yield_now, yield_none};
 
 /// passed in scope type
 /// it not use the context to pass data, but keep it's own data ref
 /// this struct provide both compile type info and runtime data
+///
+/// # Unwind safety
+///
+/// The scope is not `UnwindSafe`, it borrows the slots of the generator
+/// like a `&mut`, a `catch_unwind` around it needs an `AssertUnwindSafe`.
+/// The slots are never left half written by a panic. The cancellation of
+/// the generator is a panic raised by the yield, a `catch_unwind` in the generator must rethrow it by
+/// `resume_unwind`, see [`is_cancel_payload`](crate::is_cancel_payload),
+/// otherwise the generator is not finished by the cancel.
 pub struct Scope<'scope, 'a, A, T> {
-    para: &'a mut Option<A>,
-    ret: &'a mut Option<T>,
+    // point to the para/ret slots of the owning `GeneratorImpl`
+    para: *mut Option<A>,
+    ret: *mut Option<T>,
+    out: *mut *mut T,
+    // the free and the high end of the scratch space
+    scratch: *mut (usize, usize),
     scope: PhantomData<&'scope mut &'scope ()>,
+    phantom: PhantomData<&'a mut (Option<A>, Option<T>)>,
 }
 
-impl<'a, A, T> Scope<'_, 'a, A, T> {
+// the scope behaves like the `&'a mut` slots it points to
+unsafe impl<A: Send, T: Send> Send for Scope<'_, '_, A, T> {}
+
+impl<'scope, A, T> Scope<'scope, '_, A, T> {
     /// create a new scope object
-    pub(crate) fn new(para: &'a mut Option<A>, ret: &'a mut Option<T>) -> Self {
+    ///
+    /// # Safety
+    ///
+    /// `para`, `ret`, `out` and `scratch` must point to the slots of a `GeneratorImpl` that
+    /// outlives the scope. This holds when the scope is only moved into the
+    /// generator's own closure: the `GeneratorImpl` is pinned on the
+    /// generator stack, and the closure is always consumed or dropped
+    /// before the `GeneratorImpl` is dropped.
+    pub(crate) unsafe fn new(
+        para: *mut Option<A>,
+        ret: *mut Option<T>,
+        out: *mut *mut T,
+        scratch: *mut (usize, usize),
+    ) -> Self {
         Scope {
             para,
             ret,
+            out,
+            scratch,
             scope: PhantomData,
+            phantom: PhantomData,
         }
     }
 
     /// set current generator return value
     #[inline]
     fn set_ret(&mut self, v: T) {
-        *self.ret = Some(v);
+        unsafe {
+            // write into the slot provided by `resume_into` if any
+            let out = *self.out;
+            if out.is_null() {
+                *self.ret = Some(v);
+            } else {
+                out.write(v);
+                *self.out = std::ptr::null_mut();
+            }
+        }
     }
 
-    /// raw yield without catch passed in para
+    /// switch out with the value and come back by the next resume, return
+    /// the payload passed by `throw` if any
     #[inline]
-    fn raw_yield(&mut self, env: &ContextStack, context: &mut Context, v: T) {
+    fn switch_out(
+        &mut self,
+        env: &ContextStack,
+        context: &mut Context,
+        v: T,
+    ) -> Option<Box<dyn Any + Send>> {
         // check the context
         if !context.is_generator() {
             panic!("yield from none generator context");
         }
+        if context.cancel_deferred() {
+            return None;
+        }
 
         self.set_ret(v);
         context._ref -= 1;
         raw_yield_now(env, context);
 
         // here we just panic to exit the func
-        if context._ref != 1 {
+        if context.is_cancelled() {
             std::panic::panic_any(Error::Cancel);
         }
+        context.thrown.take()
+    }
+
+    /// raw yield without catch passed in para
+    #[inline]
+    fn raw_yield(&mut self, env: &ContextStack, context: &mut Context, v: T) {
+        if let Some(e) = self.switch_out(env, context, v) {
+            std::panic::resume_unwind(e);
+        }
     }
 
     /// yield something without catch passed in para
@@ -61,10 +126,91 @@ impl<'a, A, T> Scope<'_, 'a, A, T> {
         self.raw_yield(&env, context, v);
     }
 
+    /// finish the generator without a return value
+    ///
+    /// it's the type checked version of `done!()`, since a scope only lives
+    /// in its generator
+    pub fn done(self) -> ! {
+        done_now()
+    }
+
     /// get current generator send para
     #[inline]
     pub fn get_yield(&mut self) -> Option<A> {
-        self.para.take()
+        unsafe { (*self.para).take() }
+    }
+
+    /// take the para sent by the resumer, including the one of the first
+    /// `send` that starts the generator
+    ///
+    /// it's `get_yield` by the name of the request/response style, the para
+    /// is only taken once, by either of them
+    #[inline]
+    pub fn try_recv(&mut self) -> Option<A> {
+        self.get_yield()
+    }
+
+    /// take the para sent by the resumer
+    ///
+    /// panics at the caller if the generator is resumed without a para, e.g.
+    /// by `resume` or `next`, see `try_recv`
+    #[inline]
+    #[track_caller]
+    pub fn recv(&mut self) -> A {
+        match self.get_yield() {
+            Some(a) => a,
+            None => panic!("no para is sent to the generator"),
+        }
+    }
+
+    /// get the bytes of stack left below the caller, the guard is not counted
+    ///
+    /// recursive code can check it to switch to an explicit worklist before
+    /// the stack overflows
+    #[inline(never)]
+    pub fn stack_remaining(&self) -> usize {
+        let context = ContextStack::current().top();
+        // the frame of the call is close enough to the current sp
+        let sp = std::hint::black_box(&context) as *const _ as usize;
+        sp.saturating_sub(context.stack_guard.0)
+    }
+
+    /// move the value into the scratch space of the generator stack, see
+    /// `Builder::scratch`
+    ///
+    /// the value is dropped with the returned reference, but its space is
+    /// only reused when the generator is re-initialized
+    ///
+    /// panics if the scratch space is used up, see `try_alloc`
+    #[track_caller]
+    pub fn alloc<V>(&self, v: V) -> StackRef<'scope, V> {
+        match self.try_alloc(v) {
+            Ok(r) => r,
+            Err(_) => panic!(
+                "no room for {} bytes in the generator scratch space",
+                std::mem::size_of::<V>()
+            ),
+        }
+    }
+
+    /// move the value into the scratch space of the generator stack, the
+    /// value is given back if there is no room for it
+    pub fn try_alloc<V>(&self, v: V) -> Result<StackRef<'scope, V>, V> {
+        let layout = Layout::new::<V>();
+        if layout.size() == 0 {
+            return Ok(StackRef::new(NonNull::dangling(), v));
+        }
+        // the scope is not `Sync`, nothing else touches the space now
+        let (free, end) = unsafe { &mut *self.scratch };
+        let start = (*free + layout.align() - 1) & !(layout.align() - 1);
+        if *free == 0 || start + layout.size() > *end {
+            return Err(v);
+        }
+        *free = start + layout.size();
+        Ok(StackRef::new(
+            unsafe { NonNull::new_unchecked(start as *mut V) },
+            v,
+        ))
     }
 
     /// yield and get the send para
@@ -99,6 +245,61 @@ impl<'a, A, T> Scope<'_, 'a, A, T> {
     }
 }
 
+/// a value in the scratch space of a generator stack, see `Scope::alloc`
+///
+/// it can't outlive the generator closure, the value is dropped with it
+pub struct StackRef<'scope, V> {
+    ptr: NonNull<V>,
+    scope: PhantomData<&'scope mut V>,
+}
+
+// it owns the value like a `Box`
+unsafe impl<V: Send> Send for StackRef<'_, V> {}
+unsafe impl<V: Sync> Sync for StackRef<'_, V> {}
+
+impl<V> StackRef<'_, V> {
+    fn new(ptr: NonNull<V>, v: V) -> Self {
+        unsafe { ptr.as_ptr().write(v) };
+        StackRef {
+            ptr,
+            scope: PhantomData,
+        }
+    }
+
+    /// move the value out, the space is not reused
+    pub fn into_inner(self) -> V {
+        let v = unsafe { self.ptr.as_ptr().read() };
+        std::mem::forget(self);
+        v
+    }
+}
+
+impl<V> Deref for StackRef<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<V> DerefMut for StackRef<'_, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for StackRef<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<V> Drop for StackRef<'_, V> {
+    fn drop(&mut self) {
+        unsafe { std::ptr::drop_in_place(self.ptr.as_ptr()) };
+    }
+}
+
 impl<A, T> Scope<'_, 'static, A, T> {
     /// yield and get the send para
     // it's totally safe that we can refer to the function block
@@ -113,4 +314,51 @@ impl<A, T> Scope<'_, 'static, A, T> {
     pub fn yield_from(&mut self, g: Generator<A, T>) -> Option<A> {
         unsafe { self.yield_from_unsafe(g) }
     }
+
+    /// yield without a value and get the send para
+    ///
+    /// for a generator that runs as a cooperative task, the resumer gets
+    /// `None` while the generator is not done, see `Generator::resume_step`.
+    /// The iterator of the generator skips it
+    #[inline]
+    pub fn co_yield(&mut self) -> Option<A> {
+        yield_none();
+        atomic::compiler_fence(atomic::Ordering::Acquire);
+        self.get_yield()
+    }
+
+    /// yield and get the send para, or the payload passed by
+    /// `Generator::throw` as the `Err`
+    ///
+    /// the other yields raise the thrown payload as a panic, here it can be
+    /// handled by a match arm and the generator keeps running
+    #[inline]
+    pub fn yield_catch(&mut self, v: T) -> Result<Option<A>, Box<dyn Any + Send>> {
+        let env = ContextStack::current();
+        let context = env.top();
+        match self.switch_out(&env, context, v) {
+            Some(e) => Err(e),
+            None => {
+                atomic::compiler_fence(atomic::Ordering::Acquire);
+                Ok(self.get_yield())
+            }
+        }
+    }
+}
+
+impl<A, U, E> Scope<'_, 'static, A, Result<U, E>> {
+    /// yield the `Ok` value and get the send para, or finish the generator
+    /// with the `Err` as the return value
+    ///
+    /// it's the `?` of the generator body that also works in nested calls,
+    /// see `try_yield!`
+    pub fn try_yield(&mut self, r: Result<U, E>) -> Option<A> {
+        match r {
+            Ok(v) => self.yield_(Ok(v)),
+            Err(e) => {
+                self.set_ret(Err(e));
+                done_now()
+            }
+        }
+    }
 }
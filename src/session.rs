@@ -0,0 +1,197 @@
+//! # session typed generators
+//!
+//! A typestate layer over `Generator` where the protocol, the order of the
+//! sends and the yields and their types, is a type. Each step consumes the
+//! session and returns it in the type of the next step, so a step out of
+//! the protocol doesn't compile.
+//!
+//! The protocol is written from the resumer side with `Tx` (the resumer
+//! sends), `Rx` (the resumer receives) and `End`, the generator body gets
+//! the dual one and must bring it to `End`:
+//!
+//! ```
+//! use generator::session::{End, Rx, Session, Tx};
+//!
+//! // send a number, receive its square and its text, then close
+//! type Square = Tx<u32, Rx<u32, Rx<String, End>>>;
+//!
+//! let s = Session::<Square>::new(|c| {
+//!     let (n, c) = c.recv();
+//!     c.send(n * n).send(n.to_string())
+//! });
+//!
+//! let (sq, s) = s.send(7).recv();
+//! let (text, s) = s.recv();
+//! s.close();
+//! assert_eq!((sq, text.as_str()), (49, "7"));
+//! ```
+//!
+//! Sending twice where the protocol expects a receive is rejected:
+//!
+//! ```compile_fail
+//! use generator::session::{End, Rx, Session, Tx};
+//!
+//! let s = Session::<Tx<u32, Rx<u32, End>>>::new(|c| {
+//!     let (n, c) = c.recv();
+//!     c.send(n)
+//! });
+//! s.send(1).send(2);
+//! ```
+//!
+//! The messages are passed as `Box<dyn Any + Send>` through the generator,
+//! the protocol makes sure the types always match.
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+use crate::gen_impl::{Generator, Gn};
+use crate::scope::Scope;
+
+// the type erased message
+type Msg = Box<dyn Any + Send>;
+
+/// the step sending an `A` and continuing with `P`
+pub struct Tx<A, P>(PhantomData<(fn(A), P)>);
+
+/// the step receiving a `T` and continuing with `P`
+pub struct Rx<T, P>(PhantomData<(fn() -> T, P)>);
+
+/// the end of the protocol
+pub struct End;
+
+/// a session protocol, see the module doc
+pub trait Protocol {
+    /// the protocol of the other side, the sends and receives swapped
+    type Dual: Protocol;
+}
+
+impl Protocol for End {
+    type Dual = End;
+}
+
+impl<A, P: Protocol> Protocol for Tx<A, P> {
+    type Dual = Rx<A, P::Dual>;
+}
+
+impl<T, P: Protocol> Protocol for Rx<T, P> {
+    type Dual = Tx<T, P::Dual>;
+}
+
+fn downcast<T: Any>(m: Msg) -> T {
+    *m.downcast::<T>()
+        .unwrap_or_else(|_| unreachable!("session message type mismatch"))
+}
+
+/// the resumer side of a session typed generator, at the step `P`
+///
+/// the generator is suspended where its next step is the dual of `P`, a
+/// value it sends after a receive is kept until the resumer takes it
+pub struct Session<P> {
+    gen: Generator<'static, Msg, Option<Msg>>,
+    // sent by the generator and not received yet
+    stash: Option<Msg>,
+    phantom: PhantomData<P>,
+}
+
+impl<P: Protocol> Session<P> {
+    /// create a session with the generator body, it gets the dual side of
+    /// the protocol and returns it at `End`
+    pub fn new<F>(f: F) -> Self
+    where
+        for<'s> F: FnOnce(Chan<'s, P::Dual>) -> Chan<'s, End> + Send + 'static,
+    {
+        let gen = Gn::<Msg>::new_scoped(move |scope| {
+            f(Chan {
+                scope,
+                phantom: PhantomData,
+            });
+            None
+        });
+        Session {
+            gen,
+            stash: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P> Session<P> {
+    fn step<Q>(self) -> Session<Q> {
+        Session {
+            gen: self.gen,
+            stash: self.stash,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<A: Any + Send, P> Session<Tx<A, P>> {
+    /// send the value to the generator
+    pub fn send(mut self, a: A) -> Session<P> {
+        // the generator runs to its next step, keep what it sends
+        if let Some(Some(m)) = self.gen.raw_send(Some(Box::new(a))) {
+            self.stash = Some(m);
+        }
+        self.step()
+    }
+}
+
+impl<T: Any + Send, P> Session<Rx<T, P>> {
+    /// receive the value sent by the generator
+    pub fn recv(mut self) -> (T, Session<P>) {
+        let m = match self.stash.take() {
+            Some(m) => m,
+            None => self
+                .gen
+                .resume()
+                .flatten()
+                .expect("the generator left the session"),
+        };
+        (downcast(m), self.step())
+    }
+}
+
+impl Session<End> {
+    /// finish the generator
+    pub fn close(mut self) {
+        if !self.gen.is_done() {
+            self.gen.resume();
+        }
+        debug_assert!(self.gen.is_done());
+    }
+}
+
+/// the generator side of a session, at the step `P`
+pub struct Chan<'s, P> {
+    scope: Scope<'s, 'static, Msg, Option<Msg>>,
+    phantom: PhantomData<P>,
+}
+
+impl<'s, P> Chan<'s, P> {
+    fn step<Q>(self) -> Chan<'s, Q> {
+        Chan {
+            scope: self.scope,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'s, T: Any + Send, P> Chan<'s, Tx<T, P>> {
+    /// send the value to the resumer
+    pub fn send(mut self, t: T) -> Chan<'s, P> {
+        self.scope.yield_with(Some(Box::new(t)));
+        self.step()
+    }
+}
+
+impl<'s, A: Any + Send, P> Chan<'s, Rx<A, P>> {
+    /// receive the value sent by the resumer
+    pub fn recv(mut self) -> (A, Chan<'s, P>) {
+        // the para comes with the resume, or wait for the next one
+        let m = match self.scope.get_yield() {
+            Some(m) => m,
+            None => self.scope.yield_(None).expect("no value is sent"),
+        };
+        (downcast(m), self.step())
+    }
+}
@@ -0,0 +1,94 @@
+//! # spawn generators
+//!
+//! drive a generator on a worker and await its return value
+//!
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::gen_impl::Generator;
+use crate::sync::{Arc, Mutex};
+
+// the state shared between the worker and the future
+struct Shared<T> {
+    result: Option<thread::Result<Option<T>>>,
+    waker: Option<Waker>,
+}
+
+impl<A: Send + 'static, T: Send + 'static> Generator<'static, A, T> {
+    /// drive the generator to completion on the worker started by `executor`
+    /// and return a future of its return value
+    ///
+    /// the generator is resumed without para, the future resolves to `None`
+    /// when it finishes by `done!()`, a panic is propagated to the awaiting
+    /// task. Dropping the future doesn't stop the generator
+    ///
+    /// ```
+    /// use generator::Gn;
+    ///
+    /// let fut = Gn::<()>::new_scoped(|mut s| {
+    ///     s.yield_(1);
+    ///     2
+    /// })
+    /// .spawn_detached(|job| {
+    ///     std::thread::spawn(job);
+    /// });
+    /// # let _ = fut;
+    /// ```
+    pub fn spawn_detached<E>(mut self, executor: E) -> Spawned<T>
+    where
+        E: FnOnce(Box<dyn FnOnce() + Send>),
+    {
+        let shared = Arc::new(Mutex::new(Shared {
+            result: None,
+            waker: None,
+        }));
+        let worker = shared.clone();
+        executor(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+                let v = self.resume();
+                if self.is_done() {
+                    return v;
+                }
+            }));
+            let waker = {
+                let mut shared = worker.lock().unwrap();
+                shared.result = Some(result);
+                shared.waker.take()
+            };
+            // the waker may poll the future right away, not under the lock
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }));
+        Spawned { shared }
+    }
+}
+
+/// Future of the return value of a spawned generator, created by
+/// `spawn_detached`
+pub struct Spawned<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for Spawned<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(Ok(v)) => Poll::Ready(v),
+            Some(Err(e)) => {
+                drop(shared);
+                panic::resume_unwind(e)
+            }
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -3,11 +3,13 @@
 //!
 
 use std::error::Error;
+use std::ffi::CStr;
 use std::fmt::{self, Display};
 use std::io;
 use std::mem::MaybeUninit;
 use std::os::raw::c_void;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[cfg_attr(unix, path = "unix.rs")]
 #[cfg_attr(windows, path = "windows.rs")]
@@ -15,9 +17,51 @@ pub mod sys;
 
 pub use sys::overflow;
 
-// must align with StackBoxHeader
-const ALIGN: usize = std::mem::size_of::<StackBoxHeader>();
+pub mod pool;
+pub mod region;
+
+// the default guard region in bytes, 0 for one page
+static DEFAULT_GUARD: AtomicUsize = AtomicUsize::new(0);
+
+/// set the default size in bytes of the guard region below the stacks
+///
+/// a function with a frame larger than the guard can jump over it, the size
+/// is rounded up to the page size and is at least one page
+pub fn set_default_guard_size(bytes: usize) {
+    DEFAULT_GUARD.store(guard_bytes(bytes), Ordering::Relaxed);
+}
+
+/// get the default size in bytes of the guard region below the stacks
+pub fn default_guard_size() -> usize {
+    match DEFAULT_GUARD.load(Ordering::Relaxed) {
+        0 => sys::page_size(),
+        bytes => bytes,
+    }
+}
+
+// round the guard size up to whole pages, at least one
+fn guard_bytes(bytes: usize) -> usize {
+    let page_size = sys::page_size();
+    bytes.max(1).saturating_add(page_size - 1) & !(page_size - 1)
+}
+
+// the entry stack alignment for all the supported arches
+const STACK_ALIGN: usize = 16;
+
+// the stack that must be left for the generator frames in bytes
+#[cfg(not(windows))]
+const MIN_FREE_STACK: usize = 0x800;
+// windows needs more for the exception dispatching
+#[cfg(windows)]
+const MIN_FREE_STACK: usize = 0x4a8 * std::mem::size_of::<usize>();
+
+// must align with StackBoxHeader, it's used as a mask so it must be a power of two
+const ALIGN: usize = std::mem::size_of::<StackBoxHeader>().next_power_of_two();
+// the words at the stack end that may be written by the initial call frame
+const INIT_FRAME_SIZE: usize = 16;
 const HEADER_SIZE: usize = std::mem::size_of::<StackBoxHeader>() / std::mem::size_of::<usize>();
+// the type name of the scratch space in the leak report
+pub(crate) const SCRATCH_NAME: &str = "scratch space";
 
 struct StackBoxHeader {
     // track the stack
@@ -26,6 +70,30 @@ struct StackBoxHeader {
     data_size: usize,
     // non zero dealloc the stack
     need_drop: usize,
+    // the type of the data for the leak report
+    #[cfg(feature = "debug-checks")]
+    type_name: &'static str,
+}
+
+/// a live allocation on a generator stack, see [`Stack::live_boxes`]
+#[cfg(feature = "debug-checks")]
+pub(crate) struct LiveBox {
+    // the address of the data
+    pub addr: usize,
+    pub type_name: &'static str,
+    // the reserved bytes, including the padding
+    pub size: usize,
+}
+
+#[cfg(feature = "debug-checks")]
+impl Display for LiveBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` ({} bytes) at {:#x}",
+            self.type_name, self.size, self.addr
+        )
+    }
 }
 
 /// A pointer type for stack allocation.
@@ -37,31 +105,9 @@ pub struct StackBox<T> {
 impl<T> StackBox<T> {
     /// create uninit stack box
     fn new_uninit(stack: &mut Stack, need_drop: usize) -> MaybeUninit<Self> {
-        // cheat #[warn(clippy::needless_pass_by_ref_mut)]
-        // we need mutable ref for ownership
-        let _ = stack as *mut Stack;
-
-        let offset = unsafe { &mut *stack.get_offset() };
-        // alloc the data
         let layout = std::alloc::Layout::new::<T>();
-        let align = std::cmp::max(layout.align(), ALIGN);
-        let size = ((layout.size() + align - 1) & !(align - 1)) / std::mem::size_of::<usize>();
-        let u_align = align / std::mem::size_of::<usize>();
-        let pad_size = u_align - (*offset + size) % u_align;
-        let data_size = size + pad_size;
-        *offset += data_size;
-        let ptr = unsafe { ptr::NonNull::new_unchecked(stack.end() as *mut T) };
-
-        // init the header
-        *offset += HEADER_SIZE;
-        unsafe {
-            let mut header = ptr::NonNull::new_unchecked(stack.end() as *mut StackBoxHeader);
-            let header = header.as_mut();
-            header.data_size = data_size;
-            header.need_drop = need_drop;
-            header.stack = stack.shadow_clone();
-            MaybeUninit::new(StackBox { ptr })
-        }
+        let ptr = stack.alloc_raw(layout, need_drop, std::any::type_name::<T>());
+        MaybeUninit::new(StackBox { ptr: ptr.cast() })
     }
 
     fn get_header(&self) -> &StackBoxHeader {
@@ -71,6 +117,17 @@ impl<T> StackBox<T> {
         }
     }
 
+    /// keep the stack when the box is dropped, it's never freed
+    ///
+    /// # Safety
+    ///
+    /// `raw` must point to the data of the first box of a stack
+    #[cfg(not(panic = "abort"))]
+    pub(crate) unsafe fn leak_stack(raw: *mut T) {
+        let header = (raw as *mut usize).offset(0 - HEADER_SIZE as isize);
+        (*(header as *mut StackBoxHeader)).need_drop = 0;
+    }
+
     /// move data into the box
     pub(crate) unsafe fn init(&mut self, data: T) {
         ptr::write(self.ptr.as_ptr(), data);
@@ -113,11 +170,33 @@ pub struct Func {
 }
 
 impl Func {
+    // the address of the closure, zero once it's called
+    #[cfg(feature = "debug-checks")]
+    pub(crate) fn addr(&self) -> usize {
+        self.data as usize
+    }
+
     pub fn call_once(mut self) {
         let data = self.data;
         self.data = ptr::null_mut();
         (self.func)(data);
     }
+
+    /// move the data out without calling it
+    ///
+    /// # Safety
+    ///
+    /// `D` must be the type of the data the functor is created with
+    pub(crate) unsafe fn take<D>(mut self) -> D {
+        let data = self.data as *mut D;
+        self.data = ptr::null_mut();
+        data.read()
+    }
+}
+
+/// the data of a functor that's consumed by the call
+pub(crate) trait Entry {
+    fn run(self);
 }
 
 impl Drop for Func {
@@ -129,24 +208,16 @@ impl Drop for Func {
     }
 }
 
-impl<F: FnOnce()> StackBox<F> {
-    fn call_once(data: *mut ()) {
-        unsafe {
-            let data = data as *mut F;
-            let f = data.read();
-            f();
-        }
-    }
-
+impl<T> StackBox<T> {
     fn drop_inner(data: *mut ()) {
         unsafe {
-            let data = data as *mut F;
+            let data = data as *mut T;
             ptr::drop_in_place(data);
         }
     }
 
-    /// create a functor on the stack
-    pub(crate) fn new_fn_once(stack: &mut Stack, data: F) -> Func {
+    // move the data on the stack and erase its type
+    fn new_func(stack: &mut Stack, data: T, func: fn(*mut ())) -> Func {
         unsafe {
             let mut d = Self::new_uninit(stack, 0);
             (*d.as_mut_ptr()).init(data);
@@ -156,7 +227,7 @@ impl<F: FnOnce()> StackBox<F> {
                 data: d.ptr.as_ptr() as *mut (),
                 size: header.data_size + HEADER_SIZE,
                 offset: stack.get_offset(),
-                func: Self::call_once,
+                func,
                 drop: Self::drop_inner,
             };
             std::mem::forget(d);
@@ -165,6 +236,20 @@ impl<F: FnOnce()> StackBox<F> {
     }
 }
 
+impl<E: Entry> StackBox<E> {
+    fn run_entry(data: *mut ()) {
+        unsafe {
+            let data = data as *mut E;
+            data.read().run();
+        }
+    }
+
+    /// create a functor on the stack whose data can be taken back
+    pub(crate) fn new_entry(stack: &mut Stack, data: E) -> Func {
+        Self::new_func(stack, data, Self::run_entry)
+    }
+}
+
 impl<T> std::ops::Deref for StackBox<T> {
     type Target = T;
 
@@ -183,8 +268,9 @@ impl<T> Drop for StackBox<T> {
     fn drop(&mut self) {
         let header = self.get_header();
         unsafe {
-            *header.stack.get_offset() -= header.data_size + HEADER_SIZE;
+            // the data is still live while it's dropped
             ptr::drop_in_place(self.ptr.as_ptr());
+            *header.stack.get_offset() -= header.data_size + HEADER_SIZE;
             if header.need_drop != 0 {
                 header.stack.drop_stack();
             }
@@ -199,6 +285,9 @@ pub enum StackError {
     ExceedsMaximumSize(usize),
 
     /// Returned if some kind of I/O error happens during allocation.
+    ///
+    /// A stack too small to hold the generator is `InvalidInput`, and going
+    /// over `RLIMIT_MEMLOCK` by `mlock` is `OutOfMemory`.
     IoError(io::Error),
 }
 
@@ -269,57 +358,194 @@ impl SysStack {
         sys::min_stack_size()
     }
 
-    /// Allocates a new stack of `size`.
-    fn allocate(mut size: usize, protected: bool) -> Result<SysStack, StackError> {
+    /// Returns the usable size of a stack allocated for `size` bytes.
+    ///
+    /// It's at least `min_size()` and rounded up to the page size.
+    #[inline]
+    pub fn round_size(size: usize) -> Option<usize> {
         let page_size = sys::page_size();
-        let min_stack_size = sys::min_stack_size();
-        let max_stack_size = sys::max_stack_size();
-        let add_shift = i32::from(protected);
-        let add = page_size << add_shift;
-
-        if size < min_stack_size {
-            size = min_stack_size;
-        }
+        let size = usize::max(size, Self::min_size());
+        Some(size.checked_add(page_size - 1)? & !(page_size - 1))
+    }
 
-        size = (size - 1) & !(page_size.overflowing_sub(1).0);
+    /// Allocates a new stack of `size` with a guard region of `guard` bytes,
+    /// no guard if it's zero.
+    fn allocate(size: usize, guard: usize) -> Result<SysStack, StackError> {
+        let max_stack_size = sys::max_stack_size();
+        let protected = guard != 0;
+        let add = guard;
 
-        if let Some(size) = size.checked_add(add) {
+        if let Some(size) = Self::round_size(size).and_then(|size| size.checked_add(add)) {
             if size <= max_stack_size {
-                let mut ret = unsafe { sys::allocate_stack(size) };
-
-                if protected {
-                    if let Ok(stack) = ret {
-                        ret = unsafe { sys::protect_stack(&stack) };
+                #[cfg(feature = "deterministic")]
+                let addr = crate::deterministic::next_stack_addr(size);
+                #[cfg(not(feature = "deterministic"))]
+                let addr = None;
+                let carved = if protected && addr.is_none() {
+                    region::carve(size, guard)
+                } else {
+                    None
+                };
+                let ret = carved.unwrap_or_else(|| {
+                    let mut ret = unsafe { sys::allocate_stack(size, addr) };
+                    if protected {
+                        if let Ok(stack) = ret {
+                            ret = unsafe { sys::protect_stack(&stack, guard) };
+                        }
                     }
+                    ret
+                });
+
+                // before any page is faulted
+                #[cfg(feature = "numa")]
+                if let Ok(stack) = &ret {
+                    sys::bind_stack(stack, sys::current_node());
                 }
 
                 return ret.map_err(StackError::IoError);
             }
         }
 
-        Err(StackError::ExceedsMaximumSize(max_stack_size - add))
+        Err(StackError::ExceedsMaximumSize(
+            max_stack_size.saturating_sub(add),
+        ))
+    }
+
+    /// Reserves a new stack of `size` with a guard region of `guard` bytes,
+    /// only the top `commit` bytes are committed.
+    #[cfg(windows)]
+    fn allocate_lazy(size: usize, guard: usize, commit: usize) -> Result<SysStack, StackError> {
+        let max_stack_size = sys::max_stack_size();
+        match Self::round_size(size).and_then(|size| size.checked_add(guard)) {
+            Some(size) if size <= max_stack_size => unsafe {
+                sys::allocate_lazy_stack(size, guard, commit).map_err(StackError::IoError)
+            },
+            _ => Err(StackError::ExceedsMaximumSize(
+                max_stack_size.saturating_sub(guard),
+            )),
+        }
     }
 }
 
 unsafe impl Send for SysStack {}
 
+/// the options of a new stack, see `Builder`
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct StackConfig {
+    // lock the stack in memory
+    pub mlock: bool,
+    // fault in all the pages up front
+    pub prefault: bool,
+    // the guard region in bytes, 0 for the default one
+    pub guard: usize,
+    // commit the stack on demand like a thread stack, windows only
+    pub lazy: bool,
+    // the stack left for the overflow handlers, windows only
+    pub guarantee: usize,
+    // the bytes reserved for `Scope::alloc`
+    pub scratch: usize,
+    // the alignment of a heap buffer that only holds the generator data, 0
+    // for a system stack. There is no guard and it's never switched to
+    pub heap_align: usize,
+}
+
 /// generator stack
 /// this struct will not dealloc the memory
 /// instead StackBox<> would track it's usage and dealloc it
+///
+/// the stack pointer is aligned to [`Stack::alignment`] at the generator
+/// entry, after the generator state is placed at the top of the stack
 pub struct Stack {
     buf: SysStack,
+    // the full stack usage is tracked
+    track: bool,
+    config: StackConfig,
 }
 
 impl Stack {
-    /// Allocate a new stack of `size`. If size = 0, this is a `dummy_stack`
-    pub fn new(size: usize) -> Stack {
+    /// the stack pointer alignment at the generator entry in bytes
+    ///
+    /// it's the largest alignment required by the supported ABIs, the
+    /// locals that need a larger alignment are realigned by the compiler
+    pub const fn alignment() -> usize {
+        STACK_ALIGN
+    }
+
+    /// Allocate a new stack of `size` words
+    #[cfg(test)]
+    pub(crate) fn new(size: usize) -> Stack {
+        Self::try_new(size, 0, StackConfig::default())
+            .unwrap_or_else(|e| panic!("failed to alloc sys stack: {e}"))
+    }
+
+    /// Allocate a new stack of `size` words that has room for `reserve` bytes
+    /// of generator data besides the minimal free stack for the frames
+    pub(crate) fn try_new(
+        size: usize,
+        reserve: usize,
+        mut config: StackConfig,
+    ) -> Result<Stack, StackError> {
         let track = (size & 1) != 0;
+        config.guard = match config.guard {
+            0 => default_guard_size(),
+            bytes => guard_bytes(bytes),
+        };
+        config.scratch = (config.scratch + ALIGN - 1) & !(ALIGN - 1);
+        // the OS only keeps the guarantee while it grows the stack
+        if config.guarantee != 0 {
+            config.guarantee = guard_bytes(config.guarantee);
+            config.lazy = true;
+        }
+        // the other OSes commit the pages on the first touch anyway, and the
+        // locked or pre-faulted stacks are fully committed
+        config.lazy &= cfg!(windows) && !config.mlock && !config.prefault;
+        #[cfg(feature = "deterministic")]
+        if crate::deterministic::is_enabled() {
+            config.lazy = false;
+        }
 
-        let bytes = usize::max(size * std::mem::size_of::<usize>(), SysStack::min_size());
+        let too_big = || StackError::ExceedsMaximumSize(sys::max_stack_size());
+        let bytes = size
+            .checked_mul(std::mem::size_of::<usize>())
+            .ok_or_else(too_big)?;
+        let usable = SysStack::round_size(bytes).ok_or_else(too_big)?;
+        // the offset word and the data headers are also on the stack
+        let need = reserve + MIN_FREE_STACK + 4 * ALIGN + config.guarantee + config.scratch;
+        if usable < need {
+            return Err(StackError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Stack of {usable} bytes is too small for the generator, \
+                     need at least {need} bytes"
+                ),
+            )));
+        }
 
-        let buf = SysStack::allocate(bytes, true).expect("failed to alloc sys stack");
+        // the lazy stacks are never pooled, they are partly committed
+        #[cfg(windows)]
+        let lazy = if config.lazy {
+            Some(SysStack::allocate_lazy(bytes, config.guard, need)?)
+        } else {
+            None
+        };
+        #[cfg(not(windows))]
+        let lazy = None;
+        let buf = match lazy.or_else(|| pool::take(usable, config.guard)) {
+            Some(buf) => buf,
+            None => SysStack::allocate(bytes, config.guard)?,
+        };
+        if config.mlock {
+            if let Err(e) = unsafe { sys::lock_stack(&buf) } {
+                deallocate(&buf, config.guard);
+                return Err(e);
+            }
+        }
+        if config.prefault {
+            prefault(&buf);
+        }
+        sys::name_stack(&buf, None);
 
-        let stk = Stack { buf };
+        let stk = Stack { buf, track, config };
 
         // if size is not even we do the full foot print test
         let count = if track {
@@ -329,20 +555,56 @@ impl Stack {
             8
         };
 
-        unsafe {
-            let buf = stk.buf.bottom as *mut usize;
-            ptr::write_bytes(buf, 0xEE, count);
+        // the usage of a lazy stack is its committed part
+        if !config.lazy {
+            unsafe {
+                let buf = stk.buf.bottom as *mut usize;
+                ptr::write_bytes(buf, 0xEE, count);
+            }
         }
 
         // init the stack box usage
         let offset = stk.get_offset();
         unsafe { *offset = 1 };
 
+        Ok(stk)
+    }
+
+    /// Allocate a heap buffer for `reserve` bytes of generator data aligned
+    /// to `align`, it has no room for the frames, so nothing can run on it
+    pub(crate) fn new_heap(reserve: usize, align: usize) -> Stack {
+        let align = align.max(ALIGN);
+        let bytes = (reserve + 4 * ALIGN + align - 1) & !(align - 1);
+        let layout = std::alloc::Layout::from_size_align(bytes, align).unwrap();
+        let bottom = unsafe { std::alloc::alloc(layout) };
+        if bottom.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        let buf = unsafe { SysStack::new(bottom.add(bytes).cast(), bottom.cast()) };
+        let config = StackConfig {
+            heap_align: align,
+            ..StackConfig::default()
+        };
+        let stk = Stack {
+            buf,
+            track: false,
+            config,
+        };
+        unsafe { *stk.get_offset() = 1 };
         stk
     }
 
-    /// get used stack size
-    pub fn get_used_size(&self) -> usize {
+    // get the number of untouched words from the low end
+    fn untouched_size(&self) -> usize {
+        // the frames never touch a heap buffer
+        if self.config.heap_align != 0 {
+            return self.size();
+        }
+        #[cfg(windows)]
+        if self.config.lazy {
+            let committed = sys::committed_bottom(&self.buf);
+            return (committed - self.buf.bottom as usize) / std::mem::size_of::<usize>();
+        }
         let mut offset: usize = 0;
         unsafe {
             let mut magic: usize = 0xEE;
@@ -353,29 +615,246 @@ impl Stack {
                 ptr = ptr.offset(1);
             }
         }
-        let cap = self.size();
-        cap - offset
+        offset
+    }
+
+    /// check the lowest foot print word, it's only touched by an overflow
+    #[cfg(feature = "debug-checks")]
+    pub(crate) fn canary_intact(&self) -> bool {
+        let mut magic: usize = 0;
+        unsafe { ptr::write_bytes(&mut magic, 0xEE, 1) };
+        self.config.lazy || self.config.heap_align != 0 || unsafe { *self.begin() } == magic
+    }
+
+    /// get used stack size
+    pub(crate) fn get_used_size(&self) -> usize {
+        self.size() - self.untouched_size()
+    }
+
+    /// free all the allocations after the stack box at `data` and restore
+    /// the usage foot print, so the stack can be reused for a new run
+    ///
+    /// # Safety
+    ///
+    /// `data` must be a stack box on this stack, all the allocations and
+    /// frames after it must be dead
+    pub(crate) unsafe fn release_after<T>(&mut self, data: *const T) {
+        let end = (data as *const usize).offset(-(HEADER_SIZE as isize));
+        *self.get_offset() = (self.buf.top as usize - end as usize) / std::mem::size_of::<usize>();
+        // the committed pages stay, there is no foot print
+        if self.config.lazy || self.config.heap_align != 0 {
+            return;
+        }
+        // the last few words are only reached by a run close to the overflow
+        let magic = usize::from_ne_bytes([0xEE; std::mem::size_of::<usize>()]);
+        let refill = self.track || *self.begin().add(7) != magic;
+        #[cfg(feature = "deterministic")]
+        let refill = refill || crate::deterministic::is_enabled();
+        if refill {
+            self.refill_footprint(end);
+        }
+    }
+
+    // restore the usage foot print below `end` for a new run
+    #[cold]
+    unsafe fn refill_footprint(&mut self, end: *const usize) {
+        #[cfg(feature = "deterministic")]
+        if crate::deterministic::is_enabled() {
+            // look the same as a new stack
+            let words = (end as usize - self.buf.bottom as usize) / std::mem::size_of::<usize>();
+            ptr::write_bytes(self.begin(), 0, words);
+            ptr::write_bytes(self.begin(), 0xEE, if self.track { words } else { 8 });
+            return;
+        }
+
+        let (start, count) = if self.track {
+            // only refill the touched part
+            let start = self.untouched_size();
+            (
+                start,
+                (end as usize - self.buf.bottom as usize) / std::mem::size_of::<usize>() - start,
+            )
+        } else {
+            (0, 8)
+        };
+        ptr::write_bytes(self.begin().add(start), 0xEE, count);
+    }
+
+    /// fill the usage foot print of the whole free stack of a not started
+    /// generator, so the usage can be measured without the full tracking
+    pub(crate) fn fill_footprint(&self) {
+        if self.config.lazy || self.config.heap_align != 0 {
+            return;
+        }
+        // leave the initial call frame at the end alone
+        let free = (self.end() as usize - self.begin() as usize) / std::mem::size_of::<usize>();
+        let count = free.saturating_sub(INIT_FRAME_SIZE);
+        unsafe { ptr::write_bytes(self.begin(), 0xEE, count) };
+    }
+
+    /// list the live stack boxes from the newest one
+    #[cfg(feature = "debug-checks")]
+    pub(crate) fn live_boxes(&self) -> Vec<LiveBox> {
+        // the boxes are packed from the end up to the offset word
+        let last = self.get_offset();
+        let mut header = self.end();
+        let mut boxes = Vec::new();
+        while header < last {
+            unsafe {
+                let h = &*(header as *const StackBoxHeader);
+                let data = header.add(HEADER_SIZE);
+                boxes.push(LiveBox {
+                    addr: data as usize,
+                    type_name: h.type_name,
+                    size: h.data_size * std::mem::size_of::<usize>(),
+                });
+                header = data.add(h.data_size);
+            }
+        }
+        boxes
+    }
+
+    /// label the stack mapping with the generator name for the OS tools
+    pub(crate) fn set_name(&self, name: &CStr) {
+        if self.config.heap_align == 0 {
+            sys::name_stack(&self.buf, Some(name));
+        }
+    }
+
+    /// get the bytes of the stack that are resident in memory
+    #[cfg(feature = "stack-rss")]
+    pub(crate) fn resident_bytes(&self) -> io::Result<usize> {
+        if self.config.heap_align != 0 {
+            return Ok(self.buf.len());
+        }
+        sys::resident_bytes(&self.buf)
     }
 
     /// get the stack cap
     #[inline]
-    pub fn size(&self) -> usize {
+    pub(crate) fn size(&self) -> usize {
         self.buf.len() / std::mem::size_of::<usize>()
     }
 
+    /// get the size of the guard region below the stack in bytes
+    pub(crate) fn guard_size(&self) -> usize {
+        self.config.guard
+    }
+
+    /// Point to the low end of the committed stack, it's the stack limit of
+    /// the thread environment block while the generator runs
+    #[cfg(windows)]
+    pub(crate) fn committed_begin(&self) -> *mut usize {
+        if self.config.lazy {
+            sys::committed_bottom(&self.buf) as *mut usize
+        } else {
+            self.begin()
+        }
+    }
+
+    /// get the low end the OS grows a lazy stack down to, the guard region
+    /// below it is never committed, 0 for the fully committed stacks
+    #[cfg(windows)]
+    pub(crate) fn alloc_base(&self) -> usize {
+        if self.config.lazy {
+            self.buf.bottom as usize
+        } else {
+            0
+        }
+    }
+
+    /// get the guaranteed stack bytes for the overflow handlers
+    #[cfg(windows)]
+    pub(crate) fn guarantee(&self) -> usize {
+        self.config.guarantee
+    }
+
+    /// get the options to allocate another stack like this one
+    pub(crate) fn config(&self) -> StackConfig {
+        self.config
+    }
+
+    /// get the size in words to allocate another stack like this one
+    pub(crate) fn request_size(&self) -> usize {
+        // an odd size turns on the tracking, the size is page aligned
+        self.size() - usize::from(self.track)
+    }
+
     /// Point to the high end of the allocated stack
-    pub fn end(&self) -> *mut usize {
+    pub(crate) fn end(&self) -> *mut usize {
         let offset = self.get_offset();
         unsafe { (self.buf.top as *mut usize).offset(0 - *offset as isize) }
     }
 
     /// Point to the low end of the allocated stack
-    pub fn begin(&self) -> *mut usize {
+    pub(crate) fn begin(&self) -> *mut usize {
         self.buf.bottom as *mut _
     }
 
+    /// reserve the scratch space of `Scope::alloc` below the boxes, returns
+    /// its low and high end
+    ///
+    /// it's released with the boxes by `release_after`
+    pub(crate) fn alloc_scratch(&mut self) -> (usize, usize) {
+        let bytes = self.config.scratch;
+        if bytes == 0 {
+            return (0, 0);
+        }
+        let layout = std::alloc::Layout::from_size_align(bytes, ALIGN).unwrap();
+        let bottom = self.alloc_raw(layout, 0, SCRATCH_NAME).as_ptr() as usize;
+        (bottom, bottom + bytes)
+    }
+
+    // alloc the data and its header below the boxes
+    fn alloc_raw(
+        &mut self,
+        layout: std::alloc::Layout,
+        need_drop: usize,
+        type_name: &'static str,
+    ) -> ptr::NonNull<u8> {
+        let offset = unsafe { &mut *self.get_offset() };
+        // alloc the data
+        let align = std::cmp::max(layout.align(), ALIGN);
+        let size = ((layout.size() + align - 1) & !(align - 1)) / std::mem::size_of::<usize>();
+        let u_align = align / std::mem::size_of::<usize>();
+        let pad_size = u_align - (*offset + size) % u_align;
+        let data_size = size + pad_size;
+        assert!(
+            *offset + data_size + HEADER_SIZE <= self.size(),
+            "generator stack of {} bytes is too small to hold the generator data",
+            self.size() * std::mem::size_of::<usize>()
+        );
+        *offset += data_size;
+        let ptr = unsafe { ptr::NonNull::new_unchecked(self.end() as *mut u8) };
+
+        // init the header
+        *offset += HEADER_SIZE;
+        // the boxes are also placed from outside of the generator, where the
+        // guard page doesn't grow the commit
+        #[cfg(windows)]
+        if self.config.lazy {
+            if let Err(e) = unsafe { sys::commit_stack_to(&self.buf, self.end() as usize) } {
+                panic!("failed to commit the generator stack: {e}");
+            }
+        }
+        unsafe {
+            let mut header = ptr::NonNull::new_unchecked(self.end() as *mut StackBoxHeader);
+            let header = header.as_mut();
+            header.data_size = data_size;
+            header.need_drop = need_drop;
+            header.stack = self.shadow_clone();
+            #[cfg(feature = "debug-checks")]
+            {
+                header.type_name = type_name;
+            }
+            #[cfg(not(feature = "debug-checks"))]
+            let _ = type_name;
+        }
+        ptr
+    }
+
     /// alloc buffer on this stack
-    pub fn alloc_uninit_box<T>(&mut self) -> MaybeUninit<StackBox<T>> {
+    pub(crate) fn alloc_uninit_box<T>(&mut self) -> MaybeUninit<StackBox<T>> {
         // the first obj should set need drop to non zero
         StackBox::<T>::new_uninit(self, 1)
     }
@@ -385,16 +864,30 @@ impl Stack {
         unsafe { (self.buf.top as *mut usize).offset(-1) }
     }
 
-    // dealloc the stack
+    // dealloc the stack, or keep it in the pool
     fn drop_stack(&self) {
         if self.buf.len() == 0 {
             return;
         }
-        let page_size = sys::page_size();
-        let guard = (self.buf.bottom as usize - page_size) as *mut c_void;
-        let size_with_guard = self.buf.len() + page_size;
-        unsafe {
-            sys::deallocate_stack(guard, size_with_guard);
+        if self.config.heap_align != 0 {
+            let layout =
+                std::alloc::Layout::from_size_align(self.buf.len(), self.config.heap_align)
+                    .unwrap();
+            unsafe { std::alloc::dealloc(self.buf.bottom.cast(), layout) };
+            return;
+        }
+        let buf = SysStack {
+            top: self.buf.top,
+            bottom: self.buf.bottom,
+        };
+        // the pooled stacks are not locked
+        if self.config.mlock {
+            unsafe { sys::unlock_stack(&buf) };
+        }
+        if self.config.lazy {
+            deallocate(&buf, self.config.guard);
+        } else if let Some(buf) = pool::put(buf, self.config.guard) {
+            deallocate(&buf, self.config.guard);
         }
     }
 
@@ -404,13 +897,120 @@ impl Stack {
                 top: self.buf.top,
                 bottom: self.buf.bottom,
             },
+            track: self.track,
+            config: self.config,
         }
     }
 }
 
+// the NUMA node a stack is placed on, or to place a new stack on
+#[inline]
+fn current_node() -> usize {
+    #[cfg(feature = "numa")]
+    return sys::current_node();
+    #[cfg(not(feature = "numa"))]
+    0
+}
+
+#[inline]
+fn stack_node(_buf: &SysStack) -> usize {
+    #[cfg(feature = "numa")]
+    return sys::stack_node(_buf);
+    #[cfg(not(feature = "numa"))]
+    0
+}
+
+// touch every page of the stack
+fn prefault(buf: &SysStack) {
+    let page_size = sys::page_size();
+    let mut p = buf.bottom as usize;
+    while p < buf.top as usize {
+        unsafe { ptr::write_volatile(p as *mut u8, 0) };
+        p += page_size;
+    }
+}
+
+// dealloc a protected stack with its guard region of `guard` bytes
+fn deallocate(buf: &SysStack, guard: usize) {
+    if region::release(buf, guard) {
+        return;
+    }
+    let bottom = (buf.bottom as usize - guard) as *mut c_void;
+    let size_with_guard = buf.len() + guard;
+    unsafe {
+        sys::deallocate_stack(bottom, size_with_guard);
+    }
+}
+
 impl fmt::Debug for Stack {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let offset = self.get_offset();
         write!(f, "Stack<{:?}, Offset={}>", self.buf, unsafe { *offset })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Stack, StackBox};
+
+    #[test]
+    fn test_box_align() {
+        #[repr(align(16))]
+        struct A16(#[allow(dead_code)] u8);
+
+        let mut stack = Stack::new(0x1000);
+        let first = unsafe { stack.alloc_uninit_box::<usize>().assume_init() };
+        // the boxes after the first one don't free the stack
+        let a = unsafe { StackBox::<u8>::new_uninit(&mut stack, 0).assume_init() };
+        let b = unsafe { StackBox::<A16>::new_uninit(&mut stack, 0).assume_init() };
+        let c = unsafe { StackBox::<A16>::new_uninit(&mut stack, 0).assume_init() };
+        assert_eq!(b.as_ptr() as usize % 16, 0);
+        assert_eq!(c.as_ptr() as usize % 16, 0);
+        // the uninit data is not dropped
+        std::mem::forget((c, b, a));
+        drop(first);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-checks")]
+    fn test_live_boxes() {
+        let mut stack = Stack::new(0x1000);
+        let first = unsafe { stack.alloc_uninit_box::<usize>().assume_init() };
+        assert_eq!(stack.live_boxes().len(), 1);
+
+        let leak = unsafe { StackBox::<[u32; 5]>::new_uninit(&mut stack, 0).assume_init() };
+        let boxes = stack.live_boxes();
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].addr, leak.as_ptr() as usize);
+        assert_eq!(boxes[0].type_name, "[u32; 5]");
+        assert!(boxes[0].size >= 20);
+        assert_eq!(boxes[1].addr, first.as_ptr() as usize);
+        assert_eq!(boxes[1].type_name, "usize");
+
+        drop(leak);
+        assert_eq!(stack.live_boxes().len(), 1);
+        drop(first);
+    }
+
+    #[test]
+    #[cfg(feature = "deterministic")]
+    fn test_release_zero_fill() {
+        crate::deterministic::enable(3);
+        let mut stack = Stack::new(0x1000);
+        let mut data = unsafe { stack.alloc_uninit_box::<usize>().assume_init() };
+        unsafe { data.init(1) };
+
+        // dirty the free stack and release it
+        let free = (stack.end() as usize - stack.begin() as usize) / std::mem::size_of::<usize>();
+        unsafe { std::ptr::write_bytes(stack.begin(), 0x5a, free) };
+        unsafe { stack.release_after(data.as_ptr()) };
+
+        let words = unsafe { std::slice::from_raw_parts(stack.begin(), free) };
+        let magic = usize::from_ne_bytes([0xEE; std::mem::size_of::<usize>()]);
+        assert!(words[..8].iter().all(|&w| w == magic));
+        assert!(words[8..].iter().all(|&w| w == 0));
+
+        crate::deterministic::disable();
+        drop(data);
+    }
+}
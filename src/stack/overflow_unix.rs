@@ -2,6 +2,7 @@ use crate::rt::{guard, ContextStack};
 
 use crate::yield_::yield_now;
 use libc::{sigaction, sighandler_t, SA_ONSTACK, SA_SIGINFO, SIGBUS, SIGSEGV};
+use std::cell::RefCell;
 use std::mem;
 use std::mem::MaybeUninit;
 use std::ptr::null_mut;
@@ -42,12 +43,8 @@ unsafe extern "C" fn signal_handler(
         return;
     }
 
-    eprintln!(
-        "\ncoroutine in thread '{}' has overflowed its stack\n",
-        std::thread::current().name().unwrap_or("<unknown>")
-    );
-
-    ContextStack::current().top().err = Some(Box::new(crate::Error::StackErr));
+    // the resumer reports the details and raises the panic, see `resume_gen`
+    ContextStack::current().top().err = Some(Box::new(crate::rt::GuardFault));
 
     let mut sigset: libc::sigset_t = mem::zeroed();
     libc::sigemptyset(&mut sigset);
@@ -64,7 +61,7 @@ unsafe fn init() {
     let mut action: sigaction = mem::zeroed();
 
     action.sa_flags = SA_SIGINFO | SA_ONSTACK;
-    action.sa_sigaction = signal_handler as sighandler_t;
+    action.sa_sigaction = signal_handler as *const () as sighandler_t;
 
     let mut old_action = SIG_ACTION.lock().unwrap();
 
@@ -73,10 +70,73 @@ unsafe fn init() {
     }
 }
 
+// the alternate signal stack of a thread, the handler can't run on the
+// overflowed stack
+struct AltStack {
+    ptr: *mut libc::c_void,
+    size: usize,
+}
+
+impl AltStack {
+    // set up one if the thread doesn't have it, std only does it for the
+    // threads it spawns
+    unsafe fn install() -> Option<AltStack> {
+        let mut old: libc::stack_t = mem::zeroed();
+        libc::sigaltstack(null_mut(), &mut old);
+        if old.ss_flags & libc::SS_DISABLE == 0 {
+            return None;
+        }
+
+        let size = libc::SIGSTKSZ.max(0x4000);
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        let ptr = libc::mmap(null_mut(), size, prot, flags, -1, 0);
+        if ptr == libc::MAP_FAILED {
+            return None;
+        }
+        let stack = libc::stack_t {
+            ss_sp: ptr,
+            ss_flags: 0,
+            ss_size: size,
+        };
+        libc::sigaltstack(&stack, null_mut());
+        Some(AltStack { ptr, size })
+    }
+}
+
+impl Drop for AltStack {
+    fn drop(&mut self) {
+        unsafe {
+            let stack = libc::stack_t {
+                ss_sp: null_mut(),
+                ss_flags: libc::SS_DISABLE,
+                ss_size: self.size,
+            };
+            libc::sigaltstack(&stack, null_mut());
+            libc::munmap(self.ptr, self.size);
+        }
+    }
+}
+
+thread_local! {
+    // `Some(None)` if the thread has its own one
+    static ALT_STACK: RefCell<Option<Option<AltStack>>> = const { RefCell::new(None) };
+}
+
+/// install the handler for the process and the alternate signal stack for
+/// the current thread
 pub fn init_once() {
     static INIT_ONCE: Once = Once::new();
 
     INIT_ONCE.call_once(|| unsafe {
         init();
-    })
+    });
+
+    // the thread local may be gone while the thread exits
+    let _ = ALT_STACK.try_with(|alt| {
+        let mut alt = alt.borrow_mut();
+        if alt.is_none() {
+            *alt = Some(unsafe { AltStack::install() });
+        }
+    });
 }
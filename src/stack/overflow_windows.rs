@@ -15,15 +15,12 @@ unsafe extern "system" fn vectored_handler(exception_info: *mut EXCEPTION_POINTE
 
     if rec.ExceptionCode == EXCEPTION_STACK_OVERFLOW
         && guard::current().contains(&(context.Rsp as usize))
+        && stack_guarantee() == 0
     {
-        eprintln!(
-            "\ncoroutine in thread '{}' has overflowed its stack\n",
-            std::thread::current().name().unwrap_or("<unknown>")
-        );
-
+        // the resumer reports the details and raises the panic, see `resume_gen`
         let env = ContextStack::current();
         let cur = env.top();
-        cur.err = Some(Box::new(crate::Error::StackErr));
+        cur.err = Some(Box::new(crate::rt::GuardFault));
 
         context_init(env.pop_context(cur as *mut _), context);
 
@@ -35,6 +32,15 @@ unsafe extern "system" fn vectored_handler(exception_info: *mut EXCEPTION_POINTE
     }
 }
 
+// the guaranteed stack bytes of the running generator, the OS raises the
+// overflow with them left for the handlers of the program like on a thread
+#[cfg(target_arch = "x86_64")]
+unsafe fn stack_guarantee() -> u32 {
+    let teb: usize;
+    std::arch::asm!("mov {0}, gs:[0x30]", out(reg) teb);
+    *((teb + 0x1748) as *const u32)
+}
+
 unsafe fn init() {
     AddVectoredExceptionHandler(1, Some(vectored_handler));
 }
@@ -49,7 +55,7 @@ pub fn init_once() {
 
 #[cfg(target_arch = "x86_64")]
 unsafe fn context_init(parent: &mut Context, context: &mut CONTEXT) {
-    let [rbx, rsp, rbp, _, r12, r13, r14, r15, _, _, _, stack_base, stack_limit, dealloc_stack, ..] =
+    let [rbx, rsp, rbp, _, r12, r13, r14, r15, _, _, _, stack_base, stack_limit, dealloc_stack, _, guarantee] =
         parent.regs.regs.gpr;
 
     let rip = *(rsp as *const usize);
@@ -76,4 +82,5 @@ unsafe fn context_init(parent: &mut Context, context: &mut CONTEXT) {
     *((teb + 0x08) as *mut usize) = stack_base;
     *((teb + 0x10) as *mut usize) = stack_limit;
     *((teb + 0x1478) as *mut usize) = dealloc_stack;
+    *((teb + 0x1748) as *mut u32) = guarantee as u32;
 }
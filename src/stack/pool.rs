@@ -0,0 +1,424 @@
+//! # stack pool
+//!
+//! keep the freed generator stacks around to skip the mmap and the page
+//! faults of the next generator with the same stack size
+//!
+//! the pool is process wide and keeps nothing by default, a size class
+//! starts pooling after it's warmed by [`warm`]. The stacks are not pooled
+//! in the deterministic test mode
+//!
+//! with the `numa` feature each NUMA node has its own free lists, a new
+//! generator only gets a stack placed on the node of its creating thread
+//!
+//! the pool is tuned at runtime by [`set_max`] and [`set_trim_policy`], and
+//! observed by [`stats`] and [`class_stats`]
+//!
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{current_node, default_guard_size, stack_node, sys, StackError, SysStack};
+
+// the free stacks of the same usable and guard size on a NUMA node
+struct Class {
+    size: usize,
+    guard: usize,
+    node: usize,
+    // the max number of the pooled stacks
+    cap: usize,
+    // the stacks with the time they are pooled, the oldest first
+    free: Vec<(SysStack, Instant)>,
+    hits: u64,
+    misses: u64,
+    trimmed: u64,
+    peak: usize,
+}
+
+impl Class {
+    fn new(size: usize, guard: usize, node: usize) -> Self {
+        Class {
+            size,
+            guard,
+            node,
+            cap: 0,
+            free: Vec::new(),
+            hits: 0,
+            misses: 0,
+            trimmed: 0,
+            peak: 0,
+        }
+    }
+}
+
+struct Pool {
+    classes: Vec<Class>,
+    policy: TrimPolicy,
+    // the high-water marks of the whole pool
+    peak: usize,
+    peak_bytes: usize,
+}
+
+impl Pool {
+    fn class(&mut self, size: usize, guard: usize, node: usize) -> &mut Class {
+        let i = match self
+            .classes
+            .iter()
+            .position(|c| c.size == size && c.guard == guard && c.node == node)
+        {
+            Some(i) => i,
+            None => {
+                ACTIVE.store(true, Ordering::Relaxed);
+                self.classes.push(Class::new(size, guard, node));
+                self.classes.len() - 1
+            }
+        };
+        &mut self.classes[i]
+    }
+
+    // update the high-water marks after a stack is pooled
+    fn track_peak(&mut self) {
+        let (pooled, bytes) = self.classes.iter().fold((0, 0), |(n, b), c| {
+            (n + c.free.len(), b + c.free.len() * c.size)
+        });
+        self.peak = self.peak.max(pooled);
+        self.peak_bytes = self.peak_bytes.max(bytes);
+    }
+
+    // take out the stacks over the cap or idle for too long
+    fn expire(&mut self, now: Instant) -> Vec<(SysStack, usize)> {
+        let mut freed = Vec::new();
+        for c in &mut self.classes {
+            let over = c.free.len().saturating_sub(c.cap);
+            let idle = match self.policy {
+                TrimPolicy::Keep => 0,
+                TrimPolicy::Idle(timeout) => c
+                    .free
+                    .iter()
+                    .take_while(|(_, t)| now.duration_since(*t) >= timeout)
+                    .count(),
+            };
+            let n = over.max(idle);
+            c.trimmed += n as u64;
+            freed.extend(c.free.drain(..n).map(|(s, _)| (s, c.guard)));
+        }
+        freed
+    }
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    classes: Vec::new(),
+    policy: TrimPolicy::Keep,
+    peak: 0,
+    peak_bytes: 0,
+});
+
+// set once a size class is made by `warm` or `set_max`, so the generators
+// skip the lock while nothing is pooled, the classes are only read under it
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn lock() -> std::sync::MutexGuard<'static, Pool> {
+    // a stack list is always valid, even after a panic
+    POOL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// free the stacks taken out of the pool, without the lock
+fn free(stacks: Vec<(SysStack, usize)>) -> usize {
+    let n = stacks.len();
+    for (stack, guard) in stacks {
+        super::deallocate(&stack, guard);
+    }
+    n
+}
+
+// the usable bytes of a stack of `size` words
+fn class_size(size: usize) -> Option<usize> {
+    SysStack::round_size(size.checked_mul(std::mem::size_of::<usize>())?)
+}
+
+#[inline]
+fn enabled() -> bool {
+    #[cfg(feature = "deterministic")]
+    if crate::deterministic::is_enabled() {
+        return false;
+    }
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// when the pooled stacks are given back to the OS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimPolicy {
+    /// keep the stacks up to the max of their class, the default
+    Keep,
+    /// also free the stacks not used for the duration, it's checked when a
+    /// stack is taken or put back, and by [`trim`]
+    Idle(Duration),
+}
+
+/// the counters and the high-water marks of the pool, see [`stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// the stacks taken from the pool
+    pub hits: u64,
+    /// the stacks allocated for a pooled size class with no free stack
+    pub misses: u64,
+    /// the stacks freed over the max or by the trim policy
+    pub trimmed: u64,
+    /// the stacks in the pool
+    pub pooled: usize,
+    /// the usable bytes of the stacks in the pool
+    pub pooled_bytes: usize,
+    /// the most stacks in the pool at a time
+    pub peak_pooled: usize,
+    /// the most bytes in the pool at a time
+    pub peak_bytes: usize,
+}
+
+impl PoolStats {
+    /// get the ratio of the hits to all the requests, 0 without a request
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+/// pre-allocate and pre-fault `count` stacks of `size` words for the
+/// generators created with the same stack size, e.g. by `Gn::new_scoped_opt`
+///
+/// the size class keeps up to `count` freed stacks from now on, the stacks
+/// are placed on the NUMA node of the current thread with `numa`. They have
+/// the default guard size, see `set_default_guard_size`
+pub fn warm(count: usize, size: usize) -> Result<(), StackError> {
+    let too_big = || StackError::ExceedsMaximumSize(sys::max_stack_size());
+    let bytes = size
+        .checked_mul(std::mem::size_of::<usize>())
+        .ok_or_else(too_big)?;
+    let class = SysStack::round_size(bytes).ok_or_else(too_big)?;
+    let guard = default_guard_size();
+    let node = current_node();
+
+    let have = {
+        let mut pool = lock();
+        let c = pool.class(class, guard, node);
+        c.cap = c.cap.max(count);
+        c.free.len()
+    };
+
+    // allocate without the lock, the stacks are put back as if freed
+    for _ in have..count {
+        let stack = SysStack::allocate(bytes, guard)?;
+        super::prefault(&stack);
+        if let Some(stack) = put(stack, guard) {
+            super::deallocate(&stack, guard);
+        }
+    }
+    Ok(())
+}
+
+/// set the max number of the pooled stacks of `size` words on each node,
+/// the extra ones are freed
+///
+/// the size class starts pooling if it's not warmed, `warm` only raises it
+pub fn set_max(size: usize, max: usize) {
+    let Some(class) = class_size(size) else {
+        return;
+    };
+    let guard = default_guard_size();
+    let mut pool = lock();
+    let mut found = false;
+    for c in pool.classes.iter_mut().filter(|c| c.size == class) {
+        c.cap = max;
+        found = true;
+    }
+    if !found {
+        pool.class(class, guard, current_node()).cap = max;
+    }
+    let freed = pool.expire(Instant::now());
+    drop(pool);
+    free(freed);
+}
+
+/// set the trim policy of all the size classes
+pub fn set_trim_policy(policy: TrimPolicy) {
+    lock().policy = policy;
+}
+
+/// free the stacks over the trim policy now, returns the number of them
+pub fn trim() -> usize {
+    let freed = lock().expire(Instant::now());
+    free(freed)
+}
+
+/// get the number of the pooled stacks of `size` words on all the nodes
+pub fn pooled(size: usize) -> usize {
+    let class = class_size(size).unwrap_or(0);
+    lock()
+        .classes
+        .iter()
+        .filter(|c| c.size == class)
+        .map(|c| c.free.len())
+        .sum()
+}
+
+/// get the stats of the whole pool
+pub fn stats() -> PoolStats {
+    let pool = lock();
+    let mut stats = sum(pool.classes.iter());
+    stats.peak_pooled = pool.peak;
+    stats.peak_bytes = pool.peak_bytes;
+    stats
+}
+
+/// get the stats of the size class of `size` words on all the nodes, the
+/// high-water marks are the sums of the ones of the nodes
+pub fn class_stats(size: usize) -> PoolStats {
+    let class = class_size(size).unwrap_or(0);
+    let pool = lock();
+    let classes = pool.classes.iter().filter(|c| c.size == class);
+    let mut stats = sum(classes.clone());
+    stats.peak_pooled = classes.clone().map(|c| c.peak).sum();
+    stats.peak_bytes = classes.map(|c| c.peak * c.size).sum();
+    stats
+}
+
+fn sum<'a>(classes: impl Iterator<Item = &'a Class>) -> PoolStats {
+    let mut stats = PoolStats::default();
+    for c in classes {
+        stats.hits += c.hits;
+        stats.misses += c.misses;
+        stats.trimmed += c.trimmed;
+        stats.pooled += c.free.len();
+        stats.pooled_bytes += c.free.len() * c.size;
+    }
+    stats
+}
+
+/// reset the counters of the stats, the high-water marks restart from the
+/// pooled stacks
+pub fn reset_stats() {
+    let mut pool = lock();
+    for c in &mut pool.classes {
+        c.hits = 0;
+        c.misses = 0;
+        c.trimmed = 0;
+        c.peak = c.free.len();
+    }
+    pool.peak = 0;
+    pool.peak_bytes = 0;
+    pool.track_peak();
+}
+
+/// free all the pooled stacks and stop pooling
+pub fn clear() {
+    let classes = {
+        let mut pool = lock();
+        ACTIVE.store(false, Ordering::Relaxed);
+        std::mem::take(&mut pool.classes)
+    };
+    for c in classes {
+        for (stack, _) in c.free {
+            super::deallocate(&stack, c.guard);
+        }
+    }
+}
+
+// take a free stack of `size` usable bytes and `guard` guard bytes
+pub(crate) fn take(size: usize, guard: usize) -> Option<SysStack> {
+    if !enabled() {
+        return None;
+    }
+    let node = current_node();
+    let mut pool = lock();
+    let freed = match pool.policy {
+        TrimPolicy::Keep => Vec::new(),
+        TrimPolicy::Idle(_) => pool.expire(Instant::now()),
+    };
+    let c = pool
+        .classes
+        .iter_mut()
+        .find(|c| c.size == size && c.guard == guard && c.node == node);
+    // the newest one is the most likely to be cached
+    let ret = c.and_then(|c| match c.free.pop() {
+        Some((stack, _)) => {
+            c.hits += 1;
+            Some(stack)
+        }
+        None => {
+            c.misses += 1;
+            None
+        }
+    });
+    drop(pool);
+    free(freed);
+    ret
+}
+
+// keep the stack if its class is not full, return it otherwise
+pub(crate) fn put(stack: SysStack, guard: usize) -> Option<SysStack> {
+    if !enabled() {
+        return Some(stack);
+    }
+    let node = stack_node(&stack);
+    let now = Instant::now();
+    let mut pool = lock();
+    let ret = match pool
+        .classes
+        .iter_mut()
+        .find(|c| c.size == stack.len() && c.guard == guard && c.node == node)
+    {
+        Some(c) if c.free.len() < c.cap => {
+            c.free.push((stack, now));
+            c.peak = c.peak.max(c.free.len());
+            None
+        }
+        _ => Some(stack),
+    };
+    if ret.is_none() {
+        pool.track_peak();
+    }
+    let freed = match pool.policy {
+        TrimPolicy::Keep => Vec::new(),
+        TrimPolicy::Idle(_) => pool.expire(now),
+    };
+    drop(pool);
+    free(freed);
+    ret
+}
+
+// the deterministic mode of the other tests turns the pool off
+#[cfg(all(test, feature = "numa", not(feature = "deterministic")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_numa_classes() {
+        // a size class no other test uses
+        const SIZE: usize = 0x2d00;
+        let class = class_size(SIZE).unwrap();
+        let guard = default_guard_size();
+        let node = current_node();
+
+        // the warmed stack is placed on the node of the current thread
+        warm(1, SIZE).unwrap();
+        let stack = take(class, guard).unwrap();
+        assert_eq!(stack_node(&stack), node);
+        assert!(put(stack, guard).is_none());
+
+        // a stack pooled for another node is not handed out on this one
+        {
+            let mut pool = lock();
+            let (stack, t) = pool.class(class, guard, node).free.pop().unwrap();
+            let other = pool.class(class, guard, node + 1);
+            other.cap = 1;
+            other.free.push((stack, t));
+        }
+        assert_eq!(pooled(SIZE), 1);
+        assert!(take(class, guard).is_none());
+        assert_eq!(class_stats(SIZE).misses, 1);
+
+        let (stack, _) = lock().class(class, guard, node + 1).free.pop().unwrap();
+        free(vec![(stack, guard)]);
+    }
+}
@@ -0,0 +1,128 @@
+//! # reserved stack region
+//!
+//! carve the generator stacks out of one big region reserved up front
+//! instead of mapping each one, a stack is a slot of the region with its
+//! guard region at the low end
+//!
+//! the slots of the dropped stacks are given back to the OS and reused by
+//! the stacks of the same size. The stacks are mapped one by one as before
+//! when the region is full, and in the deterministic test mode
+//!
+//! a free slot is only reused at its exact size, it's never split or merged
+//! with its neighbours, so the region is best used by a few stack sizes, a
+//! mix of many sizes fills it with free slots none of them fits
+//!
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::{sys, SysStack};
+
+struct Region {
+    top: usize,
+    // the low end of the never used part
+    next: usize,
+    // the low ends and the sizes of the free slots
+    free: Vec<(usize, usize)>,
+}
+
+static REGION: Mutex<Option<Region>> = Mutex::new(None);
+// the bounds of the region, set once by `reserve`, so the stacks skip the
+// lock while no region is reserved, the top is 0 until then
+static BOTTOM: AtomicUsize = AtomicUsize::new(0);
+static TOP: AtomicUsize = AtomicUsize::new(0);
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Region>> {
+    // the region is always valid, even after a panic
+    REGION.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// reserve a region of `size` bytes for the generator stacks of the
+/// process, it's rounded up to the page size
+///
+/// the region only takes the address space, the memory is committed by the
+/// stacks in use. It's reserved for the lifetime of the process, a second
+/// call fails with `AlreadyExists`
+pub fn reserve(size: usize) -> io::Result<()> {
+    let page_size = sys::page_size();
+    let size = size
+        .checked_add(page_size - 1)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?
+        & !(page_size - 1);
+
+    let mut region = lock();
+    if region.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the stack region is already reserved",
+        ));
+    }
+    let buf = unsafe { sys::reserve_region(size)? };
+    *region = Some(Region {
+        top: buf.top() as usize,
+        next: buf.bottom() as usize,
+        free: Vec::new(),
+    });
+    BOTTOM.store(buf.bottom() as usize, Ordering::Relaxed);
+    TOP.store(buf.top() as usize, Ordering::Release);
+    Ok(())
+}
+
+/// get the address range of the reserved region if any
+pub fn reserved() -> Option<std::ops::Range<usize>> {
+    match TOP.load(Ordering::Acquire) {
+        0 => None,
+        top => Some(BOTTOM.load(Ordering::Relaxed)..top),
+    }
+}
+
+// carve a stack of `size` bytes including the `guard` bytes, `None` if the
+// region is not reserved or full
+pub(crate) fn carve(size: usize, guard: usize) -> Option<io::Result<SysStack>> {
+    #[cfg(feature = "deterministic")]
+    if crate::deterministic::is_enabled() {
+        return None;
+    }
+
+    reserved()?;
+    let bottom = {
+        let mut region = lock();
+        let region = region.as_mut()?;
+        match region.free.iter().position(|&(_, len)| len == size) {
+            Some(i) => region.free.swap_remove(i).0,
+            None if region.top - region.next >= size => {
+                region.next += size;
+                region.next - size
+            }
+            None => return None,
+        }
+    };
+
+    let slot = unsafe { SysStack::new((bottom + size) as _, bottom as _) };
+    let ret = unsafe { sys::commit_stack(&slot, guard) };
+    if ret.is_err() {
+        release_slot(&slot);
+    }
+    Some(ret)
+}
+
+// give the stack back to the region, false if it's not carved from it
+pub(crate) fn release(buf: &SysStack, guard: usize) -> bool {
+    let bottom = buf.bottom() as usize - guard;
+    match reserved() {
+        Some(r) if r.contains(&bottom) => {}
+        _ => return false,
+    }
+
+    let slot = unsafe { SysStack::new(buf.top(), bottom as _) };
+    unsafe { sys::decommit_stack(&slot) };
+    release_slot(&slot);
+    true
+}
+
+fn release_slot(slot: &SysStack) {
+    if let Some(region) = lock().as_mut() {
+        region.free.push((slot.bottom() as usize, slot.len()));
+    }
+}
@@ -1,9 +1,10 @@
+use std::ffi::CStr;
 use std::io;
 use std::mem;
 use std::os::raw::c_void;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use super::SysStack;
+use super::{StackError, SysStack};
 
 #[path = "overflow_unix.rs"]
 pub mod overflow;
@@ -28,15 +29,31 @@ const MAP_STACK: libc::c_int = 0;
 )))]
 const MAP_STACK: libc::c_int = libc::MAP_STACK;
 
-pub unsafe fn allocate_stack(size: usize) -> io::Result<SysStack> {
-    const NULL: *mut libc::c_void = 0 as *mut libc::c_void;
+// the anonymous mappings are tagged for vmmap by the fd, there is no name
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const MAP_FD: libc::c_int = (240u32 << 24) as libc::c_int; // VM_MAKE_TAG(VM_MEMORY_APPLICATION_SPECIFIC_1)
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const MAP_FD: libc::c_int = -1;
+
+/// allocate the stack at `addr` if it's given, it fails if the address is
+/// not available
+pub unsafe fn allocate_stack(size: usize, addr: Option<usize>) -> io::Result<SysStack> {
     const PROT: libc::c_int = libc::PROT_READ | libc::PROT_WRITE;
     const TYPE: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON | MAP_STACK;
 
-    let ptr = libc::mmap(NULL, size, PROT, TYPE, -1, 0);
+    let hint = addr.unwrap_or(0) as *mut libc::c_void;
+    let ptr = libc::mmap(hint, size, PROT, TYPE, MAP_FD, 0);
 
     if ptr == libc::MAP_FAILED {
         Err(io::Error::last_os_error())
+    } else if addr.is_some() && ptr != hint {
+        // the address is only a hint without `MAP_FIXED`
+        libc::munmap(ptr, size);
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "the stack address is not available",
+        ))
     } else {
         Ok(SysStack::new(
             (ptr as usize + size) as *mut c_void,
@@ -45,24 +62,208 @@ pub unsafe fn allocate_stack(size: usize) -> io::Result<SysStack> {
     }
 }
 
-pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
+/// reserve an inaccessible region of `size` bytes to carve stacks from
+pub unsafe fn reserve_region(size: usize) -> io::Result<SysStack> {
+    const TYPE: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON;
+
+    let ptr = libc::mmap(std::ptr::null_mut(), size, libc::PROT_NONE, TYPE, MAP_FD, 0);
+    if ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(SysStack::new(
+            (ptr as usize + size) as *mut c_void,
+            ptr as *mut c_void,
+        ))
+    }
+}
+
+/// make a slot of the reserved region a stack, the lowest `guard` bytes are
+/// left as the guard region
+pub unsafe fn commit_stack(slot: &SysStack, guard: usize) -> io::Result<SysStack> {
+    const PROT: libc::c_int = libc::PROT_READ | libc::PROT_WRITE;
+
+    let bottom = (slot.bottom() as usize + guard) as *mut c_void;
+    let stack = SysStack::new(slot.top(), bottom);
+    if libc::mprotect(bottom, stack.len(), PROT) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stack)
+}
+
+/// give the memory of a slot back to the OS and make it inaccessible again
+pub unsafe fn decommit_stack(slot: &SysStack) {
+    // a new mapping in place drops the pages on all the unixes
+    const TYPE: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_FIXED;
+    libc::mmap(slot.bottom(), slot.len(), libc::PROT_NONE, TYPE, MAP_FD, 0);
+}
+
+pub unsafe fn protect_stack(stack: &SysStack, guard: usize) -> io::Result<SysStack> {
     let page_size = page_size();
 
-    debug_assert!(stack.len() % page_size == 0 && stack.len() != 0);
+    debug_assert!(stack.len() % page_size == 0 && stack.len() > guard);
+    debug_assert!(guard % page_size == 0 && guard != 0);
 
     let ret = {
         let bottom = stack.bottom();
-        libc::mprotect(bottom, page_size, libc::PROT_NONE)
+        libc::mprotect(bottom, guard, libc::PROT_NONE)
     };
 
     if ret != 0 {
         Err(io::Error::last_os_error())
     } else {
-        let bottom = (stack.bottom() as usize + page_size) as *mut c_void;
+        let bottom = (stack.bottom() as usize + guard) as *mut c_void;
         Ok(SysStack::new(stack.top(), bottom))
     }
 }
 
+/// label the stack mapping as `generator` or `generator:<name>`, it's shown
+/// in `/proc/<pid>/maps` and `smaps` as `[anon:generator:<name>]`
+///
+/// the label is best effort, it's ignored by the kernels without
+/// `CONFIG_ANON_VMA_NAME` and for the names the kernel rejects
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn name_stack(stack: &SysStack, name: Option<&CStr>) {
+    const PR_SET_VMA: libc::c_int = 0x53564d41;
+    const PR_SET_VMA_ANON_NAME: libc::c_ulong = 0;
+    // the kernel limit including the nul
+    const MAX_LEN: usize = 80;
+    const PREFIX: &[u8] = b"generator";
+
+    let mut buf = [0u8; MAX_LEN];
+    buf[..PREFIX.len()].copy_from_slice(PREFIX);
+    if let Some(name) = name {
+        let name = name.to_bytes();
+        let len = name.len().min(MAX_LEN - PREFIX.len() - 2);
+        buf[PREFIX.len()] = b':';
+        buf[PREFIX.len() + 1..][..len].copy_from_slice(&name[..len]);
+    }
+
+    unsafe {
+        libc::prctl(
+            PR_SET_VMA,
+            PR_SET_VMA_ANON_NAME,
+            stack.bottom() as libc::c_ulong,
+            stack.len() as libc::c_ulong,
+            buf.as_ptr() as libc::c_ulong,
+        );
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn name_stack(_stack: &SysStack, _name: Option<&CStr>) {}
+
+/// get the bytes of the stack that are resident in memory
+#[cfg(feature = "stack-rss")]
+pub fn resident_bytes(stack: &SysStack) -> io::Result<usize> {
+    let page_size = page_size();
+    let mut pages = vec![0u8; stack.len() / page_size];
+    let ret = unsafe { libc::mincore(stack.bottom(), stack.len(), pages.as_mut_ptr() as _) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pages.iter().filter(|&&p| p & 1 != 0).count() * page_size)
+}
+
+/// get the NUMA node of the cpu running the current thread
+#[cfg(all(feature = "numa", any(target_os = "linux", target_os = "android")))]
+pub fn current_node() -> usize {
+    let (mut cpu, mut node) = (0u32, 0u32);
+    let null = std::ptr::null_mut::<c_void>();
+    let ret = unsafe { libc::syscall(libc::SYS_getcpu, &mut cpu, &mut node, null) };
+    if ret == 0 {
+        node as usize
+    } else {
+        0
+    }
+}
+
+/// prefer the NUMA node for the not faulted pages of the stack, it's best
+/// effort and the errors are ignored
+#[cfg(all(feature = "numa", any(target_os = "linux", target_os = "android")))]
+pub fn bind_stack(stack: &SysStack, node: usize) {
+    const MPOL_PREFERRED: libc::c_int = 1;
+    let bits = usize::BITS as usize;
+    let mut mask = vec![0usize; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            stack.bottom(),
+            stack.len(),
+            MPOL_PREFERRED,
+            mask.as_ptr(),
+            mask.len() * bits + 1,
+            0u32,
+        );
+    }
+}
+
+/// get the NUMA node of the stack, where its top page is placed
+#[cfg(all(feature = "numa", any(target_os = "linux", target_os = "android")))]
+pub fn stack_node(stack: &SysStack) -> usize {
+    const MPOL_F_NODE: libc::c_ulong = 1;
+    const MPOL_F_ADDR: libc::c_ulong = 2;
+    let mut node: libc::c_int = 0;
+    let addr = stack.top() as usize - mem::size_of::<usize>();
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_get_mempolicy,
+            &mut node,
+            std::ptr::null_mut::<libc::c_ulong>(),
+            0usize,
+            addr,
+            MPOL_F_NODE | MPOL_F_ADDR,
+        )
+    };
+    if ret == 0 {
+        node as usize
+    } else {
+        0
+    }
+}
+
+#[cfg(all(feature = "numa", not(any(target_os = "linux", target_os = "android"))))]
+pub fn current_node() -> usize {
+    0
+}
+
+#[cfg(all(feature = "numa", not(any(target_os = "linux", target_os = "android"))))]
+pub fn bind_stack(_stack: &SysStack, _node: usize) {}
+
+#[cfg(all(feature = "numa", not(any(target_os = "linux", target_os = "android"))))]
+pub fn stack_node(_stack: &SysStack) -> usize {
+    0
+}
+
+/// lock the stack in memory, the pages are faulted in by the lock
+pub unsafe fn lock_stack(stack: &SysStack) -> Result<(), StackError> {
+    if libc::mlock(stack.bottom(), stack.len()) == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    // over the limit of an unprivileged process
+    if matches!(err.raw_os_error(), Some(libc::ENOMEM | libc::EAGAIN)) {
+        let mut limit: libc::rlimit = mem::zeroed();
+        if libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) == 0
+            && limit.rlim_cur != libc::RLIM_INFINITY
+        {
+            return Err(StackError::IoError(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!(
+                    "Locking the stack of {} bytes exceeds the RLIMIT_MEMLOCK of {} bytes",
+                    stack.len(),
+                    limit.rlim_cur
+                ),
+            )));
+        }
+    }
+    Err(StackError::IoError(err))
+}
+
+pub unsafe fn unlock_stack(stack: &SysStack) {
+    libc::munlock(stack.bottom(), stack.len());
+}
+
 pub unsafe fn deallocate_stack(ptr: *mut c_void, size: usize) {
     libc::munmap(ptr, size);
 }
@@ -1,3 +1,4 @@
+use std::ffi::CStr;
 use std::io;
 use std::mem;
 use std::os::raw::c_void;
@@ -7,14 +8,16 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use windows::Win32::System::Memory::*;
 use windows::Win32::System::SystemInformation::*;
 
-use super::SysStack;
+use super::{StackError, SysStack};
 
 #[path = "overflow_windows.rs"]
 pub mod overflow;
 
-pub unsafe fn allocate_stack(size: usize) -> io::Result<SysStack> {
+/// allocate the stack at `addr` if it's given, it fails if the address is
+/// not available
+pub unsafe fn allocate_stack(size: usize, addr: Option<usize>) -> io::Result<SysStack> {
     let ptr = VirtualAlloc(
-        Some(ptr::null()),
+        Some(addr.unwrap_or(0) as *const c_void),
         size,
         MEM_COMMIT | MEM_RESERVE,
         PAGE_READWRITE,
@@ -30,15 +33,128 @@ pub unsafe fn allocate_stack(size: usize) -> io::Result<SysStack> {
     }
 }
 
-pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
+/// reserve a stack of `size` bytes and commit the top `commit` bytes, the
+/// lowest `guard` bytes are the guard region that is never committed
+///
+/// the page below the committed part is a `PAGE_GUARD` page, the OS commits
+/// the stack down on demand like a thread stack while the thread environment
+/// block points to it, see `initialize_call_frame`
+pub unsafe fn allocate_lazy_stack(
+    size: usize,
+    guard: usize,
+    commit: usize,
+) -> io::Result<SysStack> {
+    let ptr = VirtualAlloc(None, size, MEM_RESERVE, PAGE_READWRITE);
+    if ptr.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let top = ptr as usize + size;
+    let stack = SysStack::new(top as *mut c_void, (ptr as usize + guard) as *mut c_void);
+    let low = top.saturating_sub(commit) & !(page_size() - 1);
+    if let Err(e) = commit_stack_to(&stack, low) {
+        let _ = VirtualFree(ptr, 0, MEM_RELEASE);
+        return Err(e);
+    }
+    Ok(stack)
+}
+
+/// get the low end of the committed part of a lazy stack
+pub fn committed_bottom(stack: &SysStack) -> usize {
+    let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+    let len = mem::size_of::<MEMORY_BASIC_INFORMATION>();
+    let top = stack.top() as usize - 1;
+    // the committed pages at the top have the same state and protection
+    let ret = unsafe { VirtualQuery(Some(top as *const c_void), &mut info, len) };
+    if ret == 0 || info.State != MEM_COMMIT {
+        return stack.top() as usize;
+    }
+    usize::max(info.BaseAddress as usize, stack.bottom() as usize)
+}
+
+/// commit a lazy stack down to `addr` and move the `PAGE_GUARD` page below
+/// it, the whole stack is committed if there is no room for the page
+pub unsafe fn commit_stack_to(stack: &SysStack, addr: usize) -> io::Result<()> {
     let page_size = page_size();
+    let old = committed_bottom(stack);
+    if addr >= old {
+        return Ok(());
+    }
+
+    let low = usize::max(addr & !(page_size - 1), stack.bottom() as usize);
+    let guard = low
+        .checked_sub(page_size)
+        .filter(|&g| g >= stack.bottom() as usize);
+    let from = guard.unwrap_or(low);
+    let ptr = VirtualAlloc(
+        Some(from as *const c_void),
+        old - from,
+        MEM_COMMIT,
+        PAGE_READWRITE,
+    );
+    if ptr.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    // the old guard page is in the range, clear it
     let mut old_prot = mem::zeroed();
+    VirtualProtect(ptr, old - from, PAGE_READWRITE, &mut old_prot)
+        .map_err(|_| io::Error::last_os_error())?;
+    if let Some(guard) = guard {
+        VirtualProtect(
+            guard as *const c_void,
+            page_size,
+            PAGE_READWRITE | PAGE_GUARD,
+            &mut old_prot,
+        )
+        .map_err(|_| io::Error::last_os_error())?;
+    }
+    Ok(())
+}
 
-    debug_assert!(stack.len() % page_size == 0 && stack.len() != 0);
+/// reserve an inaccessible region of `size` bytes to carve stacks from
+pub unsafe fn reserve_region(size: usize) -> io::Result<SysStack> {
+    let ptr = VirtualAlloc(None, size, MEM_RESERVE, PAGE_NOACCESS);
+    if ptr.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(SysStack::new(
+            (ptr as usize + size) as *mut c_void,
+            ptr as *mut c_void,
+        ))
+    }
+}
+
+/// make a slot of the reserved region a stack, the lowest `guard` bytes are
+/// left as the guard region
+pub unsafe fn commit_stack(slot: &SysStack, guard: usize) -> io::Result<SysStack> {
+    let ptr = VirtualAlloc(
+        Some(slot.bottom() as *const c_void),
+        slot.len(),
+        MEM_COMMIT,
+        PAGE_READWRITE,
+    );
+    if ptr.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    protect_stack(slot, guard)
+}
+
+/// give the memory of a slot back to the OS and make it inaccessible again
+pub unsafe fn decommit_stack(slot: &SysStack) {
+    let _ = VirtualFree(slot.bottom(), slot.len(), MEM_DECOMMIT);
+}
+
+pub unsafe fn protect_stack(stack: &SysStack, guard: usize) -> io::Result<SysStack> {
+    let page_size = page_size();
+    let mut old_prot = mem::zeroed();
+
+    debug_assert!(stack.len() % page_size == 0 && stack.len() > guard);
+    debug_assert!(guard % page_size == 0 && guard != 0);
 
     let ret = VirtualProtect(
         stack.bottom(),
-        page_size,
+        guard,
         PAGE_READONLY | PAGE_GUARD,
         &mut old_prot,
     );
@@ -46,11 +162,62 @@ pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
     if ret.is_err() {
         Err(io::Error::last_os_error())
     } else {
-        let bottom = (stack.bottom() as usize + page_size) as *mut c_void;
+        let bottom = (stack.bottom() as usize + guard) as *mut c_void;
         Ok(SysStack::new(stack.top(), bottom))
     }
 }
 
+// there is no name for a virtual memory region on windows
+pub fn name_stack(_stack: &SysStack, _name: Option<&CStr>) {}
+
+/// get the bytes of the stack that are in the working set of the process
+#[cfg(feature = "stack-rss")]
+pub fn resident_bytes(stack: &SysStack) -> io::Result<usize> {
+    use windows::Win32::System::ProcessStatus::{
+        QueryWorkingSetEx, PSAPI_WORKING_SET_EX_INFORMATION,
+    };
+    use windows::Win32::System::Threading::GetCurrentProcess;
+
+    let page_size = page_size();
+    let mut pages: Vec<PSAPI_WORKING_SET_EX_INFORMATION> = (0..stack.len() / page_size)
+        .map(|i| PSAPI_WORKING_SET_EX_INFORMATION {
+            VirtualAddress: (stack.bottom() as usize + i * page_size) as *mut c_void,
+            ..unsafe { mem::zeroed() }
+        })
+        .collect();
+    let cb = mem::size_of_val(pages.as_slice()) as u32;
+    unsafe { QueryWorkingSetEx(GetCurrentProcess(), pages.as_mut_ptr().cast(), cb) }
+        .map_err(|_| io::Error::last_os_error())?;
+    // the lowest bit of the attributes is `Valid`
+    let valid = |p: &PSAPI_WORKING_SET_EX_INFORMATION| unsafe { p.VirtualAttributes.Flags } & 1;
+    Ok(pages.iter().filter(|p| valid(p) != 0).count() * page_size)
+}
+
+// the stacks are placed by the default policy of the OS on windows
+#[cfg(feature = "numa")]
+pub fn current_node() -> usize {
+    0
+}
+
+#[cfg(feature = "numa")]
+pub fn bind_stack(_stack: &SysStack, _node: usize) {}
+
+#[cfg(feature = "numa")]
+pub fn stack_node(_stack: &SysStack) -> usize {
+    0
+}
+
+/// lock the stack in the working set, it's limited by the minimum working
+/// set size of the process, see `SetProcessWorkingSetSize`
+pub unsafe fn lock_stack(stack: &SysStack) -> Result<(), StackError> {
+    VirtualLock(stack.bottom(), stack.len())
+        .map_err(|_| StackError::IoError(io::Error::last_os_error()))
+}
+
+pub unsafe fn unlock_stack(stack: &SysStack) {
+    let _ = VirtualUnlock(stack.bottom(), stack.len());
+}
+
 pub unsafe fn deallocate_stack(ptr: *mut c_void, _: usize) {
     let _ = VirtualFree(ptr, 0, MEM_RELEASE);
 }
@@ -0,0 +1,76 @@
+//! # stream
+//!
+//! a `futures_core::Stream` backed by a generator
+//!
+//! ```
+//! use generator::stream;
+//! use generator::stream::GenStream;
+//! use std::task::Poll;
+//!
+//! let s: GenStream<u32> = stream!(|mut s| {
+//!     for i in 0..3u32 {
+//!         // awaiting suspends the stream until the future is ready
+//!         let v = s.block_on(std::future::ready(i * 10));
+//!         s.yield_(Poll::Ready(v));
+//!     }
+//! });
+//! # let _ = s;
+//! ```
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use crate::block_on::with_waker;
+use crate::gen_impl::{default_stack_words, Generator, Gn};
+use crate::scope::Scope;
+use crate::yield_::done;
+
+/// create a `GenStream` from the body taking a `Scope`
+///
+/// the items are yielded as `Poll::Ready`, `Scope::block_on` waits for the
+/// futures and `Scope::yield_pending` suspends the stream until it's woken
+#[macro_export]
+macro_rules! stream {
+    (|$s:pat_param| $body:expr) => {
+        $crate::stream::GenStream::new(move |$s| $body)
+    };
+}
+
+/// A stream of the values yielded by a generator
+///
+/// each poll resumes the generator with the waker of the polling task
+pub struct GenStream<T: 'static> {
+    gen: Generator<'static, (), Poll<T>>,
+}
+
+impl<T: Send + 'static> GenStream<T> {
+    /// create a stream from the producer, see `stream!`
+    pub fn new<F>(producer: F) -> Self
+    where
+        for<'scope> F: FnOnce(Scope<'scope, 'static, (), Poll<T>>) + Send + 'static,
+    {
+        let gen = Gn::<()>::new_scoped_opt(default_stack_words(), move |s| {
+            producer(s);
+            done()
+        });
+        GenStream { gen }
+    }
+}
+
+impl<T> Stream for GenStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let gen = &mut self.get_mut().gen;
+        if gen.is_done() {
+            return Poll::Ready(None);
+        }
+        match with_waker(cx.waker(), || gen.resume()) {
+            Some(Poll::Ready(v)) => Poll::Ready(Some(v)),
+            Some(Poll::Pending) => Poll::Pending,
+            None => Poll::Ready(None),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! # sync shims
+//!
+//! the thread local storage and the synchronization types shared across
+//! threads, they are swapped for the loom ones under `cfg(loom)` so the
+//! cross thread resume and the worker handshakes can be model checked
+//!
+//! the atomics and locks in statics are process wide state, e.g. the
+//! stack pool and the runtime hooks, they stay the std ones since the loom
+//! types can't be in a static
+//!
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{Arc, Condvar, Mutex};
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Arc, Condvar, Mutex};
+#[cfg(not(loom))]
+pub(crate) use std::thread_local;
+
+// the loom `thread_local!` doesn't take the `const` initializer
+#[cfg(loom)]
+macro_rules! loom_thread_local {
+    ($(#[$attr:meta])* static $name:ident: $t:ty = const { $init:expr };) => {
+        loom::thread_local!($(#[$attr])* static $name: $t = $init;);
+    };
+}
+#[cfg(loom)]
+pub(crate) use loom_thread_local as thread_local;
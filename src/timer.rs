@@ -0,0 +1,177 @@
+//! # timers
+//!
+//! the hashed timer wheel of the `Executor`, the generators suspend on it
+//! by `Scope::sleep` and `Scope::sleep_until`
+//!
+
+use std::cell::Cell;
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::scope::Scope;
+use crate::sync::thread_local;
+
+// the slots of the wheel, the timers of later rounds share them
+const SLOTS: usize = 256;
+// the resolution of the timers
+const TICK: Duration = Duration::from_millis(1);
+
+pub(crate) struct TimerWheel {
+    start: Instant,
+    // the last tick whose timers are fired
+    now: u64,
+    // the due tick and the waker of the timers, hashed by the tick
+    slots: Vec<Vec<(u64, Waker)>>,
+    len: usize,
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        TimerWheel {
+            start: Instant::now(),
+            now: 0,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            len: 0,
+        }
+    }
+}
+
+impl TimerWheel {
+    // the ticks passed since the start, rounded up for the due ticks so a
+    // timer never fires early
+    fn ticks(&self, t: Instant, round_up: bool) -> u64 {
+        let d = t.saturating_duration_since(self.start);
+        let tick = TICK.as_nanos();
+        let n = if round_up {
+            d.as_nanos().div_ceil(tick)
+        } else {
+            d.as_nanos() / tick
+        };
+        n.try_into().unwrap_or(u64::MAX)
+    }
+
+    // wake `waker` once `deadline` is passed
+    pub(crate) fn insert(&mut self, deadline: Instant, waker: Waker) {
+        let tick = self.ticks(deadline, true).max(self.now + 1);
+        self.slots[tick as usize % SLOTS].push((tick, waker));
+        self.len += 1;
+    }
+
+    // wake the timers due by `now` and return the time until the next one
+    pub(crate) fn expire(&mut self, now: Instant) -> Option<Duration> {
+        let target = self.ticks(now, false);
+        if target > self.now {
+            let mut due = Vec::new();
+            // one round visits all the slots
+            let steps = (target - self.now).min(SLOTS as u64);
+            for tick in self.now + 1..=self.now + steps {
+                let slot = &mut self.slots[tick as usize % SLOTS];
+                let mut i = 0;
+                while i < slot.len() {
+                    if slot[i].0 <= target {
+                        due.push(slot.swap_remove(i).1);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            self.now = target;
+            self.len -= due.len();
+            // a waker may run any code, the wheel is not borrowed by then
+            due.into_iter().for_each(Waker::wake);
+        }
+        self.next_timeout(now)
+    }
+
+    // the time from `now` until the next timer is due
+    fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        if self.len == 0 {
+            return None;
+        }
+        // the timers of this round are found in the tick order
+        let next = (self.now + 1..=self.now + SLOTS as u64)
+            .find(|&tick| {
+                self.slots[tick as usize % SLOTS]
+                    .iter()
+                    .any(|&(t, _)| t == tick)
+            })
+            .or_else(|| {
+                let later = self.slots.iter().flatten();
+                later.map(|&(t, _)| t).min()
+            })?;
+        let since = Duration::from_nanos(next.saturating_mul(TICK.as_nanos() as u64));
+        let due = self.start.checked_add(since)?;
+        Some(due.saturating_duration_since(now))
+    }
+}
+
+thread_local! {
+    // the timers of the executor running on the thread
+    static TIMERS: Cell<Option<*mut TimerWheel>> = const { Cell::new(None) };
+}
+
+// restore the timers of the outer executor
+struct Restore(Option<*mut TimerWheel>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        TIMERS.with(|t| t.set(self.0));
+    }
+}
+
+// run `f` with `timers` as the ones the generators sleep on
+pub(crate) fn with_timers<R>(timers: *mut TimerWheel, f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(TIMERS.with(|t| t.replace(Some(timers))));
+    f()
+}
+
+impl<A, U> Scope<'_, '_, A, Poll<U>> {
+    /// suspend the generator for the duration, see `sleep_until`
+    #[track_caller]
+    pub fn sleep(&mut self, dur: Duration) {
+        self.sleep_until(Instant::now() + dur);
+    }
+
+    /// suspend the generator until the deadline is passed, the paras sent
+    /// while sleeping are dropped
+    ///
+    /// the generator is resumed by the timers of the `Executor` running it,
+    /// it panics when it's not run by an `Executor`
+    #[track_caller]
+    pub fn sleep_until(&mut self, deadline: Instant) {
+        let timers = match TIMERS.with(Cell::get) {
+            Some(timers) => timers,
+            None => panic!("the sleeping generator is not run by an executor"),
+        };
+        // a task may be woken before the deadline by the other wakers
+        while Instant::now() < deadline {
+            // the executor is not using the timers while the task runs
+            self.yield_pending(|waker| unsafe { (*timers).insert(deadline, waker) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TimerWheel;
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::time::{Duration, Instant};
+
+    struct Nop;
+
+    impl Wake for Nop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn test_far_timeout() {
+        let mut wheel = TimerWheel::default();
+        let now = Instant::now();
+        // far beyond the u32::MAX ticks of a millisecond
+        let dur = Duration::from_secs(60 * 24 * 3600);
+        wheel.insert(now + dur, Waker::from(Arc::new(Nop)));
+        let timeout = wheel.expire(now).unwrap();
+        assert!(timeout >= dur && timeout <= dur + Duration::from_millis(1));
+    }
+}
@@ -3,6 +3,7 @@
 //! generator yield implementation
 //!
 use std::any::Any;
+use std::fmt;
 use std::sync::atomic;
 
 use crate::gen_impl::{unlikely, Generator};
@@ -11,6 +12,8 @@ use crate::rt::{is_generator, Context, ContextStack, Error};
 
 /// it's a special return instruction that yield nothing
 /// but only terminate the generator safely
+///
+/// `Scope::done` does the same and can only be called in a generator
 #[macro_export]
 macro_rules! done {
     () => {{
@@ -18,12 +21,72 @@ macro_rules! done {
     }};
 }
 
+/// yield the `Ok` value of a `Result` by the scope and evaluate to the send
+/// para, or return the converted `Err` from the generator body like `?`
+///
+/// use `Scope::try_yield` to bail out from a nested function
+#[macro_export]
+macro_rules! try_yield {
+    ($s:expr, $r:expr) => {
+        match $r {
+            Ok(v) => $s.yield_(Ok(v)),
+            Err(e) => return Err(::core::convert::From::from(e)),
+        }
+    };
+}
+
+/// the error of the `try_` yield functions called outside of a generator
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct NotInGenerator;
+
+impl fmt::Display for NotInGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("not running inside a generator")
+    }
+}
+
+impl std::error::Error for NotInGenerator {}
+
+// panic at the caller of the yield function `what`
+#[cold]
+#[track_caller]
+fn not_in_generator(what: &str) -> ! {
+    let caller = std::panic::Location::caller();
+    panic!("`{what}` is called outside of a generator at {caller}")
+}
+
+// the context of the running generator
+#[inline]
+fn generator_context(env: &ContextStack) -> Result<&'static mut Context, NotInGenerator> {
+    let context = env.top();
+    if unlikely(!context.is_generator()) {
+        return Err(NotInGenerator);
+    }
+    Ok(context)
+}
+
 /// don't use it directly, use done!() macro instead
 /// would panic if use in none generator context
 #[doc(hidden)]
 #[inline]
+#[track_caller]
 pub fn done<T>() -> T {
-    assert!(is_generator(), "done is only possible in a generator");
+    done_now()
+}
+
+/// terminate the current generator without a return value
+#[track_caller]
+pub(crate) fn done_now() -> ! {
+    if !is_generator() {
+        not_in_generator("done");
+    }
+    // can't unwind, leave the frames and never come back
+    #[cfg(panic = "abort")]
+    {
+        yield_now();
+        unreachable!("done generator resumed");
+    }
+    #[cfg(not(panic = "abort"))]
     std::panic::panic_any(Error::Done)
 }
 
@@ -41,6 +104,14 @@ pub fn raw_yield_now(env: &ContextStack, cur: &mut Context) {
     RegContext::swap(&mut cur.regs, &parent.regs);
 }
 
+/// raise the payload passed by `throw` at the yield that's resumed
+#[inline]
+fn raise_thrown(context: &mut Context) {
+    if let Some(e) = context.thrown.take() {
+        std::panic::resume_unwind(e);
+    }
+}
+
 /// raw yield without catch passed in para
 #[inline]
 fn raw_yield<T: Any>(env: &ContextStack, context: &mut Context, v: T) {
@@ -48,67 +119,117 @@ fn raw_yield<T: Any>(env: &ContextStack, context: &mut Context, v: T) {
     if unlikely(!context.is_generator()) {
         panic!("yield from none generator context");
     }
+    if unlikely(context.cancel_deferred()) {
+        return;
+    }
 
     context.set_ret(v);
     context._ref -= 1;
     raw_yield_now(env, context);
 
     // here we just panic to exit the func
-    if unlikely(context._ref != 1) {
+    if unlikely(context.is_cancelled()) {
+        std::panic::panic_any(Error::Cancel);
+    }
+    raise_thrown(context);
+}
+
+/// yield without a value, the resumer gets `None` while the generator is
+/// not done
+pub(crate) fn yield_none() {
+    let env = ContextStack::current();
+    let context = env.top();
+    if unlikely(!context.is_generator()) {
+        panic!("yield from none generator context");
+    }
+    if unlikely(context.cancel_deferred()) {
+        return;
+    }
+
+    context._ref -= 1;
+    raw_yield_now(&env, context);
+
+    if unlikely(context.is_cancelled()) {
         std::panic::panic_any(Error::Cancel);
     }
+    raise_thrown(context);
 }
 
 /// yield something without catch passed in para
+///
+/// panics at the caller outside of a generator, see `try_yield_with`
 #[inline]
+#[track_caller]
 #[deprecated(since = "0.6.18", note = "please use `scope` version instead")]
 pub fn yield_with<T: Any>(v: T) {
+    if try_yield_with(v).is_err() {
+        not_in_generator("yield_with");
+    }
+}
+
+/// yield something without catch passed in para, or return
+/// `Err(NotInGenerator)` right away outside of a generator
+///
+/// for the code that may run both inside and outside of a generator, the
+/// `Scope` version is preferred in a generator body
+#[inline]
+pub fn try_yield_with<T: Any>(v: T) -> Result<(), NotInGenerator> {
     let env = ContextStack::current();
-    let context = env.top();
+    let context = generator_context(&env)?;
     raw_yield(&env, context, v);
+    Ok(())
 }
 
 /// get the passed in para
+///
+/// panics at the caller outside of a generator, see `try_get_yield`
 #[inline]
+#[track_caller]
 #[deprecated(since = "0.6.18", note = "please use `scope` version instead")]
 pub fn get_yield<A: Any>() -> Option<A> {
-    let context = ContextStack::current().top();
-    raw_get_yield(context)
+    try_get_yield().unwrap_or_else(|_| not_in_generator("get_yield"))
 }
 
-/// get the passed in para from context
+/// get the passed in para, or return `Err(NotInGenerator)` outside of a
+/// generator
 #[inline]
-fn raw_get_yield<A: Any>(context: &mut Context) -> Option<A> {
-    // check the context
-    if unlikely(!context.is_generator()) {
-        {
-            error!("get yield from none generator context");
-            std::panic::panic_any(Error::ContextErr);
-        }
-    }
-
-    context.get_para()
+pub fn try_get_yield<A: Any>() -> Result<Option<A>, NotInGenerator> {
+    let env = ContextStack::current();
+    Ok(generator_context(&env)?.get_para())
 }
 
 /// yield and get the send para
 // here yield need to return a static lifetime value, which is Any required
 // this is fine, but it's totally safe that we can refer to the function block
 // since we will come back later
+///
+/// panics at the caller outside of a generator, see `try_yield_`
 #[inline]
+#[track_caller]
 #[deprecated(since = "0.6.18", note = "please use `scope` version instead")]
 pub fn yield_<A: Any, T: Any>(v: T) -> Option<A> {
+    try_yield_(v).unwrap_or_else(|_| not_in_generator("yield_"))
+}
+
+/// yield and get the send para, or return `Err(NotInGenerator)` right away
+/// outside of a generator
+#[inline]
+pub fn try_yield_<A: Any, T: Any>(v: T) -> Result<Option<A>, NotInGenerator> {
     let env = ContextStack::current();
-    let context = env.top();
+    let context = generator_context(&env)?;
     raw_yield(&env, context, v);
     atomic::compiler_fence(atomic::Ordering::Acquire);
-    raw_get_yield(context)
+    Ok(context.get_para())
 }
 
 /// `yield_from`
+///
+/// panics at the caller outside of a generator
+#[track_caller]
 #[deprecated(since = "0.6.18", note = "please use `scope` version instead")]
 pub fn yield_from<A: Any, T: Any>(mut g: Generator<A, T>) -> Option<A> {
     let env = ContextStack::current();
-    let context = env.top();
+    let context = generator_context(&env).unwrap_or_else(|_| not_in_generator("yield_from"));
     let mut p = context.get_para();
     while unlikely(!g.is_done()) {
         match g.raw_send(p) {
@@ -122,9 +243,14 @@ pub fn yield_from<A: Any, T: Any>(mut g: Generator<A, T>) -> Option<A> {
 }
 
 /// coroutine yield
+///
+/// panics at the caller outside of a coroutine
+#[track_caller]
 pub fn co_yield_with<T: Any>(v: T) {
     let env = ContextStack::current();
-    let context = env.co_ctx().unwrap();
+    let context = env
+        .co_ctx()
+        .unwrap_or_else(|| not_in_generator("co_yield_with"));
 
     // check the context, already checked in co_ctx()
     // if !context.is_generator() {
@@ -133,6 +259,9 @@ pub fn co_yield_with<T: Any>(v: T) {
     //     return;
     // }
 
+    if unlikely(context.cancel_deferred()) {
+        return;
+    }
     // here we just panic to exit the func
     if unlikely(context._ref != 1) {
         std::panic::panic_any(Error::Cancel);
@@ -1,8 +1,16 @@
+#![cfg_attr(
+    feature = "nightly",
+    feature(coroutines, coroutine_trait, stmt_expr_attributes)
+)]
 #![allow(deprecated)]
 #![allow(unused_assignments)]
 
 extern crate generator;
 
+// the unstable syntax can only be parsed on nightly
+#[cfg(feature = "nightly")]
+mod nightly;
+
 use generator::*;
 
 #[test]
@@ -102,6 +110,60 @@ fn test_get_yield_type_error() {
     g.send(10);
 }
 
+#[test]
+fn test_scope_recv() {
+    // a request/response loop, the first request starts the generator
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let mut req = s.recv();
+        loop {
+            s.yield_with(req * 2);
+            match s.try_recv() {
+                Some(0) | None => return 0,
+                Some(r) => req = r,
+            }
+        }
+    });
+    assert_eq!(g.send(1), 2);
+    assert_eq!(g.send(5), 10);
+    assert_eq!(g.send(0), 0);
+    assert!(g.is_done());
+
+    // the para is only taken once
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        assert_eq!(s.try_recv(), Some(3));
+        assert_eq!(s.try_recv(), None);
+        0
+    });
+    assert_eq!(g.send(3), 0);
+}
+
+#[test]
+#[should_panic(expected = "no para is sent")]
+fn test_scope_recv_without_para() {
+    let mut g = Gn::<u32>::new_scoped(|mut s| s.recv());
+    g.resume();
+}
+
+#[test]
+fn test_yield_outside_generator() {
+    assert_eq!(try_yield_with(1u32), Err(NotInGenerator));
+    assert_eq!(try_yield_::<(), _>(1u32), Err(NotInGenerator));
+    assert_eq!(try_get_yield::<u32>(), Err(NotInGenerator));
+
+    let mut g = Gn::<u32>::new(|| {
+        let a = try_yield_::<u32, u32>(1).unwrap();
+        try_yield_with(a.unwrap()).unwrap();
+        try_get_yield::<u32>().unwrap().unwrap()
+    });
+    assert_eq!(g.resume(), Some(1));
+    assert_eq!(g.send(2), 2);
+    assert_eq!(g.send(3), 3);
+
+    let e = std::panic::catch_unwind(|| yield_with(1u32)).unwrap_err();
+    let msg = e.downcast_ref::<String>().unwrap();
+    assert!(msg.starts_with("`yield_with` is called outside of a generator at tests/lib.rs:"));
+}
+
 #[test]
 #[should_panic]
 fn test_deep_yield_with_type_error() {
@@ -290,6 +352,78 @@ fn test_panic_inside() {
     assert!(x == 5);
 }
 
+#[test]
+fn test_unwind_safe() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let a = s.yield_(1).unwrap();
+        if a == 0 {
+            panic!("zero");
+        }
+        a
+    });
+    assert_eq!(g.resume(), Some(1));
+
+    let e = catch_unwind(AssertUnwindSafe(|| g.send(0))).unwrap_err();
+    assert_eq!(e.downcast_ref::<&str>(), Some(&"zero"));
+
+    // the panic finishes the generator
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        s.yield_(1);
+        panic!("inner");
+    });
+    assert_eq!(g.resume(), Some(1));
+    let r = catch_unwind(AssertUnwindSafe(|| g.resume()));
+    assert!(r.is_err());
+    assert_eq!(g.state(), State::Done);
+    assert_eq!(g.resume(), None);
+}
+
+#[test]
+fn test_throw() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    // handled by the generator, it keeps running
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let mut errors = 0;
+        loop {
+            match s.yield_catch(errors) {
+                Ok(Some(0)) => return errors,
+                Ok(_) => {}
+                Err(e) => {
+                    assert_eq!(e.downcast_ref::<&str>(), Some(&"bad"));
+                    errors += 1;
+                }
+            }
+        }
+    });
+    assert_eq!(g.resume(), Some(0));
+    assert_eq!(g.throw("bad"), Some(1));
+    assert_eq!(g.send(1), 1);
+    assert_eq!(g.throw("bad"), Some(2));
+    assert_eq!(g.send(0), 2);
+    assert!(g.is_done());
+    assert_eq!(g.throw("bad"), None);
+
+    // raised by a plain yield and propagated out of the generator
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    assert_eq!(g.resume(), Some(1));
+    let e = catch_unwind(AssertUnwindSafe(|| g.throw(7u8))).unwrap_err();
+    assert_eq!(e.downcast_ref::<u8>(), Some(&7));
+    assert!(g.is_done());
+
+    // a not started generator is cancelled
+    let mut g = Gn::<()>::new_scoped(|_| 1);
+    let e = catch_unwind(AssertUnwindSafe(|| g.throw(7u8))).unwrap_err();
+    assert_eq!(e.downcast_ref::<u8>(), Some(&7));
+    assert!(g.is_done());
+    assert_eq!(g.resume(), None);
+}
+
 #[test]
 #[allow(unreachable_code)]
 fn test_cancel() {
@@ -506,40 +640,2705 @@ fn test_re_init() {
 }
 
 #[test]
-#[should_panic]
-fn done_in_normal() {
-    done!();
+fn test_re_init_reuse_stack() {
+    // odd size tracks the full stack usage
+    let mut g = Gn::<()>::new_scoped_opt(0x1001, |mut s| {
+        let buf = std::hint::black_box([1u8; 0x2000]);
+        s.yield_(buf[0] as usize);
+        0
+    });
+    assert_eq!(g.next(), Some(1));
+    let (total, deep) = g.stack_usage();
+    assert!(deep * std::mem::size_of::<usize>() > 0x2000);
+    // re-init a suspended generator cancels it
+    g.scoped_init(|_| 1);
+    assert_eq!(g.next(), Some(1));
+
+    // the usage tracking is reset for the new closure
+    for i in 0..100 {
+        g.scoped_init(move |mut s| {
+            s.yield_(i);
+            i + 1
+        });
+        assert_eq!(g.by_ref().sum::<usize>(), i * 2 + 1);
+        assert_eq!(g.stack_usage().0, total);
+        assert!(g.stack_usage().1 < deep / 2);
+    }
 }
 
 #[test]
-#[should_panic]
-fn invalid_yield_in_scope() {
-    let g = Gn::new_scoped(|_| {
-        // invalid use raw yield API with scope
-        yield_::<String, _>(());
+fn test_watch_stack() {
+    use std::sync::{Arc, Mutex};
+
+    #[inline(never)]
+    fn deep(s: &mut Scope<'_, 'static, (), usize>) {
+        let buf = std::hint::black_box([1u8; 0x5000]);
+        s.yield_(buf[0] as usize);
+    }
+
+    let fired = Arc::new(Mutex::new(None));
+    let mut g = Gn::<()>::new_scoped_opt(0x2000, |mut s| {
+        s.yield_(0);
+        deep(&mut s);
+        2
+    });
+    let f = fired.clone();
+    g.watch_stack(25, move |total, used| {
+        *f.lock().unwrap() = Some((total, used))
     });
 
-    for () in g {}
+    assert_eq!(g.next(), Some(0));
+    assert!(fired.lock().unwrap().is_none());
+    assert_eq!(g.next(), Some(1));
+    let (total, used) = fired.lock().unwrap().take().unwrap();
+    assert_eq!(total, g.stack_usage().0);
+    assert!(used * std::mem::size_of::<usize>() > 0x5000);
+    assert!(used <= total);
+    // only fired once
+    assert_eq!(g.next(), Some(2));
+    assert!(fired.lock().unwrap().is_none());
+
+    // not reached
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(0);
+        1
+    });
+    g.watch_stack(50, |_, _| panic!("stack watch fired"));
+    assert_eq!(g.by_ref().sum::<usize>(), 1);
 }
 
 #[test]
-fn test_yield_float() {
-    let mut g = Gn::<f64>::new(|| {
-        let r: f64 = yield_(10.0).unwrap();
-        let x = r * 2.0; // 6
-        let y = x * 9.0; // 54
-        let z = y / 3.0; // 18
-        let r: f64 = yield_(6.0).unwrap();
-        x * r * y * z
+fn test_switch_hook() {
+    use std::sync::{Arc, Mutex};
+
+    static EVENTS: Mutex<Vec<(usize, Switch)>> = Mutex::new(Vec::new());
+    fn record(id: usize, switch: Switch) {
+        EVENTS.lock().unwrap().push((id, switch));
+    }
+
+    let local = Arc::new(Mutex::new(vec![]));
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    let id = g.id();
+    let events = local.clone();
+    g.set_switch_hook(move |id, switch| events.lock().unwrap().push((id, switch)));
+
+    set_switch_hook(Some(record));
+    assert_eq!(g.by_ref().sum::<i32>(), 3);
+    set_switch_hook(None);
+
+    use Switch::*;
+    let expected = [(id, Resume), (id, Yield), (id, Resume), (id, Done)];
+    assert_eq!(*local.lock().unwrap(), expected);
+    let global: Vec<_> = EVENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .copied()
+        .filter(|e| e.0 == id)
+        .collect();
+    assert_eq!(global, expected);
+
+    // the cancellation is a switch too
+    local.lock().unwrap().clear();
+    g.scoped_init(|mut s| {
+        s.yield_(1);
+        2
     });
+    assert_eq!(g.next(), Some(1));
+    g.cancel();
+    assert_eq!(*local.lock().unwrap(), expected);
+}
 
-    // first start the generator
-    let i = g.raw_send(None).unwrap();
-    let x = i * 10.0;
-    assert_eq!(i, 10.0);
-    let i = g.send(3.0);
-    assert_eq!(i, 6.0);
-    let i = g.send(x / 25.0);
-    assert_eq!(i, 23328.0);
+#[test]
+fn test_suspended_stack() {
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let marker = std::hint::black_box(*b"suspended here!!");
+        s.yield_(marker.len());
+        0
+    });
+    assert!(g.suspended_stack().is_none());
+    assert_eq!(g.next(), Some(16));
+
+    let view = g.suspended_stack().unwrap();
+    assert_ne!(view.ip(), 0);
+    assert_eq!(view.frames().as_ptr() as usize, view.sp());
+    assert!(view.frames().len() < g.stack_usage().0 * std::mem::size_of::<usize>());
+    let found = view.frames().windows(16).any(|w| w == b"suspended here!!");
+    assert!(found);
+
+    assert_eq!(g.next(), Some(0));
+    assert!(g.suspended_stack().is_none());
+}
+
+#[test]
+#[cfg(feature = "debug-checks")]
+fn test_debug_checks() {
+    // the switches that pass the checks with a value left in the slots
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let mut inner = Gn::<()>::new_scoped(|mut s| {
+            s.yield_(1);
+            2
+        });
+        let v = inner.next().unwrap();
+        s.yield_(v);
+        inner.next().unwrap()
+    });
+    assert_eq!(g.peek(), Some(&1));
+    g.cancel();
+    assert!(g.is_done());
+    g.reset_with(|_| 3).unwrap();
+    assert_eq!(g.resume_ref().copied(), Some(3));
+
+    let mut g = Gn::<u32>::new_stackless(|para| Resume::Yield(para.unwrap_or(0)));
+    assert_eq!(g.send(4), 4);
+    g.cancel();
+    assert!(g.is_done());
+}
+
+#[test]
+#[cfg(feature = "fuzz")]
+fn test_fuzz_run() {
+    // xorshift, the inputs are the same in every run
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    for _ in 0..200 {
+        let len = (next() % 256) as usize;
+        let data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+        generator::fuzz::run(&data);
+    }
+}
+
+#[test]
+#[cfg(feature = "deterministic")]
+fn test_deterministic() {
+    use generator::deterministic;
+
+    fn park(mut s: Scope<'_, 'static, (), usize>) -> usize {
+        s.yield_(1);
+        0
+    }
+
+    #[inline(never)]
+    fn deep(mut s: Scope<'_, 'static, (), usize>) -> usize {
+        let buf = std::hint::black_box([0x5au8; 0x2000]);
+        s.yield_(buf[0] as usize);
+        0
+    }
+
+    let run = || {
+        deterministic::enable(7);
+        let mut g = Gn::<()>::new_scoped(park);
+        assert_eq!(g.next(), Some(1));
+        g.id()
+    };
+    // the same address for the same allocations
+    let id = run();
+    assert_eq!(run(), id);
+
+    // a reused stack keeps its address
+    deterministic::enable(7);
+    let mut g = Gn::<()>::new_scoped(deep);
+    assert_eq!(g.next(), Some(0x5a));
+    g.cancel();
+    g.reset_with(park).unwrap();
+    assert_eq!(g.next(), Some(1));
+    assert_eq!(g.id(), id);
+    drop(g);
+
+    deterministic::disable();
+    assert!(!deterministic::is_enabled());
+}
+
+#[test]
+#[cfg(feature = "dump")]
+fn test_write_dump() {
+    use generator::dump::{write_dump, DUMP_MAGIC};
+    use std::collections::HashMap;
+
+    // the state and the frames of each generator
+    fn parse(buf: &[u8]) -> HashMap<usize, (u8, Vec<u8>)> {
+        assert_eq!(buf[..8], DUMP_MAGIC);
+        let u64_at = |pos: usize| u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap());
+        let mut records = HashMap::new();
+        let mut pos = 16;
+        while pos < buf.len() {
+            let len = u64_at(pos + 25) as usize;
+            let frames = buf[pos + 33..pos + 33 + len].to_vec();
+            records.insert(u64_at(pos) as usize, (buf[pos + 8], frames));
+            pos += 33 + len;
+        }
+        assert_eq!(pos, buf.len());
+        records
+    }
+
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let marker = std::hint::black_box(*b"dumped frame!!!!");
+        s.yield_(marker.len());
+        0
+    });
+    let fresh = Gn::<()>::new_scoped(|_| 0);
+    assert_eq!(g.next(), Some(16));
+
+    let mut buf = vec![];
+    assert!(write_dump(&mut buf).unwrap() >= 2);
+    let records = parse(&buf);
+    let (state, frames) = &records[&g.id()];
+    assert_eq!(*state, 1);
+    assert!(frames.windows(16).any(|w| w == b"dumped frame!!!!"));
+    assert_eq!(records[&fresh.id()], (0, vec![]));
+
+    // the dropped generator is gone
+    let id = g.id();
+    drop(g);
+    let mut buf = vec![];
+    write_dump(&mut buf).unwrap();
+    assert!(!parse(&buf).contains_key(&id));
+}
+
+#[test]
+fn test_current_id() {
+    use std::ffi::CStr;
+
+    assert_eq!(current_id(), None);
+    let name = CStr::from_bytes_with_nul(b"outer\0").unwrap();
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let outer = current_id();
+        let mut inner = Gn::<()>::new_scoped(|mut s| {
+            s.yield_((current_id(), current_name()));
+            done!();
+        });
+        let (id, name) = inner.next().unwrap();
+        assert_eq!(id, Some(inner.id()));
+        assert_eq!(name, None);
+        // back to the outer generator
+        assert_eq!(current_id(), outer);
+        s.yield_((outer, current_name()));
+        done!();
+    });
+    g.set_name(name);
+    assert_eq!(g.name(), Some(name));
+    assert_eq!(g.next(), Some((Some(g.id()), Some(name))));
+    assert_eq!(current_id(), None);
+    assert_eq!(current_name(), None);
+
+    #[cfg(feature = "ffi")]
+    {
+        use generator::ffi::{generator_current_id, generator_current_name};
+        assert_eq!(generator_current_id(), 0);
+        assert!(generator_current_name().is_null());
+        let mut g = Gn::<()>::new_scoped(|mut s| {
+            s.yield_((generator_current_id(), generator_current_name() as usize));
+            done!();
+        });
+        g.set_name(name);
+        assert_eq!(g.next(), Some((g.id(), name.as_ptr() as usize)));
+    }
+}
+
+#[test]
+fn test_nesting_depth() {
+    use std::ffi::CStr;
+
+    assert_eq!((nesting_depth(), parent_id()), (0, None));
+    let name = CStr::from_bytes_with_nul(b"outer\0").unwrap();
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let outer = current_id();
+        let mut inner = Gn::<()>::new_scoped(|mut s| {
+            s.yield_((nesting_depth(), parent_id(), parent_name()));
+            done!();
+        });
+        let (depth, parent, name) = inner.next().unwrap();
+        assert_eq!(depth, 2);
+        assert_eq!(parent, outer);
+        s.yield_((depth, nesting_depth(), parent_id().is_none(), name));
+        done!();
+    });
+    g.set_name(name);
+    assert_eq!(g.next(), Some((2, 1, true, Some(name))));
+    assert_eq!(nesting_depth(), 0);
+}
+
+#[test]
+#[cfg(feature = "stack-rss")]
+fn test_stack_resident_bytes() {
+    #[inline(never)]
+    fn deep(s: &mut Scope<'_, 'static, (), usize>) {
+        let buf = std::hint::black_box([1u8; 0x10000]);
+        s.yield_(buf[0] as usize);
+    }
+
+    let mut g = Gn::<()>::new_scoped_opt(0x10000, |mut s| {
+        deep(&mut s);
+        0
+    });
+    let fresh = g.stack_resident_bytes().unwrap();
+    assert!(fresh < 0x10000);
+    assert_eq!(g.next(), Some(1));
+    let used = g.stack_resident_bytes().unwrap();
+    assert!(used >= fresh + 0x10000);
+    // the pages stay resident after the frames are popped
+    assert_eq!(g.next(), Some(0));
+    assert!(g.stack_resident_bytes().unwrap() >= used);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_stack_name() {
+    use std::ffi::CStr;
+
+    let maps = || std::fs::read_to_string("/proc/self/maps").unwrap();
+    let mut g = Gn::<()>::new_scoped(|_| 0);
+    // the kernel may not support the anonymous mapping names
+    if !maps().contains("[anon:generator]") {
+        return;
+    }
+    g.set_name(CStr::from_bytes_with_nul(b"test_stack_name\0").unwrap());
+    assert!(maps().contains("[anon:generator:test_stack_name]"));
+    assert_eq!(g.next(), Some(0));
+}
+
+#[test]
+fn test_into_inner() {
+    let s = String::from("inline");
+    let g = Gn::<()>::new_opt(0x800, move || s.len());
+    match g.into_inner().ok().unwrap() {
+        Inner::Plain(f) => assert_eq!(f(), 6),
+        Inner::Scoped(_) => panic!("the closure is plain"),
+    }
+
+    // a started generator is given back
+    let mut g = Gn::<()>::new_opt(0x800, || {
+        yield_with(1);
+        2
+    });
+    assert_eq!(g.next(), Some(1));
+    let mut g = g.into_inner().err().unwrap();
+    assert_eq!(g.next(), Some(2));
+
+    // a scoped closure re-arms a done generator on its stack
+    let s = String::from("scoped");
+    let g = Gn::<()>::new_scoped_opt(0x800, move |mut sc| {
+        sc.yield_(s.len());
+        s.len() * 2
+    });
+    let f = match g.into_inner().ok().unwrap() {
+        Inner::Scoped(f) => f,
+        Inner::Plain(_) => panic!("the closure is scoped"),
+    };
+    let mut g = Gn::<()>::new_scoped_opt(0x800, |_| 0);
+    assert_eq!(g.next(), Some(0));
+    assert!(g.is_done());
+    // the generator lives on its stack
+    let id = g.id();
+    g.scoped_init(f);
+    assert_eq!(g.id(), id);
+    assert_eq!(g.next(), Some(6));
+    assert_eq!(g.next(), Some(12));
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_stack_remaining() {
+    fn depth(s: &Scope<(), usize>, n: usize) -> usize {
+        if n == 0 {
+            return s.stack_remaining();
+        }
+        let pad = std::hint::black_box([0u8; 256]);
+        depth(s, n - 1) + pad[0] as usize
+    }
+
+    let mut g = Gn::<()>::new_scoped_opt(0x4000, |mut s| {
+        let top = s.stack_remaining();
+        s.yield_(top);
+        depth(&s, 8)
+    });
+    let top = g.next().unwrap();
+    assert!(top > 0 && top < 0x4000 * std::mem::size_of::<usize>());
+    assert!(g.next().unwrap() < top - 8 * 256);
+}
+
+// a foreign function calling back into the generator
+extern "C" fn call_back(cb: extern "C" fn(*mut std::ffi::c_void), arg: *mut std::ffi::c_void) {
+    cb(arg)
+}
+
+#[test]
+fn test_foreign_cancel() {
+    use std::ffi::c_void;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static YIELDS: AtomicUsize = AtomicUsize::new(0);
+    extern "C" fn yield_twice(scope: *mut c_void) {
+        let s = unsafe { &mut *(scope as *mut Scope<(), u32>) };
+        for i in 0..2 {
+            s.yield_with(i);
+            YIELDS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // the deferred cancel finishes the foreign call without suspending
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        foreign_call(|| call_back(yield_twice, &mut s as *mut _ as *mut c_void));
+        unreachable!("the cancel unwinds after the foreign call")
+    });
+    g.set_foreign_cancel(ForeignCancel::Defer);
+    assert_eq!(g.resume(), Some(0));
+    g.cancel();
+    assert!(g.is_done());
+    assert_eq!(YIELDS.load(Ordering::SeqCst), 2);
+
+    // the frames are abandoned
+    let dropped = std::rc::Rc::new(());
+    let d = dropped.clone();
+    let mut g = Gn::<()>::new_scoped_local(move |mut s: Scope<(), u32>| {
+        let _d = d;
+        foreign_call(|| call_back(yield_twice, &mut s as *mut _ as *mut c_void));
+        0u32
+    });
+    g.set_foreign_cancel(ForeignCancel::Leak);
+    assert_eq!(g.resume(), Some(0));
+    drop(g);
+    assert_eq!(std::rc::Rc::strong_count(&dropped), 2);
+}
+
+#[test]
+fn test_generator_local() {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Tracked(Cell<u32>);
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    generator_local! {
+        static ID: Cell<u32> = Cell::new(0);
+        static TRACKED: Tracked = Tracked(Cell::new(1));
+    }
+
+    let gen = |id| {
+        Gn::<()>::new_scoped(move |mut s| {
+            ID.with(|v| v.set(id));
+            TRACKED.with(|t| t.0.set(t.0.get() + id));
+            s.yield_(ID.with(Cell::get));
+            ID.with(Cell::get) + TRACKED.with(|t| t.0.get())
+        })
+    };
+    let mut a = gen(10);
+    let mut b = gen(20);
+    assert_eq!(a.next(), Some(10));
+    assert_eq!(b.next(), Some(20));
+    assert_eq!(a.next(), Some(21));
+    assert_eq!(b.next(), Some(41));
+    assert_eq!(ID.try_with(Cell::get), Err(NotInGenerator));
+
+    assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+    drop(a);
+    drop(b);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_cancel_children() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Log = Rc<RefCell<Vec<u32>>>;
+    type Held = Rc<RefCell<Vec<LocalGenerator<'static, (), u32>>>>;
+
+    // log `n` when the frame holding it is dropped
+    struct Guard(u32, Log);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    fn child(n: u32, log: &Log) -> LocalGenerator<'static, (), u32> {
+        let log = log.clone();
+        let mut g = Gn::<()>::new_scoped_local(move |mut s| {
+            let _guard = Guard(n, log.clone());
+            // a grandchild held by the frames
+            let mut grandchild = (n == 3).then(|| child(30, &log));
+            if let Some(g) = grandchild.as_mut() {
+                g.resume();
+            }
+            loop {
+                s.yield_with(n);
+            }
+        });
+        g.resume();
+        g
+    }
+
+    let log: Log = Rc::default();
+    let held: Held = Rc::default();
+    let (l, h) = (log.clone(), held.clone());
+    let mut parent = Gn::<()>::new_scoped_local(move |mut s| {
+        let _guard = Guard(0, l.clone());
+        let _first = child(1, &l);
+        let _second = child(2, &l);
+        h.borrow_mut().push(child(3, &l));
+        let mut detached = child(4, &l);
+        detached.detach();
+        h.borrow_mut().push(detached);
+        s.yield_with(0);
+        0
+    });
+    assert_eq!(parent.resume(), Some(0));
+
+    parent.cancel();
+    // the children held by the frames are cancelled by the unwinding, the
+    // newest first, before the parent is done
+    assert_eq!(*log.borrow(), [2, 1, 0]);
+    // the escaped ones at their next resume, then their own children
+    let mut held = held.borrow_mut();
+    assert_eq!(held[0].try_resume(), Err(ResumeError::CreatorCancelled));
+    assert!(held[0].is_done());
+    assert_eq!(*log.borrow(), [2, 1, 0, 30, 3]);
+    // a detached one runs on
+    assert_eq!(held[1].try_resume(), Ok(Some(4)));
+
+    // a generator handed out by a factory outlives it once detached
+    let mut factory = Gn::<()>::new_scoped(|mut s| loop {
+        let mut g = Gn::<()>::new_scoped(|mut s| {
+            s.yield_(1);
+            2
+        });
+        g.detach();
+        s.yield_(g);
+    });
+    let mut g = factory.resume().unwrap();
+    drop(factory);
+    assert_eq!(g.try_resume(), Ok(Some(1)));
+    assert_eq!(g.resume(), Some(2));
+
+    // or it's cancelled with the factory
+    let mut factory = Gn::<()>::new_scoped(|mut s| loop {
+        s.yield_(Gn::<()>::new_scoped(|mut s| loop {
+            s.yield_(1);
+        }));
+    });
+    let mut g = factory.resume().unwrap();
+    drop(factory);
+    assert_eq!(g.try_resume(), Err(ResumeError::CreatorCancelled));
+}
+
+#[test]
+fn test_generator_group() {
+    use std::time::Duration;
+
+    let group = GeneratorGroup::new();
+    let mut a = Gn::<()>::new_scoped(|mut s| loop {
+        s.yield_(1);
+    });
+    a.join_group(&group);
+    let mut b = Gn::<()>::new_scoped(|mut s| {
+        // the generators created in a member join its group
+        let child = Gn::<()>::new_scoped(|mut s| loop {
+            s.yield_(2);
+        });
+        s.yield_(child);
+        s.done()
+    });
+    b.join_group(&group);
+    assert_eq!(a.try_resume(), Ok(Some(1)));
+    let mut child = b.resume().unwrap();
+    assert_eq!(child.try_resume(), Ok(Some(2)));
+
+    group.expire();
+    assert!(group.is_expired());
+    assert_eq!(a.try_resume(), Err(ResumeError::DeadlineExceeded));
+    assert!(a.is_done());
+    assert!(b.resume().is_none());
+    assert!(b.is_done());
+    assert_eq!(child.resume(), None);
+    assert!(child.is_done());
+
+    // the deadline expires the group by itself
+    let group = GeneratorGroup::with_timeout(Duration::from_millis(10));
+    let mut g = Gn::<()>::new_scoped(|mut s| loop {
+        s.yield_(1);
+    });
+    g.join_group(&group);
+    assert_eq!(g.try_resume(), Ok(Some(1)));
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(g.try_resume(), Err(ResumeError::DeadlineExceeded));
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_reset_with() {
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    // a fresh generator can be reset
+    g.reset_with(|mut s| {
+        s.yield_(10);
+        20
+    })
+    .unwrap();
+    assert_eq!(g.next(), Some(10));
+    assert_eq!(g.reset_with(|_| 0), Err(ResetError::Suspended));
+    assert_eq!(g.next(), Some(20));
     assert!(g.is_done());
+
+    g.reset_with(|_| 30).unwrap();
+    assert_eq!(g.by_ref().collect::<Vec<_>>(), [30]);
+
+    let mut g = Gn::<()>::new_scoped_local(|mut s| {
+        s.yield_with(1);
+        2
+    });
+    g.next();
+    assert_eq!(g.reset_with(|_| 0), Err(ResetError::Suspended));
+    g.cancel();
+    g.reset_with(|_| 3).unwrap();
+    assert_eq!(g.next(), Some(3));
+}
+
+#[test]
+fn test_restart() {
+    let data = vec![1, 2, 3];
+    let mut g = Gn::<()>::new_restartable(move |mut s| {
+        for &v in &data {
+            s.yield_with(v);
+        }
+        done!()
+    });
+    assert_eq!(g.by_ref().collect::<Vec<_>>(), [1, 2, 3]);
+    assert!(g.restart());
+    assert_eq!(g.by_ref().collect::<Vec<_>>(), [1, 2, 3]);
+
+    // restart in the middle of a traversal
+    assert!(g.restart());
+    assert_eq!(g.next(), Some(1));
+    assert!(g.restart());
+    assert_eq!(g.by_ref().sum::<i32>(), 6);
+
+    let mut g = Gn::<()>::new_scoped(|_| 0);
+    assert!(!g.restart());
+
+    // the replaced closure is not remembered
+    let mut g = Gn::<()>::new_restartable(|_| 1);
+    g.reset_with(|_| 2).unwrap();
+    assert!(!g.restart());
+}
+
+#[test]
+fn test_clone_generator() {
+    let data = std::sync::Arc::new(vec![1, 2, 3]);
+    let template = {
+        let data = data.clone();
+        Gn::<()>::new_restartable_opt(0x801, move |mut s| {
+            for &v in data.iter() {
+                s.yield_with(v);
+            }
+            done!()
+        })
+    };
+    let mut gens: Vec<_> = (0..4).map(|_| template.try_clone().unwrap()).collect();
+    // each generator holds the closure to run and the remembered one
+    assert_eq!(std::sync::Arc::strong_count(&data), 11);
+    for g in &mut gens {
+        assert_eq!(g.stack_usage().0, template.stack_usage().0);
+        assert_eq!(g.by_ref().sum::<i32>(), 6);
+        // a clone is restartable too
+        assert!(g.restart());
+    }
+    assert_eq!(gens[0].by_ref().collect::<Vec<_>>(), [1, 2, 3]);
+
+    let mut g = template;
+    assert_eq!(g.next(), Some(1));
+    assert!(g.try_clone().is_none());
+    assert!(Gn::<()>::new_scoped(|_| 0).try_clone().is_none());
+
+    drop(gens);
+    drop(g);
+    assert_eq!(std::sync::Arc::strong_count(&data), 1);
+}
+
+#[test]
+fn test_state() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let me = std::sync::Arc::new(AtomicUsize::new(0));
+    let g = Gn::<()>::new_scoped({
+        let me = me.clone();
+        move |mut s| {
+            let g = me.load(Ordering::Relaxed) as *const Generator<(), State>;
+            s.yield_(unsafe { (*g).state() });
+            done!()
+        }
+    });
+    let g = Box::into_raw(Box::new(g));
+    me.store(g as usize, Ordering::Relaxed);
+    let mut g = unsafe { Box::from_raw(g) };
+    assert_eq!(g.state(), State::Fresh);
+    assert_eq!(g.next(), Some(State::Running));
+    assert_eq!(g.state(), State::Suspended);
+    assert_eq!(g.next(), None);
+    assert_eq!(g.state(), State::Done);
+
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    g.next();
+    g.cancel();
+    assert_eq!(g.state(), State::Done);
+    g.reset_with(|_| 3).unwrap();
+    assert_eq!(g.state(), State::Fresh);
+}
+
+#[test]
+fn test_counters() {
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        s.yield_(2);
+        3
+    });
+    assert_eq!((g.resume_count(), g.yield_count()), (0, 0));
+    assert_eq!(g.by_ref().count(), 3);
+    assert_eq!((g.resume_count(), g.yield_count()), (3, 2));
+
+    g.reset_with(|mut s| {
+        s.yield_(1);
+        2
+    })
+    .unwrap();
+    assert_eq!((g.resume_count(), g.yield_count()), (0, 0));
+    g.next();
+    g.cancel();
+    assert_eq!((g.resume_count(), g.yield_count()), (2, 1));
+}
+
+#[test]
+fn test_is_cancel_payload() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let cancels = std::sync::Arc::new(AtomicUsize::new(0));
+    let mut g = Gn::<()>::new_scoped({
+        let cancels = cancels.clone();
+        move |mut s| {
+            let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                s.yield_(1);
+            }));
+            if let Err(e) = r {
+                if is_cancel_payload(&e) {
+                    cancels.fetch_add(1, Ordering::Relaxed);
+                }
+                std::panic::resume_unwind(e);
+            }
+            2
+        }
+    });
+    assert_eq!(g.next(), Some(1));
+    g.cancel();
+    assert!(g.is_done());
+    assert_eq!(cancels.load(Ordering::Relaxed), 1);
+
+    let e = std::panic::catch_unwind(|| panic!("real")).unwrap_err();
+    assert!(!is_cancel_payload(&e));
+    assert_eq!(Error::Cancel.to_string(), "generator is cancelled");
+}
+
+#[test]
+fn test_scope_done() {
+    let g = Gn::<()>::new_scoped(|mut s| {
+        for i in 0..3 {
+            s.yield_(i);
+        }
+        s.done()
+    });
+    assert_eq!(g.collect::<Vec<_>>(), [0, 1, 2]);
+
+    let mut g = Gn::<()>::new_scoped_local(|mut s| {
+        s.yield_with(1);
+        if s.get_yield().is_none() {
+            s.done();
+        }
+        2
+    });
+    assert_eq!(g.next(), Some(1));
+    assert_eq!(g.next(), None);
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_try_yield() {
+    #[derive(Debug, PartialEq)]
+    struct BadInput(String);
+
+    impl From<std::num::ParseIntError> for BadInput {
+        fn from(e: std::num::ParseIntError) -> Self {
+            BadInput(e.to_string())
+        }
+    }
+
+    let g = Gn::<()>::new_scoped(|mut s| {
+        for v in ["1", "2", "x", "3"] {
+            try_yield!(s, v.parse::<u32>());
+        }
+        Ok(0)
+    });
+    let out: Vec<Result<u32, BadInput>> = g.collect();
+    assert_eq!(out.len(), 3);
+    assert_eq!(out[..2], [Ok(1), Ok(2)]);
+    assert!(out[2].is_err());
+
+    // bail out from a nested call
+    fn check(s: &mut Scope<'_, 'static, (), Result<u32, String>>, v: u32) {
+        let r = if v < 2 { Ok(v) } else { Err(format!("{v}")) };
+        s.try_yield(r);
+    }
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        for v in 0..5 {
+            check(&mut s, v);
+        }
+        Ok(5)
+    });
+    assert_eq!(g.next(), Some(Ok(0)));
+    assert_eq!(g.next(), Some(Ok(1)));
+    assert_eq!(g.next(), Some(Err("2".into())));
+    assert!(g.is_done());
+    assert_eq!(g.next(), None);
+}
+
+#[test]
+fn test_stackless() {
+    let mut n = 0;
+    let mut g = Gn::<u32>::new_stackless(move |para| {
+        n += para.unwrap_or(1);
+        if n < 10 {
+            Resume::Yield(n)
+        } else {
+            Resume::Return(n)
+        }
+    });
+    assert_eq!(g.state(), State::Fresh);
+    assert_eq!(g.send(3), 3);
+    assert_eq!(g.state(), State::Suspended);
+    assert_eq!(g.resume(), Some(4));
+    assert_eq!(g.send(5), 9);
+    assert_eq!(g.send(5), 14);
+    assert!(g.is_done());
+    assert_eq!(g.resume(), None);
+    assert_eq!((g.resume_count(), g.yield_count()), (4, 3));
+    // the generator is on the heap, there is no system stack
+    let (total, used) = g.stack_usage();
+    assert!(total * std::mem::size_of::<usize>() < 0x1000);
+    assert_eq!(used, 0);
+
+    let mut v = vec![1, 2, 3].into_iter();
+    let g = Gn::<()>::new_stackless_local(move |_| match v.next() {
+        Some(x) => Resume::Yield(x),
+        None => Resume::Done,
+    });
+    assert_eq!(g.collect::<Vec<_>>(), [1, 2, 3]);
+
+    // cancel drops the state machine
+    let rc = std::sync::Arc::new(0);
+    let r = rc.clone();
+    let mut g = Gn::<()>::new_stackless(move |_| Resume::Yield(*r));
+    assert_eq!(g.next(), Some(0));
+    assert_eq!(std::sync::Arc::strong_count(&rc), 2);
+    g.cancel();
+    assert!(g.is_done());
+    assert_eq!(std::sync::Arc::strong_count(&rc), 1);
+
+    // a panic finishes the generator
+    let mut g = Gn::<()>::new_stackless(|_| -> Resume<u32> { panic!("step") });
+    let e = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| g.next())).unwrap_err();
+    assert_eq!(e.downcast_ref::<&str>(), Some(&"step"));
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_generator_trait() {
+    let base = 10;
+    let mut gens: Vec<Box<dyn GeneratorTrait<In = u32, Out = u32> + '_>> = vec![
+        Box::new(Gn::<u32>::new_scoped(|mut s| {
+            let mut v = s.get_yield().unwrap();
+            loop {
+                s.yield_with(v + base);
+                v = s.get_yield().unwrap();
+            }
+        })),
+        Box::new(Gn::<u32>::new_scoped_local(|mut s| {
+            let v = s.get_yield().unwrap();
+            v * 2
+        })),
+        Box::new(Gn::<u32>::new_stackless(|v| Resume::Yield(v.unwrap()))),
+    ];
+
+    let out: Vec<u32> = gens.iter_mut().map(|g| g.send(1)).collect();
+    assert_eq!(out, [11, 2, 1]);
+    assert!(gens[1].is_done());
+    assert_eq!(gens[1].raw_send(Some(1)), None);
+    for g in gens.iter_mut() {
+        g.cancel();
+        assert!(g.is_done());
+        assert_eq!(g.resume(), None);
+    }
+}
+
+#[test]
+fn test_infinite() {
+    let g = Gn::<()>::new_scoped_infinite(|mut s| {
+        let mut i = 0;
+        loop {
+            s.yield_(i);
+            i += 1;
+        }
+    });
+    assert_eq!(g.size_hint(), (usize::MAX, None));
+    assert_eq!(g.take(4).collect::<Vec<_>>(), [0, 1, 2, 3]);
+
+    let mut g = Gn::<u32>::new_scoped_infinite(|mut s| {
+        let mut total = 0;
+        loop {
+            total += s.yield_(total).unwrap_or(0);
+        }
+    });
+    g.resume();
+    assert_eq!(g.send(2), 2);
+    assert_eq!(g.send(3), 5);
+    g.cancel();
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_block_on() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    // ready after being polled `n` times
+    struct Countdown(u32);
+    impl Future for Countdown {
+        type Output = u32;
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<u32> {
+            if self.0 == 0 {
+                return Poll::Ready(42);
+            }
+            self.0 -= 1;
+            Poll::Pending
+        }
+    }
+
+    let g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(Poll::Ready(1));
+        let v = s.block_on(Countdown(2));
+        s.yield_(Poll::Ready(v));
+        let v = s.block_on(async { Countdown(1).await + 1 });
+        Poll::Ready(v)
+    });
+    let v: Vec<_> = g.collect();
+    assert_eq!(
+        v,
+        [
+            Poll::Ready(1),
+            Poll::Pending,
+            Poll::Pending,
+            Poll::Ready(42),
+            Poll::Pending,
+            Poll::Ready(43)
+        ]
+    );
+}
+
+#[test]
+#[cfg(all(feature = "reactor", target_os = "linux"))]
+fn test_reactor() {
+    use generator::reactor::Reactor;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    let (mut a, b) = UnixStream::pair().unwrap();
+    a.set_nonblocking(true).unwrap();
+    let slot = Arc::new(Mutex::new(None::<Waker>));
+    let waiter = slot.clone();
+    let mut exec = Executor::with_driver(Reactor::new().unwrap());
+    exec.spawn(Gn::new_scoped(move |mut s| {
+        let mut buf = [0; 4];
+        let mut n = 0;
+        while n < buf.len() {
+            match a.read(&mut buf[n..]) {
+                Ok(len) => n += len,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    s.wait_readable(a.as_raw_fd()).unwrap()
+                }
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(&buf, b"ping");
+        Poll::Ready(())
+    }));
+    // a waker woken on another thread unparks the reactor
+    exec.spawn(Gn::new_scoped(move |mut s| {
+        s.yield_pending(|w| *waiter.lock().unwrap() = Some(w));
+        Poll::Ready(())
+    }));
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        (&b).write_all(b"pi").unwrap();
+        thread::sleep(Duration::from_millis(10));
+        (&b).write_all(b"ng").unwrap();
+        loop {
+            if let Some(w) = slot.lock().unwrap().take() {
+                break w.wake();
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+    exec.run();
+    t.join().unwrap();
+
+    // the fds can't be waited without a driver
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let e = s.wait_readable(0).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Other);
+        Poll::Ready(())
+    });
+    assert_eq!(g.resume(), Some(Poll::Ready(())));
+}
+
+#[test]
+#[cfg(all(feature = "reactor", target_os = "linux"))]
+fn test_reactor_unpark_race() {
+    use generator::reactor::{Driver, Reactor};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    let mut reactor = Reactor::new().unwrap();
+    let unparker = reactor.unparker();
+    let done = Arc::new(AtomicBool::new(false));
+    let stop = done.clone();
+    // the wakes around each park must never be lost
+    let t = thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            unparker.wake_by_ref();
+        }
+    });
+    for _ in 0..10000 {
+        reactor.park(None).unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    t.join().unwrap();
+}
+
+#[test]
+#[cfg(feature = "spawn")]
+fn test_spawn_detached() {
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+
+    struct Unpark(std::thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = std::pin::pin!(fut);
+        let waker = Arc::new(Unpark(std::thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    let spawn = |job| {
+        std::thread::spawn(job);
+    };
+    let fut = Gn::<()>::new_scoped(|mut s| {
+        for i in 0..10 {
+            s.yield_(i);
+        }
+        100
+    })
+    .spawn_detached(spawn);
+    assert_eq!(block_on(fut), Some(100));
+
+    let fut = Gn::<()>::new_scoped(|_| done!()).spawn_detached(spawn);
+    assert_eq!(block_on(fut), None::<i32>);
+
+    let fut = Gn::<()>::new_scoped(|_| -> i32 { panic!("in worker") }).spawn_detached(spawn);
+    let e = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| block_on(fut))).unwrap_err();
+    assert_eq!(e.downcast_ref::<&str>(), Some(&"in worker"));
+
+    // a waker polling the future right away doesn't deadlock
+    type Job = Box<dyn FnOnce() + Send>;
+    type Fut = std::sync::Mutex<std::pin::Pin<Box<Spawned<i32>>>>;
+    struct Repoll(Fut, std::sync::Mutex<Option<Option<i32>>>);
+    impl Wake for Repoll {
+        fn wake(self: Arc<Self>) {
+            let waker = self.clone().into();
+            let mut cx = Context::from_waker(&waker);
+            if let Poll::Ready(v) = self.0.lock().unwrap().as_mut().poll(&mut cx) {
+                *self.1.lock().unwrap() = Some(v);
+            }
+        }
+    }
+
+    let job = std::sync::Mutex::new(None::<Job>);
+    let fut = Gn::<()>::new_scoped(|_| 7).spawn_detached(|j| *job.lock().unwrap() = Some(j));
+    let repoll = Arc::new(Repoll(
+        std::sync::Mutex::new(Box::pin(fut)),
+        Default::default(),
+    ));
+    let waker = repoll.clone().into();
+    let pending = repoll
+        .0
+        .lock()
+        .unwrap()
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker));
+    assert!(pending.is_pending());
+    job.lock().unwrap().take().unwrap()();
+    assert_eq!(*repoll.1.lock().unwrap(), Some(Some(7)));
+}
+
+#[test]
+fn test_executor() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    // completed by a thread, counts the polls
+    #[derive(Default)]
+    struct Shared {
+        done: bool,
+        polls: usize,
+        waker: Option<std::task::Waker>,
+    }
+    struct Delay(Arc<Mutex<Shared>>);
+    impl Future for Delay {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut shared = self.0.lock().unwrap();
+            shared.polls += 1;
+            if shared.done {
+                return Poll::Ready(());
+            }
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let timer = {
+        let shared = shared.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            let mut shared = shared.lock().unwrap();
+            shared.done = true;
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        })
+    };
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut exec = Executor::new();
+    exec.spawn(Gn::new_scoped({
+        let (shared, order) = (shared.clone(), order.clone());
+        move |mut s| {
+            s.block_on(Delay(shared));
+            order.lock().unwrap().push("delay");
+            Poll::Ready(())
+        }
+    }));
+    exec.spawn(Gn::new_scoped({
+        let order = order.clone();
+        move |mut s| {
+            for _ in 0..3 {
+                order.lock().unwrap().push("tick");
+                s.yield_(Poll::Ready(()));
+            }
+            Poll::Ready(())
+        }
+    }));
+    exec.run();
+    timer.join().unwrap();
+    assert_eq!(*order.lock().unwrap(), ["tick", "tick", "tick", "delay"]);
+    // only polled again after the wakeup
+    assert!(shared.lock().unwrap().polls <= 2);
+
+    // without a waker the driver polls
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_pending(|w| w.wake());
+        Poll::Ready(1)
+    });
+    assert_eq!(g.next(), Some(Poll::Pending));
+    assert_eq!(g.next(), Some(Poll::Ready(1)));
+}
+
+#[test]
+fn test_pipeline() {
+    use std::sync::{Arc, Mutex};
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let l = log.clone();
+    let producer = Gn::<()>::new_scoped(move |mut s| {
+        for i in 0..5 {
+            l.lock().unwrap().push(format!("p{i}"));
+            s.yield_(i);
+        }
+        done()
+    });
+    let pipeline = Pipeline::new(producer)
+        .then(2, |mut stage| {
+            while let Some(v) = stage.recv() {
+                stage.send(v * 10);
+            }
+        })
+        // a stage may take several inputs for an output
+        .then(1, |mut stage| {
+            while let Some(a) = stage.recv() {
+                let b = stage.recv().unwrap_or(0);
+                stage.send(a + b);
+            }
+        });
+    let mut out = Vec::new();
+    for v in pipeline {
+        log.lock().unwrap().push(format!("c{v}"));
+        out.push(v);
+    }
+    assert_eq!(out, [10, 50, 40]);
+    // the producer is held back by the full buffers
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["p0", "p1", "c10", "p2", "p3", "c50", "p4", "c40"]
+    );
+
+    // the stages are dropped with the pipeline
+    let producer = Gn::<()>::new_scoped(|mut s| loop {
+        s.yield_(1);
+    });
+    let mut pipeline = Pipeline::new(producer).then(4, |mut stage| loop {
+        let v = stage.recv().unwrap();
+        stage.send(v);
+    });
+    assert_eq!(pipeline.next(), Some(1));
+}
+
+#[test]
+fn test_par_pipeline() {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    let main = thread::current().id();
+    let producer = Gn::<()>::new_scoped(|mut s| {
+        for i in 0..1000u64 {
+            s.yield_(i);
+        }
+        done()
+    });
+    let out: Vec<_> = ParPipeline::new(1, producer)
+        .then(4, move |mut stage| {
+            assert_ne!(thread::current().id(), main);
+            while let Some(v) = stage.recv() {
+                stage.send(v + 1);
+            }
+        })
+        .then(1, |mut stage| {
+            while let Some(v) = stage.recv() {
+                if v % 2 == 0 {
+                    stage.send(v);
+                }
+            }
+        })
+        .collect();
+    assert_eq!(out, (1..=500).map(|v| v * 2).collect::<Vec<_>>());
+
+    // a panic of a stage stops the others and is propagated
+    let producer = Gn::<()>::new_scoped(|mut s| loop {
+        s.yield_(1);
+    });
+    let mut pipeline = ParPipeline::new(2, producer).then(2, |mut stage: Stage<u32, u32>| {
+        for _ in 0..10 {
+            let v = stage.recv().unwrap();
+            stage.send(v);
+        }
+        panic!("boom");
+    });
+    let e = panic::catch_unwind(AssertUnwindSafe(|| pipeline.by_ref().count())).unwrap_err();
+    assert_eq!(*e.downcast::<&str>().unwrap(), "boom");
+    assert_eq!(pipeline.next(), None);
+
+    // dropping the pipeline cancels the stages
+    struct Flag(Arc<AtomicBool>);
+    impl Drop for Flag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+    let dropped = Arc::new(AtomicBool::new(false));
+    let flag = Flag(dropped.clone());
+    let producer = Gn::<()>::new_scoped(move |mut s| {
+        let _flag = flag;
+        loop {
+            s.yield_(1);
+        }
+    });
+    let mut pipeline = ParPipeline::new(2, producer).then(2, |mut stage: Stage<u32, u32>| loop {
+        let v = stage.recv().unwrap();
+        stage.send(v);
+    });
+    assert_eq!(pipeline.next(), Some(1));
+    drop(pipeline);
+    assert!(dropped.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_executor_sleep() {
+    use std::sync::{Arc, Mutex};
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut exec = Executor::new();
+    let start = Instant::now();
+    for (name, ms) in [("c", 30), ("a", 10), ("b", 20), ("d", 300)] {
+        let order = order.clone();
+        exec.spawn(Gn::new_scoped(move |mut s| {
+            s.sleep(Duration::from_millis(ms));
+            assert!(start.elapsed() >= Duration::from_millis(ms));
+            order.lock().unwrap().push(name);
+            Poll::Ready(())
+        }));
+    }
+    let o = order.clone();
+    exec.spawn(Gn::new_scoped(move |mut s| {
+        s.sleep_until(start);
+        o.lock().unwrap().push("now");
+        Poll::Ready(())
+    }));
+    exec.run();
+    // the timers of the later rounds of the wheel are kept
+    assert_eq!(*order.lock().unwrap(), ["now", "a", "b", "c", "d"]);
+
+    // only the executor resumes a sleeping generator
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.sleep(Duration::from_millis(1));
+        Poll::Ready(())
+    });
+    let e = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| g.resume())).unwrap_err();
+    let msg = e.downcast_ref::<&str>().unwrap();
+    assert!(msg.contains("not run by an executor"));
+}
+
+#[test]
+#[cfg(all(feature = "reactor", target_os = "linux"))]
+fn test_reactor_sleep() {
+    use generator::reactor::Reactor;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+
+    let mut exec = Executor::with_driver(Reactor::new().unwrap());
+    let start = Instant::now();
+    exec.spawn(Gn::new_scoped(move |mut s| {
+        s.sleep(Duration::from_millis(20));
+        Poll::Ready(())
+    }));
+    exec.run();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+#[cfg(feature = "stream")]
+fn test_stream() {
+    use futures_core::Stream;
+    use generator::stream::GenStream;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+
+    // pending until the flag is set
+    struct Gate(Arc<Mutex<(bool, Option<Waker>)>>);
+    impl std::future::Future for Gate {
+        type Output = u32;
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            let mut gate = self.0.lock().unwrap();
+            if gate.0 {
+                return Poll::Ready(7);
+            }
+            gate.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    struct Flag(Mutex<bool>);
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            *self.0.lock().unwrap() = true;
+        }
+    }
+
+    let gate = Arc::new(Mutex::new((false, None)));
+    let pending = gate.clone();
+    let mut s: GenStream<u32> = stream!(|mut s| {
+        s.yield_(Poll::Ready(1));
+        let v = s.block_on(Gate(pending));
+        s.yield_(Poll::Ready(v));
+    });
+    let flag = Arc::new(Flag(Mutex::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut s = Pin::new(&mut s);
+    assert_eq!(s.as_mut().poll_next(&mut cx), Poll::Ready(Some(1)));
+    assert_eq!(s.as_mut().poll_next(&mut cx), Poll::Pending);
+    assert!(!*flag.0.lock().unwrap());
+
+    // the waker of the polling task is woken
+    let mut g = gate.lock().unwrap();
+    g.0 = true;
+    g.1.take().unwrap().wake();
+    drop(g);
+    assert!(*flag.0.lock().unwrap());
+    assert_eq!(s.as_mut().poll_next(&mut cx), Poll::Ready(Some(7)));
+    assert_eq!(s.as_mut().poll_next(&mut cx), Poll::Ready(None));
+    assert_eq!(s.as_mut().poll_next(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn test_reader() {
+    use std::io::{BufRead, Read};
+
+    let g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(b"hello\nwor".to_vec());
+        s.yield_(Vec::new());
+        s.yield_(b"ld\n".to_vec());
+        b"end".to_vec()
+    });
+    let mut r = g.into_reader();
+    let mut line = String::new();
+    r.read_line(&mut line).unwrap();
+    assert_eq!(line, "hello\n");
+    line.clear();
+    r.read_line(&mut line).unwrap();
+    assert_eq!(line, "world\n");
+    let mut rest = String::new();
+    r.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "end");
+    assert_eq!(r.read(&mut [0; 4]).unwrap(), 0);
+    assert!(r.into_inner().is_done());
+}
+
+#[test]
+fn test_writer() {
+    use std::io::Write;
+
+    let out = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let g = Gn::<Vec<u8>>::new_scoped({
+        let out = out.clone();
+        move |mut s| {
+            let mut para = s.get_yield();
+            let mut total = 0;
+            // consume at most 4 bytes per write
+            while let Some(buf) = para {
+                let n = buf.len().min(4);
+                out.lock().unwrap().extend_from_slice(&buf[..n]);
+                total += n;
+                para = s.yield_(n);
+            }
+            total
+        }
+    });
+    let mut w = g.into_writer();
+    write!(w, "hello {}", 42).unwrap();
+    w.write_all(b" world").unwrap();
+    assert_eq!(w.finish(), Some(14));
+    assert_eq!(&*out.lock().unwrap(), b"hello 42 world");
+
+    // the consumer stops early
+    let mut w = Gn::<Vec<u8>>::new_scoped(|mut s| {
+        s.get_yield();
+        s.yield_(1);
+        0
+    })
+    .into_writer();
+    assert_eq!(w.write(b"ab").unwrap(), 1);
+    assert_eq!(w.write(b"b").unwrap(), 0);
+    assert_eq!(
+        w.write(b"b").unwrap_err().kind(),
+        std::io::ErrorKind::BrokenPipe
+    );
+    assert_eq!(w.finish(), None);
+}
+
+#[test]
+#[should_panic]
+fn done_in_normal() {
+    done!();
+}
+
+#[test]
+#[should_panic]
+fn invalid_yield_in_scope() {
+    let g = Gn::new_scoped(|_| {
+        // invalid use raw yield API with scope
+        yield_::<String, _>(());
+    });
+
+    for () in g {}
+}
+
+#[test]
+fn test_yield_float() {
+    let mut g = Gn::<f64>::new(|| {
+        let r: f64 = yield_(10.0).unwrap();
+        let x = r * 2.0; // 6
+        let y = x * 9.0; // 54
+        let z = y / 3.0; // 18
+        let r: f64 = yield_(6.0).unwrap();
+        x * r * y * z
+    });
+
+    // first start the generator
+    let i = g.raw_send(None).unwrap();
+    let x = i * 10.0;
+    assert_eq!(i, 10.0);
+    let i = g.send(3.0);
+    assert_eq!(i, 6.0);
+    let i = g.send(x / 25.0);
+    assert_eq!(i, 23328.0);
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_move_between_resume() {
+    fn step(mut g: Generator<'static, (), u32>) -> Generator<'static, (), u32> {
+        g.resume();
+        g
+    }
+
+    let g = Gn::new_scoped(|mut s| {
+        let mut i = 0;
+        loop {
+            s.yield_(i);
+            i += 1;
+        }
+    });
+
+    // move the handle around between resumes
+    let mut g = Box::new(step(g));
+    assert_eq!(g.resume(), Some(1));
+    let mut v = vec![*g];
+    assert_eq!(v[0].resume(), Some(2));
+    let g = v.pop().unwrap();
+    let h = std::thread::spawn(move || step(g)).join().unwrap();
+
+    let mut g = std::pin::pin!(h);
+    assert_eq!(g.as_mut().resume(), Some(4));
+    assert_eq!(g.resume(), Some(5));
+}
+
+#[test]
+fn test_gen_scope() {
+    let mut total = 0;
+    let mut dropped = false;
+    generator::scope(|s| {
+        let total = &mut total;
+        let dropped = &mut dropped;
+        let g = s.new_scoped(move |mut co| {
+            struct Guard<'a>(&'a mut bool);
+            impl Drop for Guard<'_> {
+                fn drop(&mut self) {
+                    *self.0 = true;
+                }
+            }
+            let _guard = Guard(dropped);
+            loop {
+                let v: u32 = co.get_yield().unwrap_or(0);
+                *total += v;
+                co.yield_with(*total);
+            }
+        });
+        g.raw_send(None);
+        assert_eq!(g.send(1), 1);
+        assert_eq!(g.send(2), 3);
+        // the suspended generator is cancelled by the scope
+    });
+    assert_eq!(total, 3);
+    assert!(dropped);
+}
+
+#[test]
+fn test_yield_ref() {
+    let mut g: LendingGenerator<u8, [u8]> = Gn::new_scoped(|mut s| {
+        let mut buf = Vec::new();
+        let mut v = s.get_yield();
+        while let Some(b) = v {
+            buf.push(b);
+            v = s.yield_ref(&buf[..]);
+        }
+        done!();
+    });
+
+    assert_eq!(g.send_lend(1), Some(&[1][..]));
+    assert_eq!(g.send_lend(2), Some(&[1, 2][..]));
+    let view = g.send_lend(3).unwrap().to_vec();
+    assert_eq!(view, [1, 2, 3]);
+    assert_eq!(g.raw_send(None).map(|_| ()), None);
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_lending_iterator() {
+    fn count<I: LendingIterator>(mut iter: I) -> usize {
+        let mut n = 0;
+        while iter.next().is_some() {
+            n += 1;
+        }
+        n
+    }
+
+    let words = || {
+        Gn::<()>::new_scoped(|mut s| {
+            let mut line = String::new();
+            for word in ["a", "bc", "def"] {
+                line.clear();
+                line.push_str(word);
+                s.yield_ref(line.as_str());
+            }
+            done!();
+        })
+    };
+
+    assert_eq!(count(words()), 3);
+    let mut total = 0;
+    LendingIterator::for_each(words(), |s: &str| total += s.len());
+    assert_eq!(total, 6);
+}
+
+#[test]
+fn test_genawaiter_compat() {
+    use generator::genawaiter::{Co, Gen, GeneratorState};
+
+    // a plain function can yield on behalf of the producer
+    fn countdown(n: u32) {
+        for i in (1..=n).rev() {
+            yield_!(i);
+        }
+    }
+
+    let mut g = gen!({
+        countdown(2);
+        "done"
+    });
+    assert_eq!(g.resume(), GeneratorState::Yielded(2u32));
+    assert_eq!(g.resume(), GeneratorState::Yielded(1));
+    assert_eq!(g.resume(), GeneratorState::Complete("done"));
+
+    // the alias for the 2024 edition
+    let g = generator!({
+        yield_!(1u32);
+    });
+    assert_eq!(g.collect::<Vec<u32>>(), [1]);
+
+    async fn producer(co: Co<u32, bool>) -> u32 {
+        let mut n = 0;
+        while co.yield_(n).await {
+            n += 1;
+        }
+        n
+    }
+    let mut g = Gen::new(producer);
+    assert_eq!(g.resume_with(true), GeneratorState::Yielded(0));
+    assert_eq!(g.resume_with(true), GeneratorState::Yielded(1));
+    assert_eq!(g.resume_with(false), GeneratorState::Complete(1));
+}
+
+#[cfg(all(feature = "unwind", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_backtrace_into_resumer() {
+    #[inline(never)]
+    fn resumer_marker_fn(g: &mut Generator<'static, (), String>) -> Option<String> {
+        g.resume()
+    }
+
+    let mut g = Gn::new_scoped_opt(0x4000, |mut s| {
+        let bt = std::backtrace::Backtrace::force_capture().to_string();
+        s.yield_(bt);
+        String::new()
+    });
+    let bt = resumer_marker_fn(&mut g).unwrap();
+    assert!(bt.contains("resumer_marker_fn"), "{bt}");
+}
+
+// the debug build keeps the frame pointers
+#[cfg(all(feature = "frame-chain", target_arch = "x86_64", debug_assertions))]
+#[test]
+fn test_frame_chain_into_resumer() {
+    // walk the frame pointer chain and return the frame addresses
+    #[inline(never)]
+    fn walk() -> Vec<usize> {
+        let mut fp: usize;
+        unsafe { std::arch::asm!("mov {}, rbp", out(reg) fp) };
+        let mut frames = vec![];
+        while fp != 0 && fp % 8 == 0 && frames.len() < 64 {
+            frames.push(fp);
+            fp = unsafe { *(fp as *const usize) };
+        }
+        frames
+    }
+
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(walk());
+        vec![]
+    });
+    let resumer = walk()[0];
+    let frames = g.next().unwrap();
+    // the chain leaves the generator stack and reaches the resumer frames
+    let gen = frames[0];
+    assert!(gen.abs_diff(resumer) > 0x10000);
+    assert!(
+        frames.iter().any(|&fp| fp.abs_diff(resumer) < 0x1000),
+        "{frames:x?}"
+    );
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi() {
+    use generator::ffi::*;
+    use std::os::raw::c_void;
+
+    // count up from *arg, stop when cancelled
+    unsafe extern "C" fn body(scope: *mut FfiScope<'static>, arg: *mut c_void) -> *mut c_void {
+        let mut i = *(arg as *mut usize);
+        while !generator_is_cancelled(scope) {
+            i += 1;
+            *(arg as *mut usize) = i;
+            let para = generator_yield(scope, arg);
+            if !para.is_null() {
+                i += *(para as *mut usize);
+            }
+        }
+        std::ptr::null_mut()
+    }
+
+    unsafe {
+        let mut n = 10usize;
+        let mut step = 100usize;
+        let g = generator_new(body, &mut n as *mut usize as *mut c_void, 0);
+        assert_eq!(*(generator_resume(g) as *mut usize), 11);
+        let p = generator_send(g, &mut step as *mut usize as *mut c_void);
+        assert_eq!(*(p as *mut usize), 112);
+        assert!(!generator_is_done(g));
+        generator_cancel(g);
+        assert!(generator_is_done(g));
+        assert!(generator_resume(g).is_null());
+        generator_free(g);
+        assert_eq!(n, 112);
+    }
+}
+
+#[test]
+fn test_raw_round_trip() {
+    let g = Gn::<u32>::new_scoped(|mut s| {
+        let v = s.get_yield().unwrap();
+        s.yield_(v + 1);
+        0
+    });
+    let raw = g.into_raw();
+    let mut g = unsafe { Generator::<u32, u32>::from_raw(raw) };
+    assert_eq!(g.send(1), 2);
+}
+
+#[cfg(checks)]
+#[test]
+#[should_panic(expected = "type mismatch")]
+fn test_raw_type_mismatch() {
+    let g = Gn::<u32>::new_scoped(|_| 0u32);
+    let raw = g.into_raw();
+    let _g = unsafe { Generator::<u32, String>::from_raw(raw) };
+}
+
+#[cfg(checks)]
+#[test]
+#[should_panic(expected = "already reclaimed")]
+fn test_raw_double_reclaim() {
+    let g = Gn::<u32>::new_scoped(|_| 0u32);
+    let raw = g.into_raw();
+    let _g = unsafe { Generator::<u32, u32>::from_raw(raw) };
+    let _g2 = unsafe { Generator::<u32, u32>::from_raw(raw) };
+}
+
+#[test]
+fn test_size_hint() {
+    let mut g = Gn::new_scoped(|mut s| {
+        for i in 0..3 {
+            s.yield_(i);
+        }
+        done!();
+    });
+    assert_eq!(g.size_hint(), (0, None));
+    // the done!() doesn't produce an item
+    g.set_size_hint(3, Some(3));
+    assert_eq!(g.next(), Some(0));
+    assert_eq!(g.size_hint(), (2, Some(2)));
+    let rest: Vec<_> = g.by_ref().collect();
+    assert_eq!(rest, [1, 2]);
+    assert_eq!(g.size_hint(), (0, Some(0)));
+    // fused
+    assert_eq!(g.next(), None);
+    assert_eq!(g.next(), None);
+}
+
+#[test]
+fn test_iter_by_mut_ref() {
+    let mut g = Gn::new_scoped(|mut s| {
+        for i in 0..5 {
+            s.yield_(i);
+        }
+        done!();
+    });
+
+    for i in &mut g {
+        if i == 2 {
+            break;
+        }
+    }
+    assert!(!g.is_done());
+
+    let mut rest = vec![];
+    for i in &mut g {
+        rest.push(i);
+    }
+    assert_eq!(rest, [3, 4]);
+    assert!(g.is_done());
+    let (total, used) = g.stack_usage();
+    assert!(used < total);
+}
+
+#[test]
+fn test_peek() {
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let mut total = 0;
+        for i in 0..3 {
+            total += s.yield_(i).unwrap_or(0);
+        }
+        total
+    });
+
+    assert_eq!(g.peek(), Some(&0));
+    assert_eq!(g.peek(), Some(&0));
+    // the buffered value is returned, the para is seen in the next resume
+    assert_eq!(g.send(10), 0);
+    assert_eq!(g.peek(), Some(&1));
+    assert_eq!(g.send(20), 1);
+    // resume without touching the para saved by the last send
+    assert_eq!(g.resume(), Some(2));
+    assert_eq!(g.peek(), Some(&30));
+    assert!(g.is_done());
+    assert_eq!(g.resume(), Some(30));
+    assert_eq!(g.peek(), None);
+}
+
+#[test]
+fn test_send_combinators() {
+    let g = Gn::<u32>::new_scoped(|mut s| {
+        let mut total = 0;
+        for i in 0..6 {
+            total += s.yield_(i).unwrap_or(0);
+        }
+        total * 2
+    });
+
+    // the para goes through the adaptors to the underlying generator
+    let mut g = g
+        .filter_yield(|v| v % 2 == 0)
+        .map_yield(|v| v * 10)
+        .take_while_yield(|&v| v < 1000);
+    assert_eq!(g.send(1), 0);
+    assert_eq!(g.send(2), 20);
+    assert_eq!(g.send(3), 40);
+    assert!(!g.is_done());
+    // skipped values are resumed without para, the total is (2 + 3 + 4) * 2
+    assert_eq!(g.send(4), 180);
+    assert!(g.is_done());
+
+    let g = Gn::<()>::new_scoped(|mut s| {
+        for i in 0..10 {
+            s.yield_(i);
+        }
+        done!();
+    });
+    let v: Vec<u32> = g
+        .take_while_yield(|&v| v < 3)
+        .map_yield(|v| v + 1)
+        .collect();
+    assert_eq!(v, [1, 2, 3]);
+}
+
+#[test]
+fn test_map_return() {
+    let g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        s.yield_(2);
+        3
+    });
+    let v: Vec<u32> = g.map_return(|r| r * 10).collect();
+    assert_eq!(v, [1, 2, 30]);
+
+    // no return value to map
+    let g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        done!()
+    });
+    let v: Vec<u32> = g.map_return(|_| unreachable!()).collect();
+    assert_eq!(v, [1]);
+
+    // adapt the return value of a sub generator
+    let g = Gn::<()>::new_scoped(|mut s| {
+        let sub = Gn::new_scoped(|mut s| {
+            s.yield_(1);
+            2
+        });
+        s.yield_from(sub.map_return(|r| r + 100));
+        0
+    });
+    assert_eq!(g.collect::<Vec<u32>>(), [1, 102, 0]);
+}
+
+#[test]
+fn test_repeat_with() {
+    let mut total = 0;
+    let mut g = Gn::repeat_with(|v: u32| {
+        total += v;
+        total
+    });
+    assert_eq!(g.send(1), 1);
+    assert_eq!(g.send(2), 3);
+    assert_eq!(g.send(3), 6);
+    assert!(!g.is_done());
+    assert_eq!(g.resume(), None);
+    assert!(g.is_done());
+    drop(g);
+    assert_eq!(total, 6);
+
+    let g = Gn::repeat_with(|v: u32| v * 2);
+    let v: Vec<_> = Gn::zip(g, Gn::repeat_with(|v: u32| v + 1))
+        .send_iter(0..3)
+        .collect();
+    assert_eq!(v, [(0, 1), (2, 2), (4, 3)]);
+}
+
+#[test]
+fn test_zip_chain() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Guard<'a>(&'a AtomicUsize);
+    impl Drop for Guard<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let a = Gn::<u32>::new_scoped(|mut s| {
+        let mut v = 0;
+        loop {
+            v += s.yield_(v).unwrap_or(1);
+        }
+    });
+    let b = Gn::<u32>::new_scoped(|mut s| {
+        for i in 0..3 {
+            s.yield_(i * 10);
+        }
+        done!();
+    });
+    let mut z = Gn::zip(a, b);
+    assert_eq!(z.send(0), (0, 0));
+    assert_eq!(z.send(5), (5, 10));
+    assert_eq!(z.send(2), (7, 20));
+    assert_eq!(z.raw_send(Some(1)), None);
+    assert!(z.is_done());
+
+    let a = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    let b = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(3);
+        done!();
+    });
+    let v: Vec<i32> = Gn::chain(a, b).collect();
+    assert_eq!(v, [1, 2, 3]);
+
+    // cancelling the adaptor cancels the suspended inputs
+    let dropped = AtomicUsize::new(0);
+    let a = Gn::<()>::new_scoped(|mut s| {
+        let _g = Guard(&dropped);
+        loop {
+            s.yield_with(0);
+        }
+    });
+    let b = Gn::<()>::new_scoped(|mut s| {
+        let _g = Guard(&dropped);
+        loop {
+            s.yield_with(1);
+        }
+    });
+    let mut z = Gn::zip(a, b);
+    assert_eq!(z.next(), Some((0, 1)));
+    drop(z);
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_merge_broadcast() {
+    use std::task::Poll;
+
+    let gen = |name: &'static str, n: u32| {
+        Gn::<u32>::new_scoped(move |mut s| {
+            let mut para = 0;
+            for i in 0..n {
+                para = s.yield_(format!("{name}{i}")).unwrap();
+            }
+            // the para of the last resume
+            format!("{name}={para}")
+        })
+    };
+    let mut m = Gn::merge(vec![gen("a", 1), gen("b", 3), gen("c", 0)]);
+    let out: Vec<_> = m.send_iter(1..).collect();
+    // the return values are outputs, the finished ones leave the turns
+    assert_eq!(out, ["a0", "b0", "c=0", "a=4", "b1", "b2", "b=7"]);
+    assert!(m.is_done());
+
+    let source = Gn::<()>::new_scoped(|mut s| {
+        for i in 0..4 {
+            s.yield_(i);
+        }
+        4
+    });
+    let mut consumers = Gn::broadcast(source, 3, 2);
+    let mut slow = consumers.pop().unwrap();
+    let mut fast = consumers.pop().unwrap();
+    // the fast one waits once the slow one has a full queue
+    assert_eq!(fast.resume(), Some(Poll::Ready(0)));
+    assert_eq!(fast.resume(), Some(Poll::Ready(1)));
+    assert_eq!(fast.resume(), Some(Poll::Pending));
+    assert_eq!(slow.resume(), Some(Poll::Ready(0)));
+    // the third one is still behind
+    assert_eq!(fast.resume(), Some(Poll::Pending));
+    // a dropped consumer doesn't hold back the others
+    drop(consumers);
+    assert_eq!(fast.resume(), Some(Poll::Ready(2)));
+    let rest: Vec<_> = slow.by_ref().collect();
+    assert_eq!(rest, [1, 2, 3, 4].map(Poll::Ready));
+    assert_eq!(fast.resume(), Some(Poll::Ready(3)));
+    assert_eq!(fast.resume(), Some(Poll::Ready(4)));
+    assert_eq!(fast.resume(), None);
+
+    // a panic of the source ends the other consumers
+    let src = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        panic!("source panic");
+    });
+    let mut consumers = Gn::broadcast(src, 2, 2);
+    let mut b = consumers.pop().unwrap();
+    let mut a = consumers.pop().unwrap();
+    assert_eq!(a.resume(), Some(Poll::Ready(1)));
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| a.resume()));
+    assert!(r.is_err());
+    assert_eq!(b.resume(), Some(Poll::Ready(1)));
+    assert_eq!(b.resume(), None);
+}
+
+#[test]
+fn test_from_iter() {
+    let g = Gn::from_iter(vec![1, 2, 3]);
+    assert_eq!(g.collect::<Vec<_>>(), [1, 2, 3]);
+
+    let data = [4, 5];
+    let mut g = Gn::from_iter(data.iter()).map_yield(|v| v * 2);
+    assert_eq!(g.next(), Some(8));
+    assert_eq!(g.next(), Some(10));
+    assert_eq!(g.next(), None);
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_send_iter() {
+    // a running sum transformer
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let mut sum = s.get_yield().unwrap_or(0);
+        while sum < 10 {
+            sum += s.yield_(sum).unwrap_or(0);
+        }
+        sum
+    });
+    let out: Vec<u32> = g.send_iter([1, 2, 3, 4, 5, 6]).collect();
+    assert_eq!(out, [1, 3, 6, 10]);
+    assert!(g.is_done());
+    assert_eq!(g.send_iter([7]).next(), None);
+
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let mut v = s.get_yield().unwrap_or(0);
+        loop {
+            v = s.yield_(v * 2).unwrap_or(0);
+        }
+    });
+    let out: Vec<u32> = g.send_iter(1..4).auto_prefetch().collect();
+    assert_eq!(out, [2, 4, 6]);
+}
+
+#[test]
+fn test_yield_batch() {
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let mut batch = Vec::with_capacity(4);
+        for i in 0..10 {
+            batch.push(i);
+            if batch.len() == 4 {
+                s.yield_batch(&mut batch);
+                assert!(batch.is_empty());
+                assert_eq!(batch.capacity(), 4);
+            }
+        }
+        // nothing is yielded for an empty batch
+        s.yield_batch(&mut Vec::new());
+        batch
+    });
+
+    let mut buf = vec![];
+    assert_eq!(g.resume_batch(&mut buf), 4);
+    assert_eq!(g.resume_batch(&mut buf), 4);
+    assert_eq!(g.resume_batch(&mut buf), 2);
+    assert_eq!(g.resume_batch(&mut buf), 0);
+    assert_eq!(buf, (0..10).collect::<Vec<_>>());
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_resume_ref() {
+    let mut g = Gn::<()>::new_scoped_opt(0x4000, |mut s| {
+        for i in 0..3 {
+            s.yield_([i; 1024]);
+        }
+        done!();
+    });
+
+    let buf = g.resume_ref().unwrap();
+    assert_eq!(buf[1023], 0);
+    buf[0] = 100;
+    assert_eq!(g.peek().map(|b| b[0]), Some(1));
+    assert_eq!(g.resume_ref().map(|b| b[0]), Some(1));
+    assert_eq!(g.resume_ref().map(|b| b[0]), Some(2));
+    // the value left in place is not returned again
+    assert_eq!(g.resume(), None);
+    assert!(g.is_done());
+    assert!(g.resume_ref().is_none());
+}
+
+#[test]
+fn test_resume_into() {
+    use std::mem::MaybeUninit;
+
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(String::from("a"));
+        s.yield_(String::from("b"));
+        String::from("c")
+    });
+
+    let mut out = MaybeUninit::uninit();
+    let mut v = vec![];
+    while g.resume_into(&mut out) {
+        v.push(unsafe { out.assume_init_read() });
+    }
+    assert_eq!(v, ["a", "b", "c"]);
+    assert!(g.is_done());
+
+    // the peeked value is moved into the slot
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        done!();
+    });
+    let mut out = MaybeUninit::uninit();
+    assert_eq!(g.peek(), Some(&1));
+    assert!(g.resume_into(&mut out));
+    assert_eq!(unsafe { out.assume_init() }, 1);
+    assert!(!g.resume_into(&mut out));
+}
+
+#[test]
+fn test_handle_layout() {
+    use std::mem::size_of;
+
+    // the handle is a single pointer into the generator stack
+    assert_eq!(size_of::<Generator<(), u64>>(), size_of::<usize>());
+    assert_eq!(size_of::<LocalGenerator<u32, String>>(), size_of::<usize>());
+    assert_eq!(size_of::<Option<Generator<(), u64>>>(), size_of::<usize>());
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn test_preserve_vector_state() {
+    use std::arch::asm;
+
+    if !is_x86_feature_detected!("avx") {
+        return;
+    }
+
+    // only touch the upper half of ymm15
+    fn set_upper(v: u64) {
+        unsafe {
+            asm!(
+                "vmovq xmm15, {0}",
+                "vinsertf128 ymm15, ymm15, xmm15, 1",
+                in(reg) v,
+                out("xmm15") _,
+            )
+        }
+    }
+
+    fn get_upper() -> u64 {
+        let v;
+        unsafe {
+            asm!(
+                "vextractf128 xmm15, ymm15, 1",
+                "vmovq {0}, xmm15",
+                out(reg) v,
+                out("xmm15") _,
+            )
+        }
+        v
+    }
+
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        set_upper(0x1234);
+        s.yield_(0);
+        get_upper()
+    });
+    assert!(g.set_preserve_vector_state(true));
+    set_upper(0x5678);
+    assert_eq!(g.resume(), Some(0));
+    assert_eq!(get_upper(), 0x5678);
+    set_upper(0x9abc);
+    assert_eq!(g.resume(), Some(0x1234));
+    assert_eq!(get_upper(), 0x9abc);
+}
+
+#[test]
+#[cfg(all(feature = "fp-control", target_arch = "x86_64"))]
+fn test_fp_control() {
+    use std::arch::asm;
+
+    fn mxcsr() -> u32 {
+        let mut v = 0u32;
+        unsafe { asm!("stmxcsr [{0}]", in(reg) &mut v) };
+        v
+    }
+
+    fn set_mxcsr(v: u32) {
+        unsafe { asm!("ldmxcsr [{0}]", in(reg) &v) };
+    }
+
+    // round toward zero
+    const RZ: u32 = 0b11 << 13;
+    let old = mxcsr();
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        set_mxcsr(mxcsr() | RZ);
+        s.yield_(mxcsr());
+        mxcsr()
+    });
+    let inner = g.resume().unwrap();
+    assert_eq!(inner & RZ, RZ);
+    // the rounding mode doesn't leak into the resumer
+    assert_eq!(mxcsr(), old);
+    // and the generator keeps its own
+    assert_eq!(g.resume(), Some(inner));
+    assert_eq!(mxcsr(), old);
+}
+
+#[test]
+fn test_stack_alignment() {
+    #[repr(align(16))]
+    struct A16(#[allow(dead_code)] u8);
+    #[repr(align(64))]
+    struct A64(#[allow(dead_code)] u8);
+
+    fn check() -> usize {
+        let a = A16(0);
+        let b = A64(0);
+        let a = std::hint::black_box(&a) as *const A16 as usize;
+        let b = std::hint::black_box(&b) as *const A64 as usize;
+        assert_eq!(a % Stack::alignment(), 0);
+        assert_eq!(b % 64, 0);
+        a
+    }
+
+    assert_eq!(Stack::alignment(), 16);
+    // different stack depth at the entry and after yields
+    for size in [0x800, 0x1000, 0x1001] {
+        let mut g = Gn::<()>::new_scoped_opt(size, |mut s| {
+            s.yield_(check());
+            let _pad = std::hint::black_box([0u8; 24]);
+            check()
+        });
+        assert!(g.resume().is_some());
+        assert!(g.resume().is_some());
+        assert!(g.is_done());
+    }
+}
+
+#[test]
+fn test_default_stack_size() {
+    let old = default_stack_size();
+    if std::env::var_os("GENERATOR_DEFAULT_STACK_SIZE").is_none() {
+        assert_eq!(old, DEFAULT_STACK_SIZE * std::mem::size_of::<usize>());
+    }
+
+    // only grow it, other tests may run at the same time
+    set_default_stack_size(0x20001);
+    let words = default_stack_size() / std::mem::size_of::<usize>();
+    assert!(default_stack_size() >= 0x20001);
+    assert_eq!(words % 2, 0);
+    let g = Gn::<()>::new_scoped(|_| 0);
+    assert!(g.stack_usage().0 >= words);
+
+    set_default_stack_size(old);
+    assert_eq!(default_stack_size(), old);
+}
+
+#[test]
+fn test_stack_size_check() {
+    // tiny sizes are rounded up to the page size
+    let mut g = Gn::<()>::try_new_scoped_opt(2, |mut s| {
+        s.yield_(1);
+        2
+    })
+    .unwrap();
+    assert!(g.stack_usage().0 * std::mem::size_of::<usize>() >= 0x1000);
+    assert_eq!(g.by_ref().collect::<Vec<_>>(), [1, 2]);
+
+    // the closure can't fit in the stack
+    let big = [1u8; 0x10000];
+    let err = Gn::<()>::try_new_scoped_opt(0x800, move |_| big[0])
+        .err()
+        .unwrap();
+    assert!(matches!(&err, StackError::IoError(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+    assert!(err.to_string().contains("too small"));
+
+    let err = Gn::<()>::try_new_scoped_opt_local(usize::MAX / 4, |_| 0)
+        .err()
+        .unwrap();
+    assert!(matches!(err, StackError::ExceedsMaximumSize(_)));
+
+    let result = std::panic::catch_unwind(|| Gn::<()>::new_scoped_opt(0x800, move |_| big[0]));
+    let msg = result.err().unwrap().downcast::<String>().unwrap();
+    assert!(msg.contains("too small"), "{msg}");
+}
+
+#[test]
+fn test_session() {
+    use generator::session::{End, Rx, Session, Tx};
+
+    // two sends and two receives in a row
+    type Sum = Tx<u32, Tx<u32, Rx<u32, Rx<bool, End>>>>;
+    let s = Session::<Sum>::new(|c| {
+        let (a, c) = c.recv();
+        let (b, c) = c.recv();
+        c.send(a + b).send(a < b)
+    });
+    let (sum, s) = s.send(1).send(2).recv();
+    let (less, s) = s.recv();
+    s.close();
+    assert_eq!((sum, less), (3, true));
+
+    // the generator starts by sending, dropped in the middle
+    let s = Session::<Rx<&str, Tx<(), End>>>::new(|c| {
+        let c = c.send("hello");
+        c.recv().1
+    });
+    let (hello, s) = s.recv();
+    assert_eq!(hello, "hello");
+    drop(s);
+}
+
+#[test]
+fn test_scope_co_yield() {
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let a = s.co_yield();
+        s.yield_(a.unwrap_or(0));
+        s.co_yield();
+        9
+    });
+    assert_eq!(g.resume_step(), Some(None));
+    assert_eq!(g.state(), State::Suspended);
+    g.set_para(5);
+    assert_eq!(g.resume_step(), Some(Some(5)));
+    assert_eq!(g.resume_step(), Some(None));
+    assert_eq!(g.resume_step(), Some(Some(9)));
+    assert_eq!(g.resume_step(), None);
+    assert_eq!(g.yield_count(), 3);
+
+    // the iterator skips the yields without a value
+    let g = Gn::new_scoped(|mut s| {
+        s.yield_(1u32);
+        s.co_yield();
+        s.yield_(2u32);
+        3u32
+    });
+    assert_eq!(g.fuse().collect::<Vec<_>>(), [1, 2, 3]);
+}
+
+#[test]
+fn test_is_started() {
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    assert!(!g.is_started());
+    assert_eq!(g.state(), State::Fresh);
+    g.resume();
+    assert!(g.is_started());
+    assert_eq!(g.state(), State::Suspended);
+    g.cancel();
+    assert!(g.is_started());
+
+    // a cancelled fresh generator never runs
+    let mut g = Gn::<()>::new_scoped(|_| 1);
+    g.cancel();
+    assert!(g.is_started() && g.is_done());
+    g.reset_with(|_| 3).unwrap();
+    assert!(!g.is_started());
+}
+
+#[test]
+fn test_stack_pool_warm() {
+    // a size class not used by the other tests
+    const SIZE: usize = 0x2c00;
+    pool::warm(2, SIZE).unwrap();
+    assert_eq!(pool::pooled(SIZE), 2);
+
+    let mut g = Gn::<()>::new_scoped_opt(SIZE, |mut s| {
+        s.yield_(1);
+        2
+    });
+    assert_eq!(pool::pooled(SIZE), 1);
+    assert_eq!(g.resume(), Some(1));
+    drop(g);
+    assert_eq!(pool::pooled(SIZE), 2);
+
+    // warming again only fills the class up
+    pool::warm(3, SIZE).unwrap();
+    assert_eq!(pool::pooled(SIZE), 3);
+    let gens: Vec<_> = (0..4)
+        .map(|_| Gn::<()>::new_scoped_opt(SIZE, |_| 0))
+        .collect();
+    assert_eq!(pool::pooled(SIZE), 0);
+    drop(gens);
+    // the class keeps up to 3 stacks
+    assert_eq!(pool::pooled(SIZE), 3);
+}
+
+#[test]
+fn test_stack_region() {
+    // the stacks of the other tests are carved from the region from now on
+    region::reserve(0x1000_0000).unwrap();
+    let range = region::reserved().unwrap();
+    assert!(region::reserve(0x1000).is_err());
+
+    let mut g = Gn::<()>::new_scoped_opt(0x1000, |mut s| {
+        s.yield_(1);
+        2
+    });
+    assert!(range.contains(&g.id()));
+    assert_eq!(g.resume(), Some(1));
+    assert_eq!(g.resume(), Some(2));
+    drop(g);
+
+    // the full usage tracking works the same on a carved stack
+    let g = Gn::<()>::new_scoped_opt(0x1001, |_| 0);
+    assert!(range.contains(&g.id()));
+    let (total, used) = g.stack_usage();
+    assert!(used < total / 2);
+}
+
+#[test]
+fn test_builder_mlock() {
+    let g = Gn::<u32>::builder()
+        .stack_size(0x2000)
+        .mlock(true)
+        .prefault(true)
+        .scoped(|mut s| {
+            let a = s.yield_(1).unwrap_or(0);
+            a + 1
+        });
+    // an unprivileged process may be over its RLIMIT_MEMLOCK
+    let mut g = match g {
+        Ok(g) => g,
+        Err(StackError::IoError(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => {
+            assert!(e.to_string().contains("RLIMIT_MEMLOCK"));
+            return;
+        }
+        Err(e) => panic!("{e}"),
+    };
+    assert_eq!(g.resume(), Some(1));
+    assert_eq!(g.send(2), 3);
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_scope_alloc() {
+    let mut g = Gn::<()>::builder()
+        .stack_size(0x2000)
+        .scratch(256)
+        .scoped(|mut s| {
+            let mut buf = s.alloc([0u8; 128]);
+            let n = s.alloc(7u64);
+            buf[0] = 1;
+            s.yield_(buf[0] as u64 + *n);
+            // the space is used up
+            assert_eq!(s.try_alloc([0u8; 128]).err(), Some([0u8; 128]));
+            let v = s.alloc(String::from("scratch"));
+            v.len() as u64
+        })
+        .unwrap();
+    assert_eq!(g.next(), Some(8));
+    assert_eq!(g.next(), Some(7));
+
+    // the space is reused by the next run
+    g.scoped_init(|s| s.alloc([1u64; 32]).iter().sum());
+    assert_eq!(g.next(), Some(32));
+
+    let mut g = Gn::<()>::new_scoped(|s| s.try_alloc(1u8).is_err() as u8);
+    assert_eq!(g.next(), Some(1));
+}
+
+#[test]
+fn test_guard_size() {
+    let page = default_guard_size();
+    assert!(page.is_power_of_two());
+
+    let mut g = Gn::<()>::builder()
+        .stack_size(0x1000)
+        .guard_size(page * 3 + 1)
+        .scoped(|mut s| {
+            s.yield_(1);
+            2
+        })
+        .unwrap();
+    // the guard is not part of the usable stack
+    let (total, used) = g.stack_usage();
+    assert_eq!(total * std::mem::size_of::<usize>(), 0x8000);
+    assert!(used < total);
+    assert_eq!(g.resume(), Some(1));
+    assert_eq!(g.resume(), Some(2));
+}
+
+#[test]
+fn test_lazy_commit() {
+    #[inline(never)]
+    fn burn(depth: usize) -> usize {
+        let buf = std::hint::black_box([depth as u8; 0x400]);
+        match depth {
+            0 => usize::from(buf[0]),
+            _ => burn(depth - 1) + usize::from(buf[0x3ff]),
+        }
+    }
+
+    // the odd size tracks the full usage on the other OSes
+    let mut g = Gn::<usize>::builder()
+        .stack_size(0x10001)
+        .lazy_commit(true)
+        .scoped(|mut s| {
+            let mut depth = s.get_yield().unwrap_or(0);
+            loop {
+                depth = s.yield_(burn(depth)).unwrap_or(0);
+            }
+        })
+        .unwrap();
+    g.send(1);
+    let (total, small) = g.stack_usage();
+    // the stack grows with the deeper frames
+    g.send(0x40);
+    let (_, used) = g.stack_usage();
+    assert!(small < used && used < total);
+    assert!(used * std::mem::size_of::<usize>() > 0x40 * 0x400);
+}
+
+#[test]
+fn test_stack_guarantee() {
+    // the guaranteed bytes must fit in the stack
+    let err = Gn::<()>::builder()
+        .stack_size(0x800)
+        .stack_guarantee(0x10000)
+        .scoped(|_| 0)
+        .unwrap_err();
+    assert!(matches!(err, StackError::IoError(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+
+    let mut g = Gn::<()>::builder()
+        .stack_size(0x10000)
+        .stack_guarantee(0x4000)
+        .scoped(|mut s| {
+            s.yield_(1);
+            2
+        })
+        .unwrap();
+    assert_eq!(g.resume(), Some(1));
+    assert_eq!(g.resume(), Some(2));
+}
+
+#[test]
+#[cfg(checks)]
+fn test_resume_on_bound_thread() {
+    let gen = || {
+        Gn::<()>::new_scoped(|mut s| {
+            s.yield_(1);
+            s.yield_(2);
+            3
+        })
+    };
+
+    let mut g = gen();
+    g.bind_thread();
+    assert_eq!(g.resume(), Some(1));
+    // resumed on another thread
+    let ret = std::thread::spawn(move || g.resume()).join();
+    let msg = ret.unwrap_err().downcast::<String>().unwrap();
+    assert!(msg.contains("is bound to thread"), "{msg}");
+    assert!(msg.contains("allow_any_thread"), "{msg}");
+
+    let mut g = gen();
+    g.bind_thread();
+    assert_eq!(g.resume(), Some(1));
+    g.allow_any_thread();
+    let g = std::thread::spawn(move || {
+        assert_eq!(g.resume(), Some(2));
+        g
+    })
+    .join()
+    .unwrap();
+    assert_eq!(g.collect::<Vec<_>>(), [3]);
 }
@@ -0,0 +1,104 @@
+//! model check the cross thread paths with loom
+//!
+//! RUSTFLAGS="--cfg loom" cargo test --release --features spawn --test loom
+#![cfg(loom)]
+
+use generator::{current_id, Executor, Gn};
+use loom::sync::{Arc, Mutex};
+use loom::thread;
+use std::task::{Poll, Waker};
+
+#[test]
+fn resume_on_other_thread() {
+    loom::model(|| {
+        let mut g = Gn::<()>::new_scoped(|mut s| {
+            s.yield_(1);
+            2
+        });
+        assert_eq!(g.resume(), Some(1));
+        let h = thread::spawn(move || {
+            let v = g.resume();
+            assert!(g.is_done());
+            v
+        });
+        assert_eq!(h.join().unwrap(), Some(2));
+        assert_eq!(current_id(), None);
+    });
+}
+
+#[test]
+fn switch_threads_inside_generators() {
+    loom::model(|| {
+        let count = Arc::new(Mutex::new(0));
+        let run = |count: Arc<Mutex<usize>>| {
+            move || {
+                let mut g = Gn::<()>::new_scoped(move |mut s| {
+                    // the other thread may run its generator here
+                    *count.lock().unwrap() += 1;
+                    s.yield_(current_id());
+                    None
+                });
+                assert_eq!(g.resume(), Some(Some(g.id())));
+                assert_eq!(current_id(), None);
+                // loom doesn't track the drops while a cancel is unwinding
+                assert_eq!(g.resume(), Some(None));
+            }
+        };
+        let h = thread::spawn(run(count.clone()));
+        run(count.clone())();
+        h.join().unwrap();
+        assert_eq!(*count.lock().unwrap(), 2);
+    });
+}
+
+#[test]
+fn reuse_stack_on_other_thread() {
+    loom::model(|| {
+        let mut g = Gn::<()>::new_scoped(|_| 1);
+        assert_eq!(g.resume(), Some(1));
+        let h = thread::spawn(move || {
+            g.reset_with(|_| 2).unwrap();
+            assert_eq!(g.resume(), Some(2));
+            g
+        });
+        let mut g = h.join().unwrap();
+        g.reset_with(|_| 3).unwrap();
+        assert_eq!(g.resume(), Some(3));
+    });
+}
+
+#[test]
+fn wake_executor_from_other_thread() {
+    loom::model(|| {
+        let slot = Arc::new(Mutex::new(None::<Waker>));
+        let waiter = slot.clone();
+        let mut exec = Executor::new();
+        exec.spawn(Gn::new_scoped(move |mut s| {
+            s.yield_pending(|w| *waiter.lock().unwrap() = Some(w));
+            Poll::Ready(())
+        }));
+        let h = thread::spawn(move || loop {
+            if let Some(w) = slot.lock().unwrap().take() {
+                return w.wake();
+            }
+            thread::yield_now();
+        });
+        exec.run();
+        h.join().unwrap();
+    });
+}
+
+#[test]
+#[cfg(feature = "spawn")]
+fn spawn_on_worker() {
+    loom::model(|| {
+        let fut = Gn::<()>::new_scoped(|mut s| {
+            s.yield_(1);
+            2
+        })
+        .spawn_detached(|job| {
+            thread::spawn(job);
+        });
+        assert_eq!(loom::future::block_on(fut), Some(2));
+    });
+}
@@ -0,0 +1,48 @@
+//! the max nesting depth is process wide, it's tested in its own process
+//! to not race with the other tests
+
+use generator::{max_nesting_depth, nesting_depth, set_max_nesting_depth, Gn};
+use std::panic::{self, AssertUnwindSafe};
+
+#[test]
+fn test_max_nesting_depth() {
+    assert_eq!(max_nesting_depth(), 0);
+    set_max_nesting_depth(2);
+
+    let mut outer = Gn::<()>::new_scoped(|mut s| {
+        let mut middle = Gn::<()>::new_scoped(|mut s| {
+            let mut inner = Gn::<()>::new_scoped(|mut s| {
+                s.yield_(nesting_depth());
+                0
+            });
+            let e = panic::catch_unwind(AssertUnwindSafe(|| inner.resume())).unwrap_err();
+            s.yield_(*e.downcast::<String>().unwrap());
+            // the refused generator is left as it is
+            assert!(!inner.is_started());
+            String::new()
+        });
+        let msg = middle.resume().unwrap();
+        s.yield_(msg);
+        // the suspended generator can still be cancelled
+        drop(middle);
+        String::new()
+    });
+    let msg = outer.resume().unwrap();
+    assert!(msg.contains("would nest 3 generators, over the max nesting depth 2"));
+    assert_eq!(outer.resume(), Some(String::new()));
+
+    set_max_nesting_depth(0);
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        let mut g = Gn::<()>::new_scoped(|mut s| {
+            let mut g = Gn::<()>::new_scoped(|mut s| {
+                s.yield_(nesting_depth());
+                0
+            });
+            s.yield_(g.resume().unwrap());
+            0
+        });
+        s.yield_(g.resume().unwrap());
+        0
+    });
+    assert_eq!(g.resume(), Some(3));
+}
@@ -0,0 +1,73 @@
+use generator::*;
+
+#[test]
+fn test_coroutine_trait() {
+    use std::ops::{Coroutine, CoroutineState};
+    use std::pin::Pin;
+
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let a = s.get_yield().unwrap();
+        let b = s.yield_(a + 1).unwrap();
+        a + b
+    });
+    let mut g = Pin::new(&mut g);
+    assert_eq!(g.as_mut().resume(1), CoroutineState::Yielded(2));
+    assert_eq!(g.as_mut().resume(5), CoroutineState::Complete(Some(6)));
+
+    // the yields without a value are skipped
+    let mut g = Gn::<u32>::new_scoped(|mut s| {
+        let a = s.get_yield().unwrap();
+        assert_eq!(s.co_yield(), None);
+        let b = s.yield_(a + 1).unwrap();
+        s.co_yield();
+        a + b
+    });
+    let mut g = Pin::new(&mut g);
+    assert_eq!(g.as_mut().resume(1), CoroutineState::Yielded(2));
+    assert_eq!(g.as_mut().resume(5), CoroutineState::Complete(Some(6)));
+
+    let c = #[coroutine]
+    |a: u32| {
+        let b = yield a * 2;
+        a + b
+    };
+    let mut g = Gn::from_coroutine(c);
+    assert_eq!(g.send(3), 6);
+    assert_eq!(g.send(4), 7);
+    assert!(g.is_done());
+}
+
+#[test]
+fn test_from_static_coroutine() {
+    use std::rc::Rc;
+
+    // a self-referential coroutine that holds a borrow across yields
+    let c = #[coroutine]
+    static || {
+        let data = [1, 2, 3];
+        let r = &data;
+        for v in r {
+            yield *v;
+        }
+        0
+    };
+    let g = Gn::<()>::from_coroutine(c);
+    assert_eq!(g.collect::<Vec<_>>(), [1, 2, 3, 0]);
+
+    // mix the compiler coroutine and the stackful generator
+    let rc = Rc::new(5);
+    let c = #[coroutine]
+    move || {
+        yield *rc;
+        *rc + 1
+    };
+    let gens = vec![
+        Gn::<()>::from_coroutine_local(c),
+        Gn::new_scoped_local(|mut s| {
+            s.yield_with(7);
+            8
+        }),
+    ];
+    let all: Vec<i32> = gens.into_iter().flatten().collect();
+    assert_eq!(all, [5, 6, 7, 8]);
+}
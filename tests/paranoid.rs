@@ -0,0 +1,35 @@
+//! the checks of the debug builds are kept in release by `paranoid`, run it
+//! with `cargo test --release --features paranoid --test paranoid`
+#![cfg(feature = "paranoid")]
+
+use generator::{Generator, Gn};
+
+#[test]
+fn test_thread_check() {
+    let mut g = Gn::<()>::new_scoped(|mut s| {
+        s.yield_(1);
+        2
+    });
+    g.bind_thread();
+    assert_eq!(g.resume(), Some(1));
+    let ret = std::thread::spawn(move || g.resume()).join();
+    let msg = ret.unwrap_err().downcast::<String>().unwrap();
+    assert!(msg.contains("is bound to thread"), "{msg}");
+}
+
+#[test]
+#[should_panic(expected = "type mismatch")]
+fn test_raw_tag_mismatch() {
+    let g = Gn::<u32>::new_scoped(|_| 0u32);
+    let raw = g.into_raw();
+    let _g = unsafe { Generator::<u32, String>::from_raw(raw) };
+}
+
+#[test]
+#[should_panic(expected = "already reclaimed")]
+fn test_raw_tag_reclaimed() {
+    let g = Gn::<u32>::new_scoped(|_| 0u32);
+    let raw = g.into_raw();
+    let _g = unsafe { Generator::<u32, u32>::from_raw(raw) };
+    let _g2 = unsafe { Generator::<u32, u32>::from_raw(raw) };
+}
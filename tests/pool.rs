@@ -0,0 +1,62 @@
+//! the stack pool is process wide, its knobs are tested in their own process
+//! to not race with the other tests
+
+use generator::pool::{self, TrimPolicy};
+use generator::Gn;
+use std::time::Duration;
+
+#[test]
+fn test_pool_tuning() {
+    const SIZE: usize = 0x3000;
+    let bytes = SIZE * std::mem::size_of::<usize>();
+
+    // the max makes the class pool without warming it
+    pool::set_max(SIZE, 2);
+    let gens: Vec<_> = (0..3)
+        .map(|_| Gn::<()>::new_scoped_opt(SIZE, |_| 0))
+        .collect();
+    drop(gens);
+    assert_eq!(pool::pooled(SIZE), 2);
+
+    let stats = pool::class_stats(SIZE);
+    assert_eq!((stats.hits, stats.misses), (0, 3));
+    assert_eq!(stats.pooled, 2);
+    assert!(stats.pooled_bytes >= 2 * bytes);
+    assert_eq!(stats.peak_pooled, 2);
+
+    let g = Gn::<()>::new_scoped_opt(SIZE, |_| 0);
+    let stats = pool::class_stats(SIZE);
+    assert_eq!((stats.hits, stats.misses), (1, 3));
+    assert_eq!(stats.hit_rate(), 0.25);
+    drop(g);
+
+    // a lower max frees the extra stacks
+    pool::set_max(SIZE, 1);
+    assert_eq!(pool::pooled(SIZE), 1);
+    assert_eq!(pool::class_stats(SIZE).trimmed, 1);
+
+    pool::reset_stats();
+    let stats = pool::stats();
+    assert_eq!((stats.hits, stats.misses, stats.trimmed), (0, 0, 0));
+    assert_eq!(stats.peak_pooled, stats.pooled);
+
+    // the idle stacks are freed by the policy
+    pool::set_trim_policy(TrimPolicy::Idle(Duration::from_millis(10)));
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(pool::trim(), 1);
+    assert_eq!(pool::pooled(SIZE), 0);
+    pool::set_trim_policy(TrimPolicy::Keep);
+    assert_eq!(pool::trim(), 0);
+
+    // the high-water marks are kept
+    pool::clear();
+    let stats = pool::stats();
+    assert_eq!((stats.pooled, stats.pooled_bytes), (0, 0));
+    assert_eq!(stats.peak_pooled, 1);
+
+    // the stack of a generator taken apart by `into_inner` is pooled
+    pool::set_max(SIZE, 1);
+    let g = Gn::<()>::new_scoped_opt(SIZE, |_| 0);
+    assert!(g.into_inner().is_ok());
+    assert_eq!(pool::pooled(SIZE), 1);
+}
@@ -0,0 +1,65 @@
+//! the runtime hooks are registered once per process, they are tested in
+//! their own process to not see the other tests
+
+use generator::{set_runtime_hooks, Gn, RuntimeHooks};
+use std::sync::Mutex;
+
+struct Log(Mutex<Vec<(&'static str, usize)>>);
+
+impl RuntimeHooks for Log {
+    fn on_spawn(&self, id: usize) {
+        self.0.lock().unwrap().push(("spawn", id));
+    }
+
+    fn on_yield(&self, id: usize) {
+        self.0.lock().unwrap().push(("yield", id));
+    }
+
+    fn on_done(&self, id: usize) {
+        self.0.lock().unwrap().push(("done", id));
+    }
+
+    fn on_stack_acquire(&self, id: usize, size: usize) {
+        assert!(size >= 0x1000);
+        self.0.lock().unwrap().push(("acquire", id));
+    }
+
+    fn on_stack_release(&self, id: usize, _size: usize) {
+        self.0.lock().unwrap().push(("release", id));
+    }
+}
+
+static LOG: Log = Log(Mutex::new(Vec::new()));
+
+struct Nop;
+
+impl RuntimeHooks for Nop {}
+
+#[test]
+fn test_runtime_hooks() {
+    assert!(set_runtime_hooks(&LOG).is_ok());
+    // only the first registration takes effect
+    assert!(set_runtime_hooks(&Nop).is_err());
+
+    let mut g = Gn::<()>::new_scoped_opt(0x1000, |mut s| {
+        s.yield_(1);
+        2
+    });
+    assert_eq!(g.resume(), Some(1));
+    assert_eq!(g.resume(), Some(2));
+    drop(g);
+
+    let log = LOG.0.lock().unwrap();
+    let id = log[0].1;
+    let events: Vec<_> = log.iter().map(|&(e, i)| (e, i == id)).collect();
+    assert_eq!(
+        events,
+        [
+            ("acquire", true),
+            ("spawn", true),
+            ("yield", true),
+            ("done", true),
+            ("release", true)
+        ]
+    );
+}
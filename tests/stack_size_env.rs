@@ -0,0 +1,57 @@
+//! the env var is read once per process, each case runs this binary again as
+//! a child process with the env var set
+
+use generator::{default_stack_size, set_default_stack_size, DEFAULT_STACK_SIZE};
+use std::process::Command;
+
+const CHILD: &str = "STACK_SIZE_ENV_CHILD";
+
+// check the default stack size in the child, `set` is applied first
+#[test]
+fn child() {
+    let Ok(case) = std::env::var(CHILD) else {
+        return;
+    };
+    let (set, expect) = case.split_once(',').unwrap();
+    if let Ok(bytes) = set.parse() {
+        set_default_stack_size(bytes);
+    }
+    assert_eq!(default_stack_size(), expect.parse::<usize>().unwrap());
+}
+
+fn run(env: Option<&str>, set: Option<usize>, expect: usize) {
+    let mut cmd = Command::new(std::env::current_exe().unwrap());
+    cmd.args(["--exact", "child"]);
+    cmd.env(
+        CHILD,
+        format!("{},{expect}", set.map_or(String::new(), |s| s.to_string())),
+    );
+    match env {
+        Some(v) => cmd.env("GENERATOR_DEFAULT_STACK_SIZE", v),
+        None => cmd.env_remove("GENERATOR_DEFAULT_STACK_SIZE"),
+    };
+    let out = cmd.output().unwrap();
+    assert!(
+        out.status.success(),
+        "env {env:?}, set {set:?}: {}",
+        String::from_utf8_lossy(&out.stdout)
+    );
+}
+
+#[test]
+fn test_env_stack_size() {
+    let word = std::mem::size_of::<usize>();
+    run(None, None, DEFAULT_STACK_SIZE * word);
+    run(Some("65536"), None, 65536);
+    // rounded up to an even number of words
+    run(
+        Some(" 1001 "),
+        None,
+        1001usize.div_ceil(word * 2) * word * 2,
+    );
+    // the invalid ones fall back to the default
+    run(Some("64k"), None, DEFAULT_STACK_SIZE * word);
+    run(Some(""), None, DEFAULT_STACK_SIZE * word);
+    // `set_default_stack_size` takes precedence
+    run(Some("65536"), Some(8192), 8192);
+}